@@ -0,0 +1,273 @@
+//! Replays a flight recording through the FC's ingestion pipeline and writes
+//! out the resulting VehicleState timeline.
+//!
+//! ```text
+//! replay [--config <path>] [--format csv|json] [--speed <factor>] [--interval <ms>] [--output <path>] <recording or directory>...
+//! ```
+//!
+//! Board telemetry is fed through `Devices::update_state` with the mappings
+//! that Servo had sent at that point in the recording, and valve commands
+//! from sequences update the commanded valve states. Nothing is ever sent to
+//! a board or to Servo. A speed of 1 replays in real time, while the default
+//! of 0 replays as fast as possible.
+//!
+//! The calibrations, valve limits and other settings that shape the state are
+//! read from the same FC config file given with `--config`, and are otherwise
+//! the defaults.
+
+use std::{borrow::Cow, fs::File, io::{self, BufWriter, Write}, net::UdpSocket, path::PathBuf, process::ExitCode, thread, time::{Duration, Instant}};
+use common::comm::{flight::{DataMessage, SequenceDomainCommand}, FlightControlMessage, VehicleState};
+use flight_computer::{config::Config, device::Devices, mappings::Mappings, recorder::{self, Reader, Record}};
+use serde::Serialize;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Format {
+  Csv,
+  Json,
+}
+
+struct Options {
+  config: Config,
+  format: Format,
+  speed: f64,
+  interval: Duration,
+  output: Option<PathBuf>,
+  recordings: Vec<PathBuf>,
+}
+
+/// A row of the JSON output, which is written as one object per line.
+#[derive(Serialize)]
+struct Snapshot<'a> {
+  timestamp: u64,
+  state: &'a VehicleState,
+}
+
+fn main() -> ExitCode {
+  let options = match parse_args(std::env::args().skip(1)) {
+    Ok(o) => o,
+    Err(e) => {
+      eprintln!("{e}");
+      eprintln!("usage: replay [--config <path>] [--format csv|json] [--speed <factor>] [--interval <ms>] [--output <path>] <recording or directory>...");
+      return ExitCode::FAILURE;
+    }
+  };
+
+  let output: Box<dyn Write> = match &options.output {
+    Some(path) => match File::create(path) {
+      Ok(f) => Box::new(f),
+      Err(e) => {
+        eprintln!("Couldn't create '{}': {e}", path.display());
+        return ExitCode::FAILURE;
+      }
+    },
+    None => Box::new(io::stdout().lock()),
+  };
+
+  match replay(&options, &mut BufWriter::new(output)) {
+    Ok(()) => ExitCode::SUCCESS,
+    Err(e) => {
+      eprintln!("Replay failed: {e}");
+      ExitCode::FAILURE
+    }
+  }
+}
+
+fn replay(options: &Options, output: &mut impl Write) -> io::Result<()> {
+  // only used to satisfy update_state, as identities are registered directly
+  // rather than by handshaking with boards that may still be on the network
+  let socket = UdpSocket::bind(("127.0.0.1", 0))?;
+
+  let mut devices = Devices::new(&options.config);
+  let mut mappings = Mappings::default();
+  let mut first: Option<(u64, Instant)> = None;
+  let mut last_written: Option<u64> = None;
+
+  if options.format == Format::Csv {
+    writeln!(output, "timestamp,kind,id,value,unit")?;
+  }
+
+  for path in expand(&options.recordings)? {
+    let reader = match Reader::open(&path) {
+      Ok(r) => r,
+      Err(e) => {
+        eprintln!("Skipping '{}': {e}", path.display());
+        continue;
+      }
+    };
+
+    for entry in reader {
+      let entry = match entry {
+        Ok(e) => e,
+        Err(e) => {
+          eprintln!("Stopped reading '{}': {e}", path.display());
+          break;
+        }
+      };
+
+      // pace the replay relative to the first entry
+      let (first_timestamp, started) = *first.get_or_insert((entry.timestamp, Instant::now()));
+      if options.speed > 0.0 {
+        let offset = Duration::from_micros(entry.timestamp.saturating_sub(first_timestamp));
+        let target = started + offset.div_f64(options.speed);
+        let now = Instant::now();
+
+        if target > now {
+          thread::sleep(target - now);
+        }
+      }
+
+      // board timeouts and refresh rates follow the recording's timeline
+      // rather than how quickly it's being replayed
+      let now = started + Duration::from_micros(entry.timestamp.saturating_sub(first_timestamp));
+
+      match entry.record {
+        Record::Telemetry(address, message) => match message.into_owned() {
+          DataMessage::Identity(id) => {
+            devices.register_device(&id, address, now);
+          },
+          message => {
+            // a recording may begin after a board's handshake, such as after
+            // the recorder rotated files, so boards are registered as needed
+            if let DataMessage::Sam(id, _) | DataMessage::Ahrs(id, _) | DataMessage::Bms(id, _) = &message {
+              if !devices.iter().any(|d| d.get_board_id() == id) {
+                devices.register_device(id, address, now);
+              }
+            }
+
+            devices.update_state(vec![(address, message)], &mappings, &socket, now);
+          },
+        },
+        Record::Servo(message) => {
+          if let FlightControlMessage::Mappings(m) = message.into_owned() {
+            mappings = Mappings::new(m);
+          }
+        },
+        Record::SequenceCommand(command) => apply_command(&mut devices, &mappings, command.into_owned()),
+        Record::AttributedSequenceCommand(attributed) => {
+          let attributed = attributed.into_owned();
+
+          // dropped commands never reached a board
+          if attributed.carried_out {
+            apply_command(&mut devices, &mappings, attributed.command);
+          }
+        },
+        // what was sent to the SAMs follows from the commands above
+        Record::State(_)
+        | Record::Status(_)
+        | Record::SequenceOutput(_)
+        | Record::SamMessage(_)
+        | Record::UnmappedSamCommand(_) => continue,
+      };
+
+      let due = last_written.is_none_or(|last| {
+        Duration::from_micros(entry.timestamp.saturating_sub(last)) >= options.interval
+      });
+
+      if due {
+        write_state(output, options.format, entry.timestamp, devices.get_state())?;
+        last_written = Some(entry.timestamp);
+      }
+    }
+  }
+
+  output.flush()
+}
+
+/// Updates the commanded state of a valve that a sequence actuated.
+fn apply_command(devices: &mut Devices, mappings: &Mappings, command: SequenceDomainCommand) {
+  if let SequenceDomainCommand::ActuateValve { valve, state } = command {
+    if mappings.get(&valve).is_some() {
+      devices.set_commanded_state(valve, state);
+    }
+  }
+}
+
+fn write_state(output: &mut impl Write, format: Format, timestamp: u64, state: &VehicleState) -> io::Result<()> {
+  match format {
+    Format::Json => {
+      serde_json::to_writer(&mut *output, &Snapshot { timestamp, state })?;
+      writeln!(output)
+    },
+    Format::Csv => {
+      let mut sensors: Vec<_> = state.sensor_readings.iter().collect();
+      sensors.sort_by(|a, b| a.0.cmp(b.0));
+
+      for (text_id, measurement) in sensors {
+        writeln!(output, "{timestamp},sensor,{text_id},{},{:?}", measurement.value, measurement.unit)?;
+      }
+
+      let mut valves: Vec<_> = state.valve_states.iter().collect();
+      valves.sort_by(|a, b| a.0.cmp(b.0));
+
+      for (text_id, valve) in valves {
+        writeln!(output, "{timestamp},valve_commanded,{text_id},{:?},", valve.commanded)?;
+        writeln!(output, "{timestamp},valve_actual,{text_id},{:?},", valve.actual)?;
+      }
+
+      Ok(())
+    },
+  }
+}
+
+/// Replaces every directory with the recordings inside of it.
+fn expand(paths: &[PathBuf]) -> io::Result<Vec<PathBuf>> {
+  let mut recordings = Vec::new();
+
+  for path in paths {
+    if path.is_dir() {
+      recordings.extend(recorder::list(path)?);
+    } else {
+      recordings.push(path.clone());
+    }
+  }
+
+  Ok(recordings)
+}
+
+fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Options, Cow<'static, str>> {
+  let mut options = Options {
+    config: Config::default(),
+    format: Format::Csv,
+    speed: 0.0,
+    interval: Duration::ZERO,
+    output: None,
+    recordings: Vec::new(),
+  };
+
+  let mut args = args.into_iter();
+  while let Some(arg) = args.next() {
+    if !arg.starts_with("--") {
+      options.recordings.push(PathBuf::from(arg));
+      continue;
+    }
+
+    let value = args.next().ok_or_else(|| format!("The flag '{arg}' requires a value."))?;
+    let invalid = || format!("'{value}' is not a valid value for '{arg}'.");
+
+    match arg.as_str() {
+      // loaded the same way as by the FC, so that it's validated just as well
+      "--config" => options.config = Config::parse([arg, value]).map_err(|e| e.to_string())?,
+      "--format" => options.format = match value.as_str() {
+        "csv" => Format::Csv,
+        "json" => Format::Json,
+        _ => return Err(invalid().into()),
+      },
+      "--speed" => {
+        options.speed = value.parse().map_err(|_| invalid())?;
+
+        if !(options.speed >= 0.0 && options.speed.is_finite()) {
+          return Err(invalid().into());
+        }
+      },
+      "--interval" => options.interval = Duration::from_millis(value.parse().map_err(|_| invalid())?),
+      "--output" => options.output = Some(PathBuf::from(value)),
+      _ => return Err(format!("'{arg}' is not a recognized flag.").into()),
+    };
+  }
+
+  if options.recordings.is_empty() {
+    return Err("At least one recording must be given.".into());
+  }
+
+  Ok(options)
+}
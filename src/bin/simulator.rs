@@ -0,0 +1,65 @@
+//! Runs simulated boards against a local flight computer.
+//!
+//! ```text
+//! simulator [--config <path>]
+//! ```
+//!
+//! Without a config file, a SAM with one current loop PT and one valve, an
+//! AHRS and a BMS are simulated on 127.0.0.2 through 127.0.0.4, talking to an
+//! FC at 127.0.0.1:4573.
+
+use std::{fs, process::ExitCode, thread, time::Duration};
+use flight_computer::{clock::SystemClock, simulator::{SimulatedBoard, SimulatorConfig}};
+
+/// How long the simulator sleeps in between steps of every board.
+const STEP_PERIOD: Duration = Duration::from_millis(1);
+
+fn main() -> ExitCode {
+  let args: Vec<String> = std::env::args().skip(1).collect();
+
+  let config = match args.as_slice() {
+    [] => SimulatorConfig::default(),
+    [flag, path] if flag == "--config" => {
+      let parsed = fs::read_to_string(path)
+        .map_err(|e| e.to_string())
+        .and_then(|contents| toml::from_str(&contents).map_err(|e| e.to_string()));
+
+      match parsed {
+        Ok(c) => c,
+        Err(e) => {
+          eprintln!("Couldn't load the simulator config at '{path}': {e}");
+          return ExitCode::FAILURE;
+        }
+      }
+    },
+    _ => {
+      eprintln!("usage: simulator [--config <path>]");
+      return ExitCode::FAILURE;
+    }
+  };
+
+  let mut boards = Vec::new();
+  for board in config.boards {
+    let id = board.id.clone();
+
+    match SimulatedBoard::new(board, config.fc_address, config.command_port, SystemClock) {
+      Ok(b) => boards.push(b),
+      Err(e) => {
+        eprintln!("Couldn't start simulated board '{id}': {e}");
+        return ExitCode::FAILURE;
+      }
+    };
+  }
+
+  println!("Simulating {} boards against the FC at {}.", boards.len(), config.fc_address);
+
+  loop {
+    for board in &mut boards {
+      if let Err(e) = board.step() {
+        eprintln!("[{}] {e}", board.id());
+      }
+    }
+
+    thread::sleep(STEP_PERIOD);
+  }
+}
@@ -0,0 +1,381 @@
+//! Converts the raw values that SAMs report into calibrated measurements.
+//!
+//! A calibration is configured per text ID, under `[calibrations.<text_id>]`,
+//! and takes the place of the built-in PT, load cell and temperature formulas
+//! for that sensor. Sensors without one keep using the built-in formulas.
+//! A value that can't be calibrated, such as one beyond a thermocouple's
+//! range, is skipped and the sensor keeps its last reading. The
+//! `calibrated_offset` of a PT or load cell's mapping is subtracted from its
+//! calibrated readings, just as from those of the built-in formulas.
+//!
+//! ```toml
+//! [calibrations.FU_TANK_PT]
+//! model = "current_loop"
+//! min = 0.0
+//! max = 1000.0
+//! unit = "Psi"
+//!
+//! [calibrations.ENGINE_TC]
+//! model = "thermocouple"
+//! type = "K"
+//! cold_junction = "BOARD_RTD"
+//! ```
+
+use std::collections::HashMap;
+use common::comm::{sam::Unit, Measurement};
+use serde::Deserialize;
+
+/// The calibrations of every sensor that has one, keyed by text ID.
+pub type Calibrations = HashMap<String, Calibration>;
+
+/// 0 °C in Kelvin.
+const ZERO_CELSIUS: f64 = 273.15;
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "model", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Calibration {
+  /// `c0 + c1 v + c2 v^2 + ...` of the raw value `v`, given lowest order
+  /// first.
+  Polynomial {
+    coefficients: Vec<f64>,
+    unit: Unit,
+  },
+
+  /// Linear interpolation between `[raw, calibrated]` points, which must be
+  /// sorted by their raw value. Beyond either end, the outermost two points
+  /// are extrapolated from.
+  Table {
+    points: Vec<(f64, f64)>,
+    unit: Unit,
+  },
+
+  /// A 4-20 mA transmitter, read as the voltage across a shunt resistor,
+  /// where 4 mA corresponds to `min` and 20 mA to `max`.
+  CurrentLoop {
+    min: f64,
+    max: f64,
+    unit: Unit,
+    #[serde(default = "default_shunt_ohms")]
+    shunt_ohms: f64,
+  },
+
+  /// A thermocouple whose EMF is reported in volts, converted to Kelvin with
+  /// the NIST ITS-90 polynomials after compensating for the temperature of
+  /// the cold junction.
+  Thermocouple {
+    #[serde(rename = "type")]
+    kind: ThermocoupleType,
+    cold_junction: ColdJunction,
+  },
+}
+
+/// Where the temperature of a thermocouple's cold junction comes from.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(untagged)]
+pub enum ColdJunction {
+  /// A fixed temperature in Kelvin.
+  Fixed(f64),
+
+  /// The text ID of a sensor, such as an RTD on the board, which reads the
+  /// temperature in Kelvin.
+  Sensor(String),
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+pub enum ThermocoupleType {
+  K,
+  T,
+}
+
+/// The 200 Ω shunt that turns 4-20 mA into the 0.8-4 V range assumed by the
+/// built-in PT formula.
+fn default_shunt_ohms() -> f64 {
+  200.0
+}
+
+impl Calibration {
+  /// Calibrates a raw value, using the latest sensor readings for anything
+  /// that depends on another sensor. Returns `None` if the value can't be
+  /// calibrated, such as when it's outside of a thermocouple's range.
+  pub fn apply(&self, raw: f64, readings: &HashMap<String, Measurement>) -> Option<Measurement> {
+    let (value, unit) = match self {
+      Self::Polynomial { coefficients, unit } => (polynomial(coefficients, raw), *unit),
+      Self::Table { points, unit } => (interpolate(points, raw), *unit),
+      Self::CurrentLoop { min, max, unit, shunt_ohms } => {
+        let milliamps = raw / shunt_ohms * 1000.0;
+        ((milliamps - 4.0) / 16.0 * (max - min) + min, *unit)
+      },
+      Self::Thermocouple { kind, cold_junction } => {
+        let cold_junction = match cold_junction {
+          ColdJunction::Fixed(kelvin) => *kelvin,
+          ColdJunction::Sensor(text_id) => match readings.get(text_id) {
+            Some(Measurement { value, unit: Unit::Kelvin }) => *value,
+            _ => return None,
+          },
+        };
+
+        // the measured EMF is relative to the cold junction, while the NIST
+        // tables are relative to 0 °C
+        let emf = raw * 1000.0 + kind.emf(cold_junction - ZERO_CELSIUS)?;
+        (kind.temperature(emf)? + ZERO_CELSIUS, Unit::Kelvin)
+      },
+    };
+
+    value.is_finite().then_some(Measurement { value, unit })
+  }
+
+  /// Checks that the calibration can be applied to any value.
+  pub fn validate(&self) -> Result<(), String> {
+    match self {
+      Self::Polynomial { coefficients, .. } => {
+        if coefficients.is_empty() || !coefficients.iter().all(|c| c.is_finite()) {
+          return Err("a polynomial needs at least one coefficient, and all must be finite".to_string());
+        }
+      },
+      Self::Table { points, .. } => {
+        if points.len() < 2 || !points.iter().all(|(raw, value)| raw.is_finite() && value.is_finite()) {
+          return Err("a table needs at least two points, and all must be finite".to_string());
+        }
+
+        if points.windows(2).any(|pair| pair[0].0 >= pair[1].0) {
+          return Err("the points of a table must be sorted by their raw value without repeats".to_string());
+        }
+      },
+      Self::CurrentLoop { min, max, shunt_ohms, .. } => {
+        if !(min.is_finite() && max.is_finite()) || min == max {
+          return Err("a current loop's min and max must be finite and different".to_string());
+        }
+
+        if !(shunt_ohms.is_finite() && *shunt_ohms > 0.0) {
+          return Err("a current loop's shunt_ohms must be positive".to_string());
+        }
+      },
+      Self::Thermocouple { cold_junction, .. } => {
+        if let ColdJunction::Fixed(kelvin) = cold_junction {
+          if !(kelvin.is_finite() && *kelvin > 0.0) {
+            return Err("a fixed cold junction temperature must be a positive number of Kelvin".to_string());
+          }
+        }
+      },
+    };
+
+    Ok(())
+  }
+}
+
+fn polynomial(coefficients: &[f64], x: f64) -> f64 {
+  coefficients.iter().rev().fold(0.0, |sum, c| sum * x + c)
+}
+
+fn interpolate(points: &[(f64, f64)], raw: f64) -> f64 {
+  let i = points.partition_point(|(x, _)| *x <= raw).clamp(1, points.len() - 1);
+  let (x0, y0) = points[i - 1];
+  let (x1, y1) = points[i];
+
+  y0 + (raw - x0) * (y1 - y0) / (x1 - x0)
+}
+
+/// A polynomial of a thermocouple's reference table along with the inclusive
+/// range of inputs that it's defined for.
+struct Segment {
+  low: f64,
+  high: f64,
+  coefficients: &'static [f64],
+}
+
+impl ThermocoupleType {
+  /// The EMF in millivolts with the measuring junction at `celsius` and the
+  /// reference junction at 0 °C.
+  fn emf(self, celsius: f64) -> Option<f64> {
+    let segments = match self {
+      Self::K => &TYPE_K_EMF,
+      Self::T => &TYPE_T_EMF,
+    };
+
+    let mut emf = evaluate(segments, celsius)?;
+
+    if self == Self::K && celsius >= 0.0 {
+      let [a0, a1, a2] = TYPE_K_EMF_EXPONENTIAL;
+      emf += a0 * (a1 * (celsius - a2).powi(2)).exp();
+    }
+
+    Some(emf)
+  }
+
+  /// The temperature in °C of the measuring junction given the EMF in
+  /// millivolts with the reference junction at 0 °C.
+  fn temperature(self, millivolts: f64) -> Option<f64> {
+    match self {
+      Self::K => evaluate(&TYPE_K_TEMPERATURE, millivolts),
+      Self::T => evaluate(&TYPE_T_TEMPERATURE, millivolts),
+    }
+  }
+}
+
+/// Evaluates the segment that `x` falls in, which is the higher one where two
+/// segments meet.
+fn evaluate(segments: &[Segment], x: f64) -> Option<f64> {
+  segments
+    .iter()
+    .rev()
+    .find(|s| s.low <= x)
+    .filter(|s| x <= s.high)
+    .map(|s| polynomial(s.coefficients, x))
+}
+
+// NIST ITS-90 thermocouple reference functions and inverse functions
+
+const TYPE_K_EMF: [Segment; 2] = [
+  Segment {
+    low: -270.0,
+    high: 0.0,
+    coefficients: &[
+      0.0,
+      0.394501280250e-1,
+      0.236223735980e-4,
+      -0.328589067840e-6,
+      -0.499048287770e-8,
+      -0.675090591730e-10,
+      -0.574103274280e-12,
+      -0.310888728940e-14,
+      -0.104516093650e-16,
+      -0.198892668780e-19,
+      -0.163226974860e-22,
+    ],
+  },
+  Segment {
+    low: 0.0,
+    high: 1372.0,
+    coefficients: &[
+      -0.176004136860e-1,
+      0.389212049750e-1,
+      0.185587700320e-4,
+      -0.994575928740e-7,
+      0.318409457190e-9,
+      -0.560728448890e-12,
+      0.560750590590e-15,
+      -0.320207200030e-18,
+      0.971511471520e-22,
+      -0.121047212750e-25,
+    ],
+  },
+];
+
+/// The exponential term added to type K's EMF above 0 °C.
+const TYPE_K_EMF_EXPONENTIAL: [f64; 3] = [0.118597600000, -0.118343200000e-3, 0.126968600000e3];
+
+const TYPE_K_TEMPERATURE: [Segment; 3] = [
+  Segment {
+    low: -5.891,
+    high: 0.0,
+    coefficients: &[
+      0.0,
+      2.5173462e1,
+      -1.1662878,
+      -1.0833638,
+      -8.9773540e-1,
+      -3.7342377e-1,
+      -8.6632643e-2,
+      -1.0450598e-2,
+      -5.1920577e-4,
+    ],
+  },
+  Segment {
+    low: 0.0,
+    high: 20.644,
+    coefficients: &[
+      0.0,
+      2.508355e1,
+      7.860106e-2,
+      -2.503131e-1,
+      8.315270e-2,
+      -1.228034e-2,
+      9.804036e-4,
+      -4.413030e-5,
+      1.057734e-6,
+      -1.052755e-8,
+    ],
+  },
+  Segment {
+    low: 20.644,
+    high: 54.886,
+    coefficients: &[
+      -1.318058e2,
+      4.830222e1,
+      -1.646031,
+      5.464731e-2,
+      -9.650715e-4,
+      8.802193e-6,
+      -3.110810e-8,
+    ],
+  },
+];
+
+const TYPE_T_EMF: [Segment; 2] = [
+  Segment {
+    low: -270.0,
+    high: 0.0,
+    coefficients: &[
+      0.0,
+      0.387481063640e-1,
+      0.441944343470e-4,
+      0.118443231050e-6,
+      0.200329735540e-7,
+      0.901380195590e-9,
+      0.226511565930e-10,
+      0.360711542050e-12,
+      0.384939398830e-14,
+      0.282135219250e-16,
+      0.142515947790e-18,
+      0.487686622860e-21,
+      0.107955392700e-23,
+      0.139450270620e-26,
+      0.797951539270e-30,
+    ],
+  },
+  Segment {
+    low: 0.0,
+    high: 400.0,
+    coefficients: &[
+      0.0,
+      0.387481063640e-1,
+      0.332922278800e-4,
+      0.206182434040e-6,
+      -0.218822568460e-8,
+      0.109968809280e-10,
+      -0.308157587720e-13,
+      0.454791352900e-16,
+      -0.275129016730e-19,
+    ],
+  },
+];
+
+const TYPE_T_TEMPERATURE: [Segment; 2] = [
+  Segment {
+    low: -5.603,
+    high: 0.0,
+    coefficients: &[
+      0.0,
+      2.5949192e1,
+      -2.1316967e-1,
+      7.9018692e-1,
+      4.2527777e-1,
+      1.3304473e-1,
+      2.0241446e-2,
+      1.2668171e-3,
+    ],
+  },
+  Segment {
+    low: 0.0,
+    high: 20.872,
+    coefficients: &[
+      0.0,
+      2.592800e1,
+      -7.602961e-1,
+      4.637791e-2,
+      -2.165394e-3,
+      6.048144e-5,
+      -7.293422e-7,
+    ],
+  },
+];
@@ -0,0 +1,60 @@
+//! Where the flight computer gets the current time from.
+//!
+//! Every timeout in the control cycle, the recorder and the simulated boards
+//! is measured against a `Clock` rather than by calling `Instant::now()`
+//! directly, so that tests can drive time by hand with a `ManualClock` instead
+//! of sleeping. Wall-clock timestamps, such as those in recordings, still come
+//! from the system.
+
+use std::{sync::{Arc, Mutex}, time::{Duration, Instant}};
+
+pub trait Clock {
+  fn now(&self) -> Instant;
+}
+
+/// The monotonic clock of the operating system.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+  fn now(&self) -> Instant {
+    Instant::now()
+  }
+}
+
+/// A clock that only moves when told to. Clones share the same time, so a
+/// test can keep a clone to advance the clock given to a `FlightComputer`.
+#[derive(Clone, Debug)]
+pub struct ManualClock(Arc<Mutex<Instant>>);
+
+impl ManualClock {
+  /// Starts the clock at the current time of the system clock.
+  pub fn new() -> Self {
+    ManualClock(Arc::new(Mutex::new(Instant::now())))
+  }
+
+  pub fn advance(&self, by: Duration) {
+    *self.lock() += by;
+  }
+
+  pub fn set(&self, to: Instant) {
+    *self.lock() = to;
+  }
+
+  fn lock(&self) -> std::sync::MutexGuard<'_, Instant> {
+    // an Instant can't be left half-written, so a poisoned lock is still usable
+    self.0.lock().unwrap_or_else(|e| e.into_inner())
+  }
+}
+
+impl Default for ManualClock {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl Clock for ManualClock {
+  fn now(&self) -> Instant {
+    *self.lock()
+  }
+}
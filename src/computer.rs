@@ -0,0 +1,596 @@
+use std::{collections::HashMap, io, net::UdpSocket, os::unix::net::UnixDatagram, process::ExitCode, time::{Duration, Instant}};
+use common::comm::{flight::SequenceDomainCommand, FlightControlMessage, Sequence, VehicleState};
+use mmap_sync::synchronizer::Synchronizer;
+use crate::{clock::{Clock, SystemClock}, config::{CommsLossAction, Config, SafingAction}, device::{self, Connection, Devices}, reactor::Reactor, recorder::{AttributedCommand, Recorder}, redline::Redlines, servo::{self, ServoLink}, ownership::ValveOwners, sequence::{self, OutputLine, SequenceState, Sequences}, shutdown, state, status::StatusMessage, store::{self, Store}, trigger::{self, Triggers}, valve::MismatchDetector, watchdog::ServoWatchdog, Mappings, MAX_WAIT};
+
+/// Everything the flight computer knows, along with the sockets it talks
+/// through. Each call to `tick` runs one control cycle, and `wait` sleeps until
+/// the next one is needed.
+///
+/// Time is only ever read from the clock, so a `ManualClock` makes every
+/// timeout deterministic. Only time is injectable, though: the FC talks
+/// through real sockets, which tests bind to loopback, and connects to Servo
+/// over TCP in the background, so messages still arrive in real time.
+pub struct FlightComputer<C: Clock = SystemClock> {
+  config: Config,
+  clock: C,
+  socket: UdpSocket,
+  command_socket: UnixDatagram,
+  synchronizer: Synchronizer,
+  reactor: Reactor,
+  servo: ServoLink,
+  mappings: Mappings,
+  devices: Devices,
+  sequences: Sequences,
+  triggers: Triggers,
+  abort_sequence: Option<Sequence>,
+  recorder: Recorder,
+  store: Store,
+  mismatches: MismatchDetector,
+  redlines: Redlines,
+  owners: ValveOwners,
+  watchdog: ServoWatchdog,
+  last_sent_to_servo: Instant, // for sending messages to servo
+  last_heartbeat_sent: Instant, // for sending messages to boards
+  last_sequence_statuses_sent: Instant,
+  mapping_has_prvnt: bool,
+  sent_prvnt_sam_msg: bool,
+}
+
+impl<C: Clock> FlightComputer<C> {
+  /// Creates a flight computer that communicates through the given sockets,
+  /// which must already be non-blocking. `socket` talks to the boards and
+  /// Servo's data port, while `command_socket` receives commands from
+  /// sequences.
+  pub fn new(config: Config, clock: C, socket: UdpSocket, command_socket: UnixDatagram, synchronizer: Synchronizer) -> io::Result<Self> {
+    let now = clock.now();
+    let reactor = Reactor::new(&socket, &command_socket)?;
+    let mut servo = ServoLink::new(config.servo_addresses.clone(), now);
+    servo.set_waker(reactor.waker());
+    let mut sequences = Sequences::new(&config);
+    sequences.set_waker(reactor.waker());
+
+    if let Err(e) = sequence::pass_credentials(&command_socket) {
+      eprintln!("Couldn't identify the senders of sequence commands, valve ownership won't be tracked: {e}");
+    }
+
+    let store = Store::new(&config.store);
+    let (mappings, abort_sequence) = restore(&store);
+
+    Ok(FlightComputer {
+      reactor,
+      servo,
+      mapping_has_prvnt: mappings.get("PRVNT").is_some(),
+      mappings,
+      devices: Devices::new(&config),
+      sequences,
+      triggers: HashMap::new(),
+      abort_sequence: abort_sequence.or_else(|| load_default_abort(&config)),
+      recorder: Recorder::new(&config.recorder, now),
+      store,
+      mismatches: MismatchDetector::new(&config),
+      redlines: Redlines::new(&config),
+      owners: ValveOwners::new(&config),
+      watchdog: ServoWatchdog::new(&config),
+      last_sent_to_servo: now,
+      last_heartbeat_sent: now,
+      last_sequence_statuses_sent: now,
+      sent_prvnt_sam_msg: false,
+      config,
+      clock,
+      socket,
+      command_socket,
+      synchronizer,
+    })
+  }
+
+  /// Runs a single control cycle without blocking.
+  pub fn tick(&mut self) {
+    let now = self.clock.now();
+
+    let was_connected = self.servo.is_connected();
+    let servo_messages = self.servo.poll(now);
+
+    if let Err(e) = self.reactor.watch_servo(self.servo.stream()) {
+      eprintln!("Couldn't watch the Servo stream for incoming messages: {e}");
+    }
+
+    // escalate through the stages of servo loss the longer it stays silent
+    let mut reports = Vec::new();
+
+    if self.servo.is_connected() && !was_connected {
+      self.watchdog.reset(&mut reports);
+      reports.push(self.versions());
+      reports.extend(self.sequences.statuses().into_iter().map(StatusMessage::Sequence));
+      self.last_sequence_statuses_sent = now;
+    }
+
+    let silent_for = now.saturating_duration_since(self.servo.last_received());
+    let servo_loss_actions = self.watchdog.check(silent_for, &mut reports);
+
+    for report in reports {
+      self.report(report);
+    }
+
+    for action in servo_loss_actions {
+      match action {
+        SafingAction::Warn => println!("Haven't heard from Servo in {silent_for:?}."),
+        SafingAction::Abort => {
+          println!("Haven't heard from Servo in {silent_for:?}, aborting...");
+          self.abort();
+        },
+        SafingAction::SafeValves => {
+          println!("Haven't heard from Servo in {silent_for:?}, safing valves...");
+          self.safe_valves();
+        },
+      };
+    }
+
+    // decoding servo messages, if any were received
+    for command in servo_messages {
+      println!("Recieved a FlightControlMessage: {command:#?}");
+      self.recorder.servo_message(&command);
+
+      match command {
+        FlightControlMessage::Abort => self.abort(),
+        FlightControlMessage::AhrsCommand(c) => self.devices.send_ahrs_command(&self.socket, c),
+        FlightControlMessage::BmsCommand(c) => self.devices.send_bms_command(&self.socket, c),
+        FlightControlMessage::Trigger(t) => {
+          let mut reports = Vec::new();
+          trigger::register(&mut self.triggers, t, &self.mappings, &mut reports);
+
+          for report in reports {
+            self.report(report);
+          }
+        },
+        FlightControlMessage::Mappings(m) => {
+          if let Err(e) = self.store.save_mappings(&m) {
+            eprintln!("Couldn't store the mappings, they will be lost if the FC restarts: {e}");
+          }
+
+          self.mappings = Mappings::new(m);
+
+          if !self.mappings.duplicates().is_empty() {
+            self.report(StatusMessage::DuplicateMappings { text_ids: self.mappings.duplicates().to_vec() });
+          }
+
+          self.mapping_has_prvnt = self.mappings.get("PRVNT").is_some();
+          self.sent_prvnt_sam_msg = false;
+          // send clear message to sams. this is needed in case we move PRVNT to a different sam on the new mappings
+          // as the old mapped sam will still think it has prvnt. we also need to sent the prvnt msg to the newly mapped
+          // prvnt sam (if it exists)
+          // still need to figure out when to send messages when devices connect
+          self.devices.send_sam_clear_prvnt_channel(&self.socket, &self.mappings);
+          // need to send prvnt mapping to sam board again if mappings change while everything is up
+          self.report(self.versions());
+        },
+        FlightControlMessage::Sequence(s) if s.name == sequence::ABORT => {
+          if let Err(e) = self.store.save_abort_sequence(&s) {
+            eprintln!("Couldn't store the abort sequence, it will be lost if the FC restarts: {e}");
+          }
+
+          self.abort_sequence = Some(s);
+          self.report(self.versions());
+        },
+        FlightControlMessage::Sequence(ref s) => {
+          sequence::execute(&self.mappings, s, &mut self.sequences, now);
+        },
+        FlightControlMessage::StopSequence(n) => {
+          if let Err(e) = sequence::kill(&mut self.sequences, &n) {
+            eprintln!("There was an issue in stopping sequence '{n}': {e}");
+          }
+        },
+      };
+    }
+
+    // updates records
+    self.devices.update_last_updates(now);
+
+    if now.duration_since(self.last_sent_to_servo) > self.config.fc_to_servo_rate {
+      // send servo the current vehicle telemetry
+      if let Some(servo_address) = self.servo.address() {
+        if let Err(e) = servo::push(&self.socket, servo_address, self.config.servo_data_port, self.devices.get_state()) {
+          eprintln!("Issue in sending servo the vehicle telemetry: {e}");
+        }
+      }
+      self.last_sent_to_servo = now;
+    }
+
+    // receive telemetry
+    let telemetry = device::receive(&self.socket);
+
+    for (address, message) in &telemetry {
+      self.recorder.telemetry(*address, message);
+    }
+
+    // process telemetry from boards
+    self.devices.update_state(telemetry, &self.mappings, &self.socket, now);
+
+    self.check_connections(now);
+
+    // flag valves that haven't reached their commanded state and sensors
+    // that are out of limits
+    let mut reports = Vec::new();
+    let mismatch_abort = self.mismatches.check(self.devices.get_state(), now, &mut reports);
+    let redline_actions = self.redlines.check(self.devices.get_state(), now, &mut reports);
+
+    for report in reports {
+      self.report(report);
+    }
+
+    if redline_actions.contains(&SafingAction::SafeValves) {
+      println!("A redline was exceeded, safing valves...");
+      self.safe_valves();
+    }
+
+    if mismatch_abort {
+      println!("A valve didn't reach its commanded state in time, aborting...");
+      self.abort();
+    } else if redline_actions.contains(&SafingAction::Abort) {
+      println!("A redline was exceeded, aborting...");
+      self.abort();
+    }
+
+    self.recorder.state(self.devices.get_state(), now);
+
+    // updates all running sequences with the newest received data
+    if let Err(e) = state::sync_sequences(&mut self.synchronizer, self.devices.get_state()) {
+      println!("There was an error in synchronizing vehicle state: {e}");
+    }
+
+    let need_to_send_heartbeat = now.duration_since(self.last_heartbeat_sent) > self.config.send_heartbeat_rate;
+    // Update board lifetimes and send heartbeats to connected boards.
+    for device in self.devices.iter() {
+      if device.is_disconnected(now) {
+        continue;
+      }
+
+      if need_to_send_heartbeat {
+        if let Err(e) = device.send_heartbeat(&self.socket, &self.devices, &self.mappings) {
+          println!(
+            "There was an error in notifying board {} at IP {} that the FC is still connected: {e}",
+            device.get_board_id(),
+            device.get_ip()
+          );
+        }
+        self.last_heartbeat_sent = now;
+      }
+    }
+
+    for device in self.devices.iter_mut() {
+      device.set_first_heartbeat_var(false);
+    }
+
+    // sequences and triggers
+    let mut sam_commands = Vec::new();
+    let mut reports = Vec::new();
+
+    for (pid, command) in sequence::pull_commands(&self.command_socket) {
+      let sender = pid.and_then(|pid| self.sequences.name_of(pid)).map(str::to_string);
+
+      // aborts are never held back, and unknown senders have no limits
+      let within_rate = match (&command, &sender) {
+        (SequenceDomainCommand::Abort, _) | (_, None) => true,
+        (_, Some(name)) => self.sequences.admit(name, now, &mut reports),
+      };
+
+      let carried_out = within_rate && self.owners.permit(&command, sender.as_deref(), &mut reports);
+
+      if matches!(command, SequenceDomainCommand::Abort) {
+        match (&sender, pid) {
+          (Some(name), _) => println!("Sequence '{name}' requested an abort."),
+          (None, Some(pid)) => println!("An unknown process with PID {pid} requested an abort."),
+          (None, None) => println!("An unknown process requested an abort."),
+        };
+      }
+
+      self.recorder.sequence_command(AttributedCommand {
+        sequence: sender,
+        pid,
+        carried_out,
+        command: command.clone(),
+      });
+
+      if carried_out {
+        if let SequenceDomainCommand::ActuateValve { valve, .. } = &command {
+          self.mismatches.commanded(valve, now, &mut reports);
+        }
+
+        sam_commands.push(command);
+      }
+    }
+
+    for report in reports {
+      self.report(report);
+    }
+
+    let should_abort = self.devices.send_sam_commands(&self.socket, &self.mappings, sam_commands, &mut self.recorder);
+
+    if should_abort {
+      self.abort();
+    }
+
+    // triggers
+    trigger::check(&mut self.triggers, self.devices.get_state(), &self.mappings, &mut self.sequences, now);
+
+    let mut reports = Vec::new();
+
+    for line in self.sequences.output(&mut reports) {
+      self.forward_output(line);
+    }
+
+    for report in reports {
+      self.report(report);
+    }
+
+    // report sequences that started or stopped, which may take up to
+    // MAX_WAIT to notice as exiting children don't wake the reactor
+    for status in self.sequences.reap(now) {
+      let mut reports = Vec::new();
+
+      if status.state != SequenceState::Running {
+        self.owners.release(&status.name, &mut reports);
+      }
+
+      self.report(StatusMessage::Sequence(status));
+
+      for report in reports {
+        self.report(report);
+      }
+    }
+
+    if now.duration_since(self.last_sequence_statuses_sent) >= self.config.sequence_status_rate {
+      self.resend_sequence_statuses();
+      self.last_sequence_statuses_sent = now;
+    }
+
+    self.recorder.flush(now);
+  }
+
+  /// Sleeps until a board, a sequence or Servo sends something, or until the
+  /// next deadline of the control cycle, whichever comes first. Doesn't sleep
+  /// at all while received messages or sequence output are left over.
+  pub fn wait(&mut self) {
+    let timeout = if self.servo.has_pending() || self.sequences.has_pending_output() {
+      Duration::ZERO
+    } else {
+      let now = self.clock.now();
+
+      self.next_deadline(now)
+        .map_or(MAX_WAIT, |deadline| deadline.saturating_duration_since(now))
+        .min(MAX_WAIT)
+    };
+
+    if let Err(e) = self.reactor.wait(timeout) {
+      eprintln!("Couldn't wait for incoming messages: {e}");
+    }
+  }
+
+  /// The soonest time at which `tick` has something to do even if nothing is
+  /// received, such as sending heartbeats or noticing that Servo went silent.
+  fn next_deadline(&self, now: Instant) -> Option<Instant> {
+    let mut deadlines = vec![self.servo.deadline()];
+
+    if self.servo.address().is_some() {
+      deadlines.push(Some(self.last_sent_to_servo + self.config.fc_to_servo_rate));
+    }
+
+    deadlines.push(self.watchdog.deadline(self.servo.last_received()));
+    deadlines.push(self.sequences.deadline());
+
+    if self.servo.address().is_some() {
+      deadlines.push(Some(self.last_sequence_statuses_sent + self.config.sequence_status_rate));
+    }
+
+    if self.devices.iter().any(|d| !d.is_disconnected(now)) {
+      deadlines.push(Some(self.last_heartbeat_sent + self.config.send_heartbeat_rate));
+    }
+
+    deadlines.into_iter().flatten().min()
+  }
+
+  /// Safes the vehicle and releases every resource, returning the status that
+  /// the process should exit with.
+  pub fn stop(mut self) -> ExitCode {
+    let code = shutdown::stop(&self.socket, &self.devices, &mut self.sequences, &mut self.recorder);
+    self.recorder.flush(self.clock.now());
+    code
+  }
+
+  /// Applies the loss-of-comms policy of every board that was lost or
+  /// restored since the last cycle.
+  fn check_connections(&mut self, now: Instant) {
+    for (board_id, change) in self.devices.check_connections(now) {
+      let action = self.config.boards.get(&board_id).map(|b| b.on_disconnect).unwrap_or_default();
+
+      if action == CommsLossAction::Ignore {
+        continue;
+      }
+
+      match change {
+        Connection::Lost => {
+          println!("Lost connection with {board_id}, responding with {action:?}.");
+          self.report(StatusMessage::BoardLost { board_id, action });
+
+          match action {
+            CommsLossAction::SafeValves => self.safe_valves(),
+            CommsLossAction::Abort => self.abort(),
+            CommsLossAction::Ignore | CommsLossAction::Alarm => {},
+          };
+        },
+        Connection::Restored => self.report(StatusMessage::BoardRestored { board_id }),
+      };
+    }
+  }
+
+  /// The versions of the mappings and abort sequence currently in use.
+  fn versions(&self) -> StatusMessage {
+    StatusMessage::Versions {
+      mappings: (!self.mappings.is_empty()).then(|| store::version(&*self.mappings)).flatten(),
+      abort_sequence: self.abort_sequence.as_ref().and_then(store::version),
+    }
+  }
+
+  /// Logs and records a line of sequence output, and sends it to Servo if
+  /// connected. Unlike other reports, it's recorded as output rather than as
+  /// a status.
+  fn forward_output(&mut self, line: OutputLine) {
+    println!("[{}] {}", line.name, line.line);
+    self.recorder.sequence_output(&line);
+
+    if let Some(servo_address) = self.servo.address() {
+      if let Err(e) = servo::report(&self.socket, servo_address, self.config.servo_status_port, &StatusMessage::SequenceOutput(line)) {
+        eprintln!("Couldn't forward sequence output to Servo: {e}");
+      }
+    }
+  }
+
+  /// Sends Servo the status of every known sequence, in case a report of one
+  /// starting or stopping was lost. Nothing has changed, so they're neither
+  /// logged nor recorded again.
+  fn resend_sequence_statuses(&self) {
+    let Some(servo_address) = self.servo.address() else {
+      return;
+    };
+
+    for status in self.sequences.statuses() {
+      if let Err(e) = servo::report(&self.socket, servo_address, self.config.servo_status_port, &StatusMessage::Sequence(status)) {
+        eprintln!("Issue in re-sending servo the status of a sequence: {e}");
+      }
+    }
+  }
+
+  /// Logs and records a status report, and sends it to Servo if connected.
+  fn report(&mut self, message: StatusMessage) {
+    println!("Reporting status to servo: {message:?}");
+    self.recorder.status(&message);
+
+    if let Some(servo_address) = self.servo.address() {
+      if let Err(e) = servo::report(&self.socket, servo_address, self.config.servo_status_port, &message) {
+        eprintln!("Issue in sending servo a status report: {e}");
+      }
+    }
+  }
+
+  /// Commands every SAM to safe its valves, which are then considered
+  /// commanded to their unpowered state and given time to settle there.
+  fn safe_valves(&mut self) {
+    let boards = self.devices.send_sam_safe_valves(&self.socket, &mut self.recorder);
+    let now = self.clock.now();
+    let mut reports = Vec::new();
+
+    for valve in self.devices.set_safed(&boards, &self.mappings) {
+      self.mismatches.commanded(&valve, now, &mut reports);
+    }
+
+    for report in reports {
+      self.report(report);
+    }
+  }
+
+  fn abort(&mut self) {
+    // a running abort sequence is left to finish rather than being cut off by
+    // a repeated abort, as it couldn't be restarted until its kill is reaped
+    if self.sequences.is_running(sequence::ABORT) {
+      println!("Received an abort command while the abort sequence is running, letting it finish...");
+      self.sequences.kill_all_except(sequence::ABORT);
+      return;
+    }
+
+    self.sequences.kill_all();
+
+    let started = match self.abort_sequence {
+      Some(ref sequence) => sequence::execute(&self.mappings, sequence, &mut self.sequences, self.clock.now()),
+      None => {
+        println!("Received an abort command, but no abort sequence has been set.");
+        false
+      },
+    };
+
+    // the vehicle is safed regardless, as an abort must never be ignored
+    if !started {
+      println!("Safing valves in place of the abort sequence...");
+      self.safe_valves();
+    }
+  }
+
+  /// The socket that the boards and Servo's data port are talked to through.
+  pub fn socket(&self) -> &UdpSocket {
+    &self.socket
+  }
+
+  pub fn clock(&self) -> &C {
+    &self.clock
+  }
+
+  pub fn state(&self) -> &VehicleState {
+    self.devices.get_state()
+  }
+
+  pub fn devices(&self) -> &Devices {
+    &self.devices
+  }
+
+  pub fn mappings(&self) -> &Mappings {
+    &self.mappings
+  }
+
+  pub fn servo(&self) -> &ServoLink {
+    &self.servo
+  }
+
+  /// Whether the vehicle has been safed because Servo went silent for longer
+  /// than `servo_to_fc_time_to_live`. Cleared once Servo reconnects.
+  pub fn is_servo_lost(&self) -> bool {
+    self.watchdog.is_lost()
+  }
+}
+
+/// Loads the abort sequence that's used until Servo uploads one, if any.
+fn load_default_abort(config: &Config) -> Option<Sequence> {
+  let path = config.default_abort_sequence.as_ref()?;
+
+  match sequence::load_abort(path) {
+    Ok(sequence) => Some(sequence),
+    Err(e) => {
+      eprintln!("Couldn't load the default abort sequence from '{}', aborts will only safe valves: {e}", path.display());
+      None
+    },
+  }
+}
+
+/// Restores the mappings and abort sequence stored before the FC last
+/// stopped, if any.
+fn restore(store: &Store) -> (Mappings, Option<Sequence>) {
+  let mappings = match store.load_mappings() {
+    Ok(Some(mappings)) => {
+      println!("Restored {} stored mappings.", mappings.len());
+      let mappings = Mappings::new(mappings);
+
+      for text_id in mappings.duplicates() {
+        eprintln!("Mapping '{text_id}' is defined more than once, ignoring all but the first definition.");
+      }
+
+      mappings
+    },
+    Ok(None) => Mappings::default(),
+    Err(e) => {
+      eprintln!("Couldn't restore the stored mappings, waiting for Servo to send them: {e}");
+      Mappings::default()
+    },
+  };
+
+  let abort_sequence = match store.load_abort_sequence() {
+    Ok(Some(sequence)) => {
+      println!("Restored the stored abort sequence.");
+      Some(sequence)
+    },
+    Ok(None) => None,
+    Err(e) => {
+      eprintln!("Couldn't restore the stored abort sequence, waiting for Servo to send it: {e}");
+      None
+    },
+  };
+
+  (mappings, abort_sequence)
+}
@@ -0,0 +1,544 @@
+use std::{collections::HashMap, fmt, fs, io, net::SocketAddr, path::PathBuf, time::Duration};
+use serde::{Deserialize, Deserializer, Serialize};
+use crate::calibration::Calibrations;
+
+/// Runtime configuration of the flight computer, read from a TOML file given
+/// with `--config <path>` and then overridden by any other command line flags.
+/// Every field falls back to the values the FC has historically used.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+  /// The addresses, in `host:port` form, at which Servo is tried in order.
+  pub servo_addresses: Vec<String>,
+
+  /// The address that the FC listens on for board telemetry.
+  pub fc_address: SocketAddr,
+
+  /// The identity that the FC hands boards during the handshake.
+  pub identity: String,
+
+  /// The port that boards receive commands on.
+  pub device_command_port: u16,
+
+  /// The port that Servo receives vehicle telemetry on.
+  pub servo_data_port: u16,
+
+  /// The port that Servo receives status reports, such as alarms, on.
+  pub servo_status_port: u16,
+
+  /// How long from the last received message before a board is considered
+  /// disconnected.
+  #[serde(rename = "time_to_live_ms", deserialize_with = "milliseconds")]
+  pub time_to_live: Duration,
+
+  /// How often we want to update servo
+  #[serde(rename = "fc_to_servo_rate_ms", deserialize_with = "milliseconds")]
+  pub fc_to_servo_rate: Duration,
+
+  /// How often we want to send hearbeats
+  #[serde(rename = "send_heartbeat_rate_ms", deserialize_with = "milliseconds")]
+  pub send_heartbeat_rate: Duration,
+
+  /// If we do not hear from servo for this amount of time, we abort
+  #[serde(rename = "servo_to_fc_time_to_live_ms", deserialize_with = "milliseconds")]
+  pub servo_to_fc_time_to_live: Duration,
+
+  /// How often the status of every sequence is sent to Servo, on top of
+  /// whenever one starts or stops, so that a lost report is made up for.
+  #[serde(rename = "sequence_status_rate_ms", deserialize_with = "milliseconds")]
+  pub sequence_status_rate: Duration,
+
+  /// Actions taken as Servo stays silent, in addition to safing valves after
+  /// `servo_to_fc_time_to_live_ms`, under `[[servo_loss]]`.
+  pub servo_loss: Vec<ServoLossStage>,
+
+  /// A Python script that's run as the abort sequence until Servo uploads
+  /// one. Without either, aborting kills every sequence and safes valves.
+  pub default_abort_sequence: Option<PathBuf>,
+
+  /// What happens when a sequence actuates a valve owned by another.
+  pub on_valve_conflict: ValveConflictAction,
+
+  /// Limits on the resources of individual sequences, under
+  /// `[sequences.<name>]`. Sequences without an entry are unlimited.
+  pub sequences: HashMap<String, SequenceConfig>,
+
+  /// Settings of the flight data recorder, under `[recorder]`.
+  pub recorder: RecorderConfig,
+
+  /// Where what Servo uploads is kept across restarts, under `[store]`.
+  pub store: StoreConfig,
+
+  /// Calibrations of individual sensors, under `[calibrations.<text_id>]`.
+  pub calibrations: Calibrations,
+
+  /// How the state of individual valves is estimated, under
+  /// `[valves.<text_id>]`. Valves without an entry use the defaults.
+  pub valves: HashMap<String, ValveConfig>,
+
+  /// Limits that the FC enforces on individual sensors, under
+  /// `[redlines.<text_id>]`.
+  pub redlines: HashMap<String, RedlineConfig>,
+
+  /// How the FC reacts to losing individual boards, under
+  /// `[boards.<board_id>]`. Boards without an entry use the defaults.
+  pub boards: HashMap<String, BoardConfig>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RecorderConfig {
+  /// Whether telemetry, commands and state snapshots are recorded to disk.
+  pub enabled: bool,
+
+  /// The directory that recordings are written to.
+  pub directory: PathBuf,
+
+  /// The size in bytes after which a new recording file is started.
+  pub max_file_size: u64,
+
+  /// How many recording files are kept before the oldest is deleted.
+  pub max_files: usize,
+
+  /// How often the vehicle state is recorded.
+  #[serde(rename = "snapshot_interval_ms", deserialize_with = "milliseconds")]
+  pub snapshot_interval: Duration,
+
+  /// How often recorded data is forced onto the disk. Anything recorded since
+  /// the last sync may be lost on power loss.
+  #[serde(rename = "sync_interval_ms", deserialize_with = "milliseconds")]
+  pub sync_interval: Duration,
+}
+
+/// What the FC does when a sequence actuates a valve that's owned by another
+/// running sequence.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ValveConflictAction {
+  /// Report the conflict and hand the valve over to the new sequence.
+  #[default]
+  Warn,
+
+  /// Report the conflict and drop the command.
+  Reject,
+}
+
+/// Limits applied to a sequence. A sequence that exceeds its timeout or CPU
+/// time is killed and reported, one that exceeds its memory fails to allocate
+/// and is reported if it exits because of it, and commands beyond its rate are
+/// dropped.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SequenceConfig {
+  /// How long the sequence may run for in real time.
+  #[serde(rename = "timeout_ms", deserialize_with = "optional_milliseconds")]
+  pub timeout: Option<Duration>,
+
+  /// How many seconds of CPU time the sequence may use.
+  pub cpu_seconds: Option<u64>,
+
+  /// How many bytes of virtual memory the sequence may map.
+  pub memory_bytes: Option<u64>,
+
+  /// The niceness that the sequence runs at, from -20 to 19. If it can't be
+  /// set, such as when it's negative and the FC lacks CAP_SYS_NICE, the
+  /// sequence runs at the default instead.
+  pub nice: Option<i32>,
+
+  /// How many commands the sequence may send within any one second. Aborts
+  /// are never dropped.
+  pub max_commands_per_second: Option<u32>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StoreConfig {
+  /// Whether the mappings and abort sequence are stored and restored on boot.
+  pub enabled: bool,
+
+  /// The directory that they're stored in.
+  pub directory: PathBuf,
+}
+
+/// The limits used to estimate the actual state of a valve from the voltage and
+/// current that its SAM channel measures.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ValveConfig {
+  /// The current in amps at or above which the valve is considered powered.
+  /// Falls back to the `powered_threshold` of the valve's mapping.
+  pub powered_current: Option<f64>,
+
+  /// The voltage below which an unpowered valve is considered closed rather
+  /// than disconnected. Must be less than `powered_voltage`.
+  pub unpowered_voltage: f64,
+
+  /// The voltage below which a powered valve is considered faulted rather
+  /// than open.
+  pub powered_voltage: f64,
+
+  /// How far in amps the current must cross back over `powered_current`
+  /// before the valve is considered to have changed between powered and
+  /// unpowered. Must be less than `powered_current`.
+  pub current_hysteresis: f64,
+
+  /// How far in volts the voltage must cross back over either voltage
+  /// threshold before the estimate changes back.
+  pub voltage_hysteresis: f64,
+
+  /// How long a new estimate must hold before it's reported as the valve's
+  /// actual state.
+  #[serde(rename = "debounce_ms", deserialize_with = "milliseconds")]
+  pub debounce: Duration,
+
+  /// How long the valve may take to reach its commanded state before it's
+  /// reported as mismatched.
+  #[serde(rename = "settle_time_ms", deserialize_with = "milliseconds")]
+  pub settle_time: Duration,
+
+  /// Whether a mismatch on this valve triggers an abort.
+  pub abort_on_mismatch: bool,
+}
+
+/// The limits of a single sensor and what to do when they're exceeded.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RedlineConfig {
+  /// The lowest allowed reading, if any.
+  pub min: Option<f64>,
+
+  /// The highest allowed reading, if any.
+  pub max: Option<f64>,
+
+  /// How long the reading must stay out of limits before the redline trips,
+  /// so that a single noisy sample doesn't trip it.
+  #[serde(default, rename = "persistence_ms", deserialize_with = "milliseconds")]
+  pub persistence: Duration,
+
+  #[serde(default)]
+  pub action: SafingAction,
+}
+
+/// What the FC does when a redline trips or a stage of Servo loss is reached.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SafingAction {
+  /// Only report the violation.
+  #[default]
+  Warn,
+
+  /// Report the violation and run the abort sequence.
+  Abort,
+
+  /// Report the violation and command every SAM to safe its valves.
+  SafeValves,
+}
+
+/// An action taken once Servo has been silent for longer than `after`. Each
+/// stage is taken once per loss of Servo.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ServoLossStage {
+  #[serde(rename = "after_ms", deserialize_with = "milliseconds")]
+  pub after: Duration,
+  pub action: SafingAction,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BoardConfig {
+  /// What the FC does once the board has been silent for longer than
+  /// `time_to_live_ms`. The board coming back is reported unless this is
+  /// `ignore`.
+  pub on_disconnect: CommsLossAction,
+}
+
+/// What the FC does when a board is lost.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CommsLossAction {
+  /// Do nothing besides no longer sending the board heartbeats.
+  Ignore,
+
+  /// Report the loss to Servo.
+  #[default]
+  Alarm,
+
+  /// Report the loss and command every SAM to safe its valves.
+  SafeValves,
+
+  /// Report the loss and run the abort sequence.
+  Abort,
+}
+
+impl Default for ValveConfig {
+  fn default() -> Self {
+    ValveConfig {
+      powered_current: None,
+      unpowered_voltage: 4.0,
+      powered_voltage: 20.0,
+      current_hysteresis: 0.0,
+      voltage_hysteresis: 0.0,
+      debounce: Duration::ZERO,
+      settle_time: Duration::from_secs(1),
+      abort_on_mismatch: false,
+    }
+  }
+}
+
+impl Default for RecorderConfig {
+  fn default() -> Self {
+    RecorderConfig {
+      enabled: true,
+      directory: PathBuf::from("recordings"),
+      max_file_size: 64 * 1024 * 1024,
+      max_files: 64,
+      snapshot_interval: Duration::from_millis(100),
+      sync_interval: Duration::from_secs(1),
+    }
+  }
+}
+
+impl Default for StoreConfig {
+  fn default() -> Self {
+    StoreConfig {
+      enabled: true,
+      directory: PathBuf::from("state"),
+    }
+  }
+}
+
+impl Default for Config {
+  fn default() -> Self {
+    Config {
+      servo_addresses: vec![
+        "192.168.1.10:5025".to_string(),
+        "server-01.local:5025".to_string(),
+        "server-02.local:5025".to_string(),
+        "localhost:5025".to_string(),
+      ],
+      fc_address: SocketAddr::from(([0, 0, 0, 0], 4573)),
+      identity: "flight-01".to_string(),
+      device_command_port: 8378,
+      servo_data_port: 7201,
+      servo_status_port: 7202,
+      time_to_live: Duration::from_millis(350),
+      fc_to_servo_rate: Duration::from_millis(10),
+      send_heartbeat_rate: Duration::from_millis(50),
+      servo_to_fc_time_to_live: Duration::from_secs(60 * 10),
+      sequence_status_rate: Duration::from_secs(1),
+      servo_loss: Vec::new(),
+      default_abort_sequence: None,
+      on_valve_conflict: ValveConflictAction::default(),
+      sequences: HashMap::new(),
+      recorder: RecorderConfig::default(),
+      store: StoreConfig::default(),
+      calibrations: HashMap::new(),
+      valves: HashMap::new(),
+      redlines: HashMap::new(),
+      boards: HashMap::new(),
+    }
+  }
+}
+
+impl Config {
+  /// Builds the configuration from the process arguments.
+  pub fn from_args() -> Result<Self> {
+    Self::parse(std::env::args().skip(1))
+  }
+
+  /// Builds the configuration from command line arguments, excluding the
+  /// name of the program.
+  pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self> {
+    let mut args = args.into_iter();
+    let mut config_path: Option<PathBuf> = None;
+    let mut overrides = Vec::new();
+
+    while let Some(flag) = args.next() {
+      let Some(value) = args.next() else {
+        return Err(Error::MissingValue(flag));
+      };
+
+      match flag.as_str() {
+        "--config" => config_path = Some(PathBuf::from(value)),
+        "--servo" | "--fc-address" | "--identity" | "--device-command-port" | "--servo-data-port" | "--servo-status-port" => {
+          overrides.push((flag, value));
+        },
+        _ => return Err(Error::UnknownFlag(flag)),
+      };
+    }
+
+    let mut config = match config_path {
+      Some(path) => {
+        let contents = fs::read_to_string(&path).map_err(|e| Error::Read(path.clone(), e))?;
+        toml::from_str(&contents).map_err(|e| Error::Parse(path, e))?
+      },
+      None => Config::default(),
+    };
+
+    let mut servo_overridden = false;
+    for (flag, value) in overrides {
+      let invalid = || Error::InvalidValue(flag.clone(), value.clone());
+
+      match flag.as_str() {
+        "--servo" => {
+          // the first --servo replaces the configured list, later ones append
+          if !servo_overridden {
+            config.servo_addresses.clear();
+            servo_overridden = true;
+          }
+
+          config.servo_addresses.push(value);
+        },
+        "--fc-address" => config.fc_address = value.parse().map_err(|_| invalid())?,
+        "--identity" => config.identity = value,
+        "--device-command-port" => config.device_command_port = value.parse().map_err(|_| invalid())?,
+        "--servo-data-port" => config.servo_data_port = value.parse().map_err(|_| invalid())?,
+        "--servo-status-port" => config.servo_status_port = value.parse().map_err(|_| invalid())?,
+        _ => unreachable!("only known flags are collected as overrides"),
+      };
+    }
+
+    config.validate()?;
+    Ok(config)
+  }
+
+  /// Checks that the FC can run with the configuration, returning the first
+  /// problem found.
+  pub fn validate(&self) -> Result<()> {
+    if self.servo_addresses.is_empty() {
+      return Err(Error::Invalid("at least one servo address must be given".to_string()));
+    }
+
+    for address in &self.servo_addresses {
+      let valid = address
+        .rsplit_once(':')
+        .is_some_and(|(host, port)| !host.is_empty() && port.parse::<u16>().is_ok_and(|p| p != 0));
+
+      if !valid {
+        return Err(Error::Invalid(format!("servo address '{address}' is not of the form host:port")));
+      }
+    }
+
+    if self.identity.is_empty() {
+      return Err(Error::Invalid("the identity can't be empty".to_string()));
+    }
+
+    if self.device_command_port == 0 || self.servo_data_port == 0 || self.servo_status_port == 0 {
+      return Err(Error::Invalid("ports must be nonzero".to_string()));
+    }
+
+    let durations = [
+      ("time_to_live_ms", self.time_to_live),
+      ("fc_to_servo_rate_ms", self.fc_to_servo_rate),
+      ("send_heartbeat_rate_ms", self.send_heartbeat_rate),
+      ("servo_to_fc_time_to_live_ms", self.servo_to_fc_time_to_live),
+      ("sequence_status_rate_ms", self.sequence_status_rate),
+    ];
+
+    for (name, duration) in durations {
+      if duration.is_zero() {
+        return Err(Error::Invalid(format!("{name} must be nonzero")));
+      }
+    }
+
+    if self.servo_loss.iter().any(|stage| stage.after.is_zero()) {
+      return Err(Error::Invalid("the after_ms of every servo_loss stage must be nonzero".to_string()));
+    }
+
+    if let Some(path) = &self.default_abort_sequence {
+      if !path.is_file() {
+        return Err(Error::Invalid(format!("the default abort sequence '{}' isn't a file", path.display())));
+      }
+    }
+
+    for (name, sequence) in &self.sequences {
+      let nonzero = sequence.timeout.is_none_or(|t| !t.is_zero())
+        && sequence.cpu_seconds.is_none_or(|s| s > 0)
+        && sequence.memory_bytes.is_none_or(|b| b > 0)
+        && sequence.max_commands_per_second.is_none_or(|c| c > 0);
+
+      if !nonzero || sequence.nice.is_some_and(|n| !(-20..=19).contains(&n)) {
+        return Err(Error::Invalid(format!("the limits of sequence '{name}' must be nonzero, with nice from -20 to 19")));
+      }
+    }
+
+    if self.recorder.max_files == 0 || self.recorder.max_file_size < 1024 {
+      return Err(Error::Invalid("the recorder must keep at least one file of at least 1 KiB".to_string()));
+    }
+
+    for (text_id, calibration) in &self.calibrations {
+      calibration.validate().map_err(|reason| Error::Invalid(format!("the calibration of '{text_id}' is invalid: {reason}")))?;
+    }
+
+    for (text_id, valve) in &self.valves {
+      let limits = [valve.unpowered_voltage, valve.powered_voltage, valve.current_hysteresis, valve.voltage_hysteresis];
+
+      if !limits.iter().all(|l| l.is_finite() && *l >= 0.0) || valve.powered_current.is_some_and(|c| !(c.is_finite() && c > 0.0)) {
+        return Err(Error::Invalid(format!("the limits of valve '{text_id}' must be finite and not negative, and powered_current must be positive")));
+      }
+
+      // a valve could never be closed or never be open otherwise
+      if valve.unpowered_voltage >= valve.powered_voltage {
+        return Err(Error::Invalid(format!("the unpowered_voltage of valve '{text_id}' must be less than its powered_voltage")));
+      }
+
+      // a powered valve could never be considered unpowered again otherwise
+      if valve.powered_current.is_some_and(|c| valve.current_hysteresis >= c) {
+        return Err(Error::Invalid(format!("the current_hysteresis of valve '{text_id}' must be less than its powered_current")));
+      }
+    }
+
+    for (text_id, redline) in &self.redlines {
+      let valid = match (redline.min, redline.max) {
+        (Some(min), Some(max)) => min.is_finite() && max.is_finite() && min < max,
+        (Some(limit), None) | (None, Some(limit)) => limit.is_finite(),
+        (None, None) => false,
+      };
+
+      if !valid {
+        return Err(Error::Invalid(format!("the redline of '{text_id}' needs a finite min, max or both, with min less than max")));
+      }
+    }
+
+    // boards would consider the FC lost in between heartbeats otherwise
+    if self.send_heartbeat_rate >= self.time_to_live {
+      return Err(Error::Invalid("send_heartbeat_rate_ms must be less than time_to_live_ms".to_string()));
+    }
+
+    Ok(())
+  }
+}
+
+fn milliseconds<'de, D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Duration, D::Error> {
+  u64::deserialize(deserializer).map(Duration::from_millis)
+}
+
+fn optional_milliseconds<'de, D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Option<Duration>, D::Error> {
+  Option::<u64>::deserialize(deserializer).map(|ms| ms.map(Duration::from_millis))
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug)]
+pub enum Error {
+  MissingValue(String),
+  UnknownFlag(String),
+  InvalidValue(String, String),
+  Read(PathBuf, io::Error),
+  Parse(PathBuf, toml::de::Error),
+  Invalid(String),
+}
+
+impl fmt::Display for Error {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::MissingValue(flag) => write!(f, "The flag '{flag}' requires a value."),
+      Self::UnknownFlag(flag) => write!(f, "'{flag}' is not a recognized flag."),
+      Self::InvalidValue(flag, value) => write!(f, "'{value}' is not a valid value for '{flag}'."),
+      Self::Read(path, e) => write!(f, "Couldn't read the config file at '{}': {e}", path.display()),
+      Self::Parse(path, e) => write!(f, "Couldn't parse the config file at '{}': {e}", path.display()),
+      Self::Invalid(reason) => write!(f, "The configuration is invalid: {reason}."),
+    }
+  }
+}
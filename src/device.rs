@@ -1,34 +1,35 @@
 use core::fmt;
 use std::{collections::HashMap, io, net::{IpAddr, SocketAddr, UdpSocket}, time::{Duration, Instant}};
-use common::comm::{ahrs, bms, flight::{DataMessage, SequenceDomainCommand}, sam::SamControlMessage, CompositeValveState, NodeMapping, Statistics, ValveState, VehicleState};
+use common::comm::{ahrs, bms, flight::{DataMessage, SequenceDomainCommand}, sam::SamControlMessage, CompositeValveState, SensorType, Statistics, ValveState, VehicleState};
 
-use crate::{Ingestible, DECAY, DEVICE_COMMAND_PORT, TIME_TO_LIVE};
-
-pub(crate) type Mappings = Vec<NodeMapping>;
+use crate::{calibration::Calibrations, config::Config, recorder::{Recorder, SamMessage}, valve::ValveEstimators, Ingestible, Mappings, DECAY};
 
 #[derive(Clone)]
-pub(crate) struct Device {
+pub struct Device {
     id: String,
     address: SocketAddr,
     last_recieved: Instant,
     first_heartbeat: bool, //NEW CHANGE
+    command_port: u16,
+    time_to_live: Duration,
+    lost: bool,
 }
 
 impl Device {
-    fn new(id: String, address: SocketAddr) -> Self {
-        Device { id, address, last_recieved: Instant::now(), first_heartbeat: true }
+    fn new(id: String, address: SocketAddr, command_port: u16, time_to_live: Duration, now: Instant) -> Self {
+        Device { id, address, last_recieved: now, first_heartbeat: true, command_port, time_to_live, lost: false }
     }
 
     /// Should be ran whenever data is received from a board to update.
-    pub(crate) fn reset_timer(&mut self) {
-        if self.is_disconnected() {
+    pub fn reset_timer(&mut self, now: Instant) {
+        if self.is_disconnected(now) {
             println!("{} at {} reconnected!", self.address.ip(), self.id);
         }
 
-        self.last_recieved = Instant::now();
+        self.last_recieved = now;
     }
 
-    pub(crate) fn send_heartbeat(&self, socket: &UdpSocket, devices: &Devices, mappings: &Mappings) -> Result<()> {
+    pub fn send_heartbeat(&self, socket: &UdpSocket, devices: &Devices, mappings: &Mappings) -> Result<()> {
         let mut buf: [u8; 1024] = [0; 1024];
         let serialized = postcard::to_slice(&DataMessage::FlightHeartbeat, &mut buf)
             .map_err(|e| Error::SerializationFailed(e))?;
@@ -43,8 +44,10 @@ impl Device {
         Ok(())
     }
 
-    pub(crate) fn is_disconnected(&self) -> bool {
-        Instant::now().duration_since(self.last_recieved) > TIME_TO_LIVE
+    /// Whether the board has been silent for longer than its time to live as
+    /// of `now`.
+    pub fn is_disconnected(&self, now: Instant) -> bool {
+        now.duration_since(self.last_recieved) > self.time_to_live
     }
 
     /// Sends a message on a socket to a board with id `destination`
@@ -67,14 +70,14 @@ impl Device {
     }
 
     /// Sends data to the device via a given socket.
-    pub(crate) fn send(&self, socket: &UdpSocket, buf: &[u8]) -> Result<()> {
-        socket.send_to(buf, (self.address.ip(), DEVICE_COMMAND_PORT)).map_err(|e| Error::TransportFailed(e))?;
+    pub fn send(&self, socket: &UdpSocket, buf: &[u8]) -> Result<()> {
+        socket.send_to(buf, (self.address.ip(), self.command_port)).map_err(|e| Error::TransportFailed(e))?;
         Ok(())
     }
 
-    pub(crate) fn send_sam_prvnt_safe(&self, socket: &UdpSocket, mappings: &Mappings, board_id: &std::string::String, devices: &Devices) {
+    pub fn send_sam_prvnt_safe(&self, socket: &UdpSocket, mappings: &Mappings, board_id: &std::string::String, devices: &Devices) {
         // find prvnt if it exists
-        let Some(prvnt_mapping) = mappings.iter().find(|m| m.text_id == "PRVNT") else {
+        let Some(prvnt_mapping) = mappings.get("PRVNT") else {
               eprintln!("PRVNT not found");
               return
         };
@@ -88,40 +91,63 @@ impl Device {
         }
     }
 
-    pub(crate) fn get_board_id(&self) -> &String {
+    pub fn get_board_id(&self) -> &String {
         &self.id
     }
 
-    pub(crate) fn get_ip(&self) -> IpAddr {
+    pub fn get_ip(&self) -> IpAddr {
         self.address.ip()
     }
 
-    pub(crate) fn set_first_heartbeat_var(&mut self, value: bool) {
+    pub fn set_first_heartbeat_var(&mut self, value: bool) {
         self.first_heartbeat = value;
     }
 }
 
-pub(crate) struct Devices {
+/// A change in whether a board is connected.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Connection {
+    Lost,
+    Restored,
+}
+
+pub struct Devices {
     devices: Vec<Device>,
     state: VehicleState,
     last_updates: HashMap<String, Instant>,
+    identity: String,
+    command_port: u16,
+    time_to_live: Duration,
+    calibrations: Calibrations,
+    valves: ValveEstimators,
 }
 
 impl Devices {
     /// Creates an empty set to hold Devices
-    pub(crate) fn new() -> Self {
-        Devices { devices: Vec::new(), state: VehicleState::new(), last_updates: HashMap::new(), }
+    pub fn new(config: &Config) -> Self {
+        Devices {
+            devices: Vec::new(),
+            state: VehicleState::new(),
+            last_updates: HashMap::new(),
+            identity: config.identity.clone(),
+            command_port: config.device_command_port,
+            time_to_live: config.time_to_live,
+            calibrations: config.calibrations.clone(),
+            valves: ValveEstimators::new(config),
+        }
     }
 
     /// Inserts a device into the set, overwriting an existing device.
     /// Overwriting a device replaces all of its associated data, as if it were
     /// connecting for the first time. Returns a reference to the newly inserted
     /// device and the overwritten device, if it existed.
-    pub(crate) fn register_device(&mut self, id: &String, address: SocketAddr) -> Option<Device> {
-        let device = Device::new(id.clone(), address);
+    pub fn register_device(&mut self, id: &String, address: SocketAddr, now: Instant) -> Option<Device> {
+        let mut device = Device::new(id.clone(), address, self.command_port, self.time_to_live, now);
 
         if let Some(copy) = self.devices.iter_mut().find(|d| d.id == device.id) {
             let old = copy.clone();
+            // a lost board that handshakes again is still reported as restored
+            device.lost = old.lost;
             *copy = device;
             return Some(old);
         } else {
@@ -130,11 +156,28 @@ impl Devices {
         }
     }
 
+    /// Finds the boards that have been lost or restored since the last call,
+    /// as of `now`. A board is lost once it has been silent for longer than
+    /// its time to live, and restored once it sends data again.
+    pub fn check_connections(&mut self, now: Instant) -> Vec<(String, Connection)> {
+        let mut changes = Vec::new();
+
+        for device in &mut self.devices {
+            let disconnected = device.is_disconnected(now);
+
+            if disconnected != device.lost {
+                device.lost = disconnected;
+                let change = if disconnected { Connection::Lost } else { Connection::Restored };
+                changes.push((device.id.clone(), change));
+            }
+        }
+
+        changes
+    }
+
     /// should be ran whenever data is sent
     /// TODO: INTEGRATE THIS WITH THE MAIN DATA
-    pub(crate) fn update_last_updates(&mut self) {
-        let now = Instant::now();
-
+    pub fn update_last_updates(&mut self, now: Instant) {
         for (name, stats) in &mut self.state.rolling {
             if !self.last_updates.contains_key(name.as_str()) {
                 continue;
@@ -149,7 +192,7 @@ impl Devices {
     }
 
     /// Updates the VehicleState struct with the newly recieved board telemetry
-    pub(crate) fn update_state(&mut self, telemetry: Vec<(SocketAddr, DataMessage)>, mappings: &Mappings, socket: &UdpSocket) {
+    pub fn update_state(&mut self, telemetry: Vec<(SocketAddr, DataMessage)>, mappings: &Mappings, socket: &UdpSocket, now: Instant) {
         for (address, message) in telemetry {
             match message {
                 DataMessage::FlightHeartbeat => continue,
@@ -162,12 +205,11 @@ impl Devices {
                     };
 
                     // TODO: Comment out moving averages
-                    let now = Instant::now();
                     let mut delta_time = Duration::new(0, 0);
 
                     match self.last_updates.get_mut(id) {
                         Some(last_update) => {
-                            delta_time = now - *last_update;
+                            delta_time = now.saturating_duration_since(*last_update);
                             *last_update = now;
                         }
                         None => { self.last_updates.insert(id.clone(), now); }
@@ -190,14 +232,14 @@ impl Devices {
                         }
                     }
 
-                    device.reset_timer();
+                    device.reset_timer(now);
                 },
                 DataMessage::Identity(ref id) => {
-                    if let Err(e) = handshake(&address, socket) {
+                    if let Err(e) = handshake(&address, socket, &self.identity) {
                         println!("Connection with {id} couldn't be established: {e}");
                     } else {
                         println!("Connection established with {id}.");
-                        if let Some(old_device) = self.register_device(id, address) {
+                        if let Some(old_device) = self.register_device(id, address, now) {
                             println!("Overwrote data of previously registered {id} at {}", old_device.address.ip());
                         }
                     }
@@ -206,7 +248,7 @@ impl Devices {
                 }
             }
             
-            message.ingest(&mut self.state, mappings);
+            message.ingest(&mut self.state, mappings, &self.calibrations, &mut self.valves, now);
         }
     }
 
@@ -229,15 +271,18 @@ impl Devices {
         return Ok(())
     }
 
-    ///
-    pub(crate) fn send_sam_commands(&mut self, socket: &UdpSocket, mappings: &Mappings, commands: Vec<SequenceDomainCommand>) -> bool {
+    /// Sends the valve commands to the SAMs that the valves are mapped to,
+    /// recording every message sent, and returns whether an abort was
+    /// requested.
+    pub fn send_sam_commands(&mut self, socket: &UdpSocket, mappings: &Mappings, commands: Vec<SequenceDomainCommand>, recorder: &mut Recorder) -> bool {
         let mut should_abort = false;
         
         for command in commands {
             match command {
                 SequenceDomainCommand::ActuateValve { valve, state } => {
-                    let Some(mapping) = mappings.iter().find(|m| m.text_id == valve) else {
+                    let Some(mapping) = mappings.get(&valve) else {
                         eprintln!("Failed to actuate valve: mapping '{valve}' is not defined.");
+                        recorder.unmapped_sam_command(&SequenceDomainCommand::ActuateValve { valve, state });
                         continue;
                     };
     
@@ -245,23 +290,20 @@ impl Devices {
                     let normally_closed = mapping.normally_closed.unwrap_or(true);
                     let powered = closed != normally_closed;
 
-                    if let Some(existing) = self.state.valve_states.get_mut(&valve) {
-                        existing.commanded = state;
-                    } else {
-                        self.state.valve_states.insert(
-                            valve,
-                            CompositeValveState {
-                                commanded: state,
-                                actual: ValveState::Undetermined
-                            }
-                        );
-                    }
+                    self.set_commanded_state(valve, state);
 
                     let command = SamControlMessage::ActuateValve { channel: mapping.channel, powered };
+                    let result = self.serialize_and_send(socket, &mapping.board_id, &command);
 
-                    if let Err(msg) = self.serialize_and_send(socket, &mapping.board_id, &command) {
+                    if let Err(msg) = &result {
                         println!("{}", msg);
                     }
+
+                    recorder.sam_message(SamMessage {
+                        board_id: mapping.board_id.clone(),
+                        message: command,
+                        error: result.err(),
+                    });
                 }
                 SequenceDomainCommand::Abort => should_abort = true,
             }
@@ -270,7 +312,22 @@ impl Devices {
         should_abort
     }
 
-    pub(crate) fn send_sam_clear_prvnt_channel(&self, socket: &UdpSocket, mappings: &Mappings) {
+    /// Updates the commanded state of a valve in the VehicleState.
+    pub fn set_commanded_state(&mut self, valve: String, state: ValveState) {
+        if let Some(existing) = self.state.valve_states.get_mut(&valve) {
+            existing.commanded = state;
+        } else {
+            self.state.valve_states.insert(
+                valve,
+                CompositeValveState {
+                    commanded: state,
+                    actual: ValveState::Undetermined
+                }
+            );
+        }
+    }
+
+    pub fn send_sam_clear_prvnt_channel(&self, socket: &UdpSocket, mappings: &Mappings) {
         for device in self.devices.iter() {
             if device.get_board_id().starts_with("sam") {
                 let command = SamControlMessage::ClearPRVNTMsg { };
@@ -283,19 +340,53 @@ impl Devices {
         }
     }
 
-    // send SafeValves messages to sams
-    pub(crate) fn send_sam_safe_valves(&self, socket: &UdpSocket) {
+    /// Sends SafeValves messages to every SAM, recording each one, and
+    /// returns the IDs of the SAMs that it was sent to.
+    pub fn send_sam_safe_valves(&self, socket: &UdpSocket, recorder: &mut Recorder) -> Vec<String> {
+        let mut safed = Vec::new();
+
         for device in self.devices.iter() {
             if device.get_board_id().starts_with("sam") {
                 let command = SamControlMessage::SafeValves { };
-                if let Err(msg) = self.serialize_and_send(socket, device.get_board_id(), &command) {
+                let result = self.serialize_and_send(socket, device.get_board_id(), &command);
+
+                if let Err(msg) = &result {
                         println!("{}", msg);
+                } else {
+                    safed.push(device.get_board_id().to_string());
                 }
+
+                recorder.sam_message(SamMessage {
+                    board_id: device.get_board_id().to_string(),
+                    message: command,
+                    error: result.err(),
+                });
             }
         }
+
+        safed
+    }
+
+    /// Marks every valve on the given SAMs as commanded to its unpowered state,
+    /// which is where safing the SAMs leaves them, so that they aren't flagged
+    /// as mismatched afterwards. Returns the names of the valves marked.
+    pub fn set_safed(&mut self, board_ids: &[String], mappings: &Mappings) -> Vec<String> {
+        let mut safed = Vec::new();
+
+        for mapping in mappings {
+            if !matches!(mapping.sensor_type, SensorType::Valve) || !board_ids.contains(&mapping.board_id) {
+                continue;
+            }
+
+            let state = if mapping.normally_closed.unwrap_or(true) { ValveState::Closed } else { ValveState::Open };
+            self.set_commanded_state(mapping.text_id.clone(), state);
+            safed.push(mapping.text_id.clone());
+        }
+
+        safed
     }
 
-    pub(crate) fn send_bms_command(&self, socket: &UdpSocket, command: bms::Command) {
+    pub fn send_bms_command(&self, socket: &UdpSocket, command: bms::Command) {
         let Some(bms) = self.devices.iter().find(|d| d.id.starts_with("bms")) else {
             println!("Couldn't send a BMS command as BMS isn't connected.");
             return;
@@ -306,7 +397,7 @@ impl Devices {
         }
     }
 
-    pub(crate) fn send_ahrs_command(&self, socket: &UdpSocket, command: ahrs::Command) {
+    pub fn send_ahrs_command(&self, socket: &UdpSocket, command: ahrs::Command) {
         let Some(ahrs) = self.devices.iter().find(|d| d.id.starts_with("ahrs")) else {
             println!("Couldn't send an AHRS command as AHRS isn't connected.");
             return;
@@ -317,30 +408,30 @@ impl Devices {
         }
     }
 
-    pub(crate) fn get_state(&self) -> &VehicleState {
+    pub fn get_state(&self) -> &VehicleState {
         return &self.state;
     }
     
-    pub(crate) fn iter_mut(&mut self) -> ::core::slice::IterMut<'_, Device> {
+    pub fn iter_mut(&mut self) -> ::core::slice::IterMut<'_, Device> {
         self.devices.iter_mut()
     }
 
-    pub(crate) fn iter(&self) -> ::core::slice::Iter<'_, Device> {
+    pub fn iter(&self) -> ::core::slice::Iter<'_, Device> {
         self.devices.iter()
     }
 }
 
 /// performs a flight handshake with the board.
-pub(crate) fn handshake(address: &SocketAddr, socket: &UdpSocket) -> Result<()> {
+pub fn handshake(address: &SocketAddr, socket: &UdpSocket, identity: &str) -> Result<()> {
     let mut buf: [u8; 1024] = [0; 1024];
-    let serialized = postcard::to_slice(&DataMessage::Identity("flight-01".to_string()), &mut buf)
+    let serialized = postcard::to_slice(&DataMessage::Identity(identity.to_string()), &mut buf)
         .map_err(|e| Error::SerializationFailed(e))?;
     socket.send_to(serialized, address).map_err(|e| Error::TransportFailed(e))?;
     Ok(())
 }
 
 /// Gets the most recent UDP Commands
-pub(crate) fn receive(socket: &UdpSocket) -> Vec<(SocketAddr, DataMessage)> {
+pub fn receive(socket: &UdpSocket) -> Vec<(SocketAddr, DataMessage)> {
     let mut messages = Vec::new();
     let mut buf: [u8; 1024] = [0; 1024];
     
@@ -369,7 +460,7 @@ pub(crate) fn receive(socket: &UdpSocket) -> Vec<(SocketAddr, DataMessage)> {
 }
 
 type Result<T> = ::std::result::Result<T, Error>;
-pub(crate) enum Error {
+pub enum Error {
     SerializationFailed(postcard::Error),
     TransportFailed(io::Error),
 }
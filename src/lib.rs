@@ -0,0 +1,58 @@
+pub mod calibration;
+pub mod clock;
+pub mod computer;
+pub mod config;
+pub mod device;
+pub mod mappings;
+pub mod ownership;
+pub mod reactor;
+pub mod redline;
+pub mod recorder;
+pub mod servo;
+pub mod state;
+pub mod status;
+pub mod store;
+pub mod sequence;
+pub mod shutdown;
+pub mod simulator;
+pub mod trigger;
+pub mod valve;
+pub mod watchdog;
+
+use std::time::Duration;
+use crate::{mappings::Mappings, state::Ingestible};
+
+/// How quickly a sequence must read from the shared VehicleState before the
+/// data becomes corrupted.
+const MMAP_GRACE_PERIOD: Duration = Duration::from_millis(20);
+
+/// The TCP timeout for each attempt at connecting to servo. Attempts run in
+/// the background, so this doesn't delay the control cycle.
+const SERVO_CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How long to wait before retrying after the first failed attempt at
+/// connecting to servo. The wait doubles after each consecutive failure.
+const SERVO_INITIAL_BACKOFF: Duration = Duration::from_millis(250);
+
+/// The longest wait in between attempts at connecting to servo.
+const SERVO_MAX_BACKOFF: Duration = Duration::from_secs(8);
+
+/// How long the connection with servo may go without traffic before the kernel
+/// starts probing it with TCP keepalives.
+const SERVO_KEEPALIVE_IDLE: Duration = Duration::from_secs(1);
+
+/// How long to wait between keepalive probes.
+const SERVO_KEEPALIVE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How many keepalive probes may go unanswered before the connection with
+/// servo is dropped. Along with the idle time and interval, a servo that
+/// disappears without closing the connection is noticed within 4 seconds.
+const SERVO_KEEPALIVE_PROBES: u32 = 3;
+
+/// The longest the FC sleeps in between control cycles, which bounds how long
+/// a shutdown request or a lazily checked interval, such as the recorder's,
+/// can go unnoticed.
+const MAX_WAIT: Duration = Duration::from_millis(100);
+
+/// How often the refresh rate data decays over time.
+const DECAY: f64 = 0.9;
@@ -1,54 +1,23 @@
-mod device;
-mod servo;
-mod state;
-mod sequence;
-
-// TODO: Make it so you enter servo's socket address.
-// TODO: Clean up domain socket on exit.
-use std::{collections::HashMap, env, net::{SocketAddr, TcpStream, UdpSocket}, os::unix::net::UnixDatagram, process::Command, thread, time::{Duration, Instant}};
-use common::{comm::{FlightControlMessage, Sequence}, sequence::{MMAP_PATH, SOCKET_PATH}};
-use crate::{device::Devices, servo::ServoError, sequence::Sequences, state::Ingestible, device::Mappings};
+use std::{env, net::UdpSocket, os::unix::net::UnixDatagram, process::{Command, ExitCode}, thread, time::Duration};
+use common::sequence::{MMAP_PATH, SOCKET_PATH};
+use flight_computer::{clock::SystemClock, computer::FlightComputer, config::Config, shutdown::{self, Shutdown}};
 use mmap_sync::synchronizer::Synchronizer;
 
-const SERVO_SOCKET_ADDRESSES: [(&str, u16); 4] = [
-  ("192.168.1.10", 5025),
-  ("server-01.local", 5025),
-  ("server-02.local", 5025),
-  ("localhost", 5025),
-];
-const FC_SOCKET_ADDRESS: (&str, u16) = ("0.0.0.0", 4573);
-const DEVICE_COMMAND_PORT: u16 = 8378;
-const SERVO_DATA_PORT: u16 = 7201;
-
-/// How quickly a sequence must read from the shared VehicleState before the
-/// data becomes corrupted.
-const MMAP_GRACE_PERIOD: Duration = Duration::from_millis(20);
-
-/// How long from the last received message before a board is considered
-/// disconnected.
-const TIME_TO_LIVE: Duration = Duration::from_millis(350);
-
-/// How many times a reconnect will be tried with a disconnected servo.
-const SERVO_RECONNECT_RETRY_COUNT: u8 = 1;
-
-/// The TCP timeout for re-establishing connection with a disconnected servo.
-const SERVO_RECONNECT_TIMEOUT: Duration = Duration::from_millis(50);
-
-/// How often the refresh rate data decays over time.
-const DECAY: f64 = 0.9;
 
-/// How often we want to update servo
-const FC_TO_SERVO_RATE: Duration = Duration::from_millis(10);
-
-/// How often we want to send hearbeats
-const SEND_HEARTBEAT_RATE: Duration = Duration::from_millis(50);
-
-/// If we do not hear from servo for this amount of time, we abort
-const SERVO_TO_FC_TIME_TO_LIVE: Duration = Duration::from_secs(60 * 10); // times 10 for 10 minutes
+fn main() -> ExitCode {
+  let config = match Config::from_args() {
+    Ok(c) => c,
+    Err(e) => {
+      eprintln!("{e}");
+      return ExitCode::FAILURE;
+    },
+  };
 
+  let shutdown = Shutdown::register().expect("Couldn't register the SIGINT and SIGTERM handlers.");
 
-fn main() -> ! {
-  Command::new("rm").arg(SOCKET_PATH).output().unwrap();
+  if let Err(e) = shutdown::remove_stale_socket() {
+    panic!("Couldn't remove the stale sequence command socket at '{SOCKET_PATH}': {e}");
+  }
 
   // Checks if all the python dependencies are in order.
   if let Err(missing) = check_python_dependencies(&["common"]) {
@@ -62,16 +31,12 @@ fn main() -> ! {
     panic!("{}", error_message);
   }
 
-  let socket: UdpSocket = UdpSocket::bind(FC_SOCKET_ADDRESS).expect(&format!("Couldn't open port {} on IP address {}", FC_SOCKET_ADDRESS.1, FC_SOCKET_ADDRESS.0));
+  let socket: UdpSocket = UdpSocket::bind(config.fc_address).expect(&format!("Couldn't open port {} on IP address {}", config.fc_address.port(), config.fc_address.ip()));
   socket.set_nonblocking(true).expect("Cannot set incoming to non-blocking.");
   let command_socket: UnixDatagram = UnixDatagram::bind(SOCKET_PATH).expect(&format!("Could not open sequence command socket on path '{SOCKET_PATH}'."));
   command_socket.set_nonblocking(true).expect("Cannot set sequence command socket to non-blocking.");
 
-  let mut mappings: Mappings = Vec::new();
-  let mut devices: Devices = Devices::new();
-  let mut sequences: Sequences = HashMap::new();
-  let mut synchronizer: Synchronizer = Synchronizer::new(MMAP_PATH.as_ref());
-  let mut abort_sequence: Option<Sequence> = None;
+  let synchronizer: Synchronizer = Synchronizer::new(MMAP_PATH.as_ref());
   
   println!("Flight Computer running on version {}\n", env!("CARGO_PKG_VERSION"));
   println!("!!!! ATTENTION !!! ATTENTION !!!!");
@@ -82,185 +47,15 @@ fn main() -> ! {
   thread::sleep(Duration::from_secs(5));
   println!("\nStarting...\n");
 
-  let mut last_received_from_servo = Instant::now(); // last time that we had an established connection with servo
-  let (mut servo_stream, mut servo_address)= loop {
-    match servo::establish(&SERVO_SOCKET_ADDRESSES, None, 3, Duration::from_secs(2)) {
-      Ok(s) => {
-        println!("Connected to servo successfully. Beginning control cycle...\n");
-        last_received_from_servo = Instant::now();
-        break s;
-      },
-      Err(e) => {
-        println!("Couldn't connect due to error: {e}\n");
-        thread::sleep(Duration::from_secs(2));
-      },
-    }
-  };
-  
-  let mut last_sent_to_servo = Instant::now(); // for sending messages to servo
-  let mut last_heartbeat_sent = Instant::now(); // for sending messages to boards
-  let mut aborted = false;
-  let mut mapping_has_prvnt = false;
-  let mut sent_prvnt_sam_msg = false;
-  loop {
-    let servo_message = get_servo_data(&mut servo_stream, &mut servo_address, &mut last_received_from_servo, &mut aborted);
-
-    // if we haven't heard from servo in over 10 minutes, abort.
-    if (!aborted) && (Instant::now().duration_since(last_received_from_servo) > SERVO_TO_FC_TIME_TO_LIVE) {
-      aborted = true;
-      devices.send_sam_safe_valves(&socket);
-    }
-
-    // decoding servo message, if it was received
-    if let Some(command) = servo_message {
-      println!("Recieved a FlightControlMessage: {command:#?}");
-
-      match command {
-        FlightControlMessage::Abort => abort(&mappings, &mut sequences, &abort_sequence),
-        FlightControlMessage::AhrsCommand(c) => devices.send_ahrs_command(&socket, c),
-        FlightControlMessage::BmsCommand(c) => devices.send_bms_command(&socket, c),
-        FlightControlMessage::Trigger(_) => todo!(),
-        FlightControlMessage::Mappings(m) => {
-          mappings = m;
-          mapping_has_prvnt = mappings.iter().any(|m| m.text_id == "PRVNT");
-          sent_prvnt_sam_msg = false;
-          // send clear message to sams. this is needed in case we move PRVNT to a different sam on the new mappings
-          // as the old mapped sam will still think it has prvnt. we also need to sent the prvnt msg to the newly mapped
-          // prvnt sam (if it exists)
-          // still need to figure out when to send messages when devices connect
-          devices.send_sam_clear_prvnt_channel(&socket, &mappings);
-          // need to send prvnt mapping to sam board again if mappings change while everything is up
-        },
-        FlightControlMessage::Sequence(s) if s.name == "abort" => abort_sequence = Some(s),
-        FlightControlMessage::Sequence(ref s) => sequence::execute(&mappings, s, &mut sequences),
-        FlightControlMessage::StopSequence(n) => {
-          if let Err(e) = sequence::kill(&mut sequences, &n) {
-            eprintln!("There was an issue in stopping sequence '{n}': {e}");
-          }
-        },
-      };
-    }
-
-    // updates records
-    devices.update_last_updates();
-
-    if Instant::now().duration_since(last_sent_to_servo) > FC_TO_SERVO_RATE {
-      // send servo the current vehicle telemetry
-      if let Err(e) = servo::push(&socket, servo_address, devices.get_state()) {
-        eprintln!("Issue in sending servo the vehicle telemetry: {e}");
-      }
-      last_sent_to_servo = Instant::now();
-    }
-
-    // receive telemetry
-    let telemetry = device::receive(&socket);
+  let mut flight_computer = FlightComputer::new(config, SystemClock, socket, command_socket, synchronizer)
+    .expect("Couldn't start waiting for incoming messages.");
 
-    // process telemetry from boards
-    devices.update_state(telemetry, &mappings, &socket);
-
-    // updates all running sequences with the newest received data
-    if let Err(e) = state::sync_sequences(&mut synchronizer, devices.get_state()) {
-      println!("There was an error in synchronizing vehicle state: {e}");
-    }
-
-    let need_to_send_heartbeat = Instant::now().duration_since(last_heartbeat_sent) > SEND_HEARTBEAT_RATE;
-    // Update board lifetimes and send heartbeats to connected boards.
-    for device in devices.iter() {
-      if device.is_disconnected() {
-        continue;
-      }
-
-      if need_to_send_heartbeat {
-        if let Err(e) = device.send_heartbeat(&socket, &devices, &mappings) {
-          println!(
-            "There was an error in notifying board {} at IP {} that the FC is still connected: {e}", 
-            device.get_board_id(),
-            device.get_ip()
-          );
-        }
-        last_heartbeat_sent = Instant::now();
-      }
-    }
-
-    for device in devices.iter_mut() {
-      device.set_first_heartbeat_var(false);
-    }
-
-    // sequences and triggers
-    let sam_commands = sequence::pull_commands(&command_socket);
-    let should_abort = devices.send_sam_commands(&socket, &mappings, sam_commands);
-
-    if should_abort {
-      abort(&mappings, &mut sequences, &abort_sequence);
-    }
-
-    // triggers
-  }
-}
-
-fn abort(mappings: &Mappings, sequences: &mut Sequences, abort_sequence: &Option<Sequence>) {
-  if let Some(ref sequence) = abort_sequence {
-    for (_, sequence) in &mut *sequences {
-      if let Err(e) = sequence.kill() {
-        println!("Couldn't kill a sequence in preperation for abort, continuing normally: {e}");
-      }
-    }
-
-    sequence::execute(&mappings, sequence, sequences);
-  } else {
-    println!("Received an abort command, but no abort sequence has been set. Continuing normally...");
+  while !shutdown.is_requested() {
+    flight_computer.tick();
+    flight_computer.wait();
   }
-}
-
 
-/// Pulls data from Servo, if available.
-/// # Error Handling
-/// 
-/// ## FC-Servo Connection Dropped
-/// If the connection between the FC and Servo was severed, the connection
-/// will tried to be re-established. If a new connection is successfully
-/// established, servo_stream and servo_address will be set to mirror the
-/// change. Otherwise, a notification will be printed to the terminal and None
-/// will be returned.
-/// 
-/// ## Servo Message Deserialization Fails
-/// If postcard returns an error during message deserialization, None will be
-/// returned.
-/// 
-/// ## Transport Layer failed
-/// If reading from servo_stream is not possible, None will be returned.
-fn get_servo_data(servo_stream: &mut TcpStream, servo_address: &mut SocketAddr, last_received_from_servo: &mut Instant, aborted: &mut bool) -> Option<FlightControlMessage> {
-  match servo::pull(servo_stream) {
-    Ok(message) => {
-      *last_received_from_servo = Instant::now();
-      message
-    },
-    Err(e) => {
-      eprintln!("Issue in pulling data from Servo: {e}");
-
-      match e {
-        ServoError::ServoDisconnected => {
-          eprintln!("Attempting to reconnect to servo... ");
-
-          match servo::establish(&SERVO_SOCKET_ADDRESSES, Some(servo_address), SERVO_RECONNECT_RETRY_COUNT, SERVO_RECONNECT_TIMEOUT) {
-            Ok(s) => {
-              (*servo_stream, *servo_address) = s;
-              *last_received_from_servo = Instant::now();
-              *aborted = false;
-              eprintln!("Connection successfully re-established.");
-            },
-            Err(e) => {
-              eprintln!("Connection could not be re-established: {e}. Continuing...")
-            },
-          };
-        },
-        ServoError::DeserializationFailed(_) => {},
-        ServoError::TransportFailed(_) => {},
-      };
-    
-      None
-    }
-  }
+  flight_computer.stop()
 }
 
 /// Checks if python3 and the passed python modules exist.
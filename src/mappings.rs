@@ -0,0 +1,99 @@
+use std::{collections::HashMap, mem::{self, Discriminant}, ops::Deref, slice};
+use common::comm::{sam::ChannelType, NodeMapping};
+
+/// Identifies a channel on a single board.
+type ChannelKey = (u32, Discriminant<ChannelType>);
+
+/// The mappings most recently sent by Servo, indexed so that the mapping of a
+/// SAM data point or of a valve can be found without scanning every mapping.
+///
+/// The indices are built once when the mappings arrive, as lookups happen for
+/// every data point received while the mappings rarely change. Derefs to the
+/// underlying slice for everything else.
+///
+/// A text ID that's defined more than once is found by the first definition,
+/// and is listed by `duplicates` so that it can be reported.
+#[derive(Clone, Debug, Default)]
+pub struct Mappings {
+  mappings: Vec<NodeMapping>,
+  by_channel: HashMap<String, HashMap<ChannelKey, Vec<usize>>>,
+  by_text_id: HashMap<String, usize>,
+  duplicates: Vec<String>,
+}
+
+impl Mappings {
+  pub fn new(mappings: Vec<NodeMapping>) -> Self {
+    let mut by_channel: HashMap<String, HashMap<ChannelKey, Vec<usize>>> = HashMap::new();
+    let mut by_text_id = HashMap::new();
+    let mut duplicates = Vec::new();
+
+    for (i, mapping) in mappings.iter().enumerate() {
+      let channels = by_channel.entry(mapping.board_id.clone()).or_default();
+
+      for channel_type in mapping.sensor_type.channel_types().iter() {
+        channels
+          .entry((mapping.channel, mem::discriminant(channel_type)))
+          .or_default()
+          .push(i);
+      }
+
+      // the first mapping wins, as a linear search would have found it first
+      if !by_text_id.contains_key(&mapping.text_id) {
+        by_text_id.insert(mapping.text_id.clone(), i);
+      } else if !duplicates.contains(&mapping.text_id) {
+        duplicates.push(mapping.text_id.clone());
+      }
+    }
+
+    Mappings { mappings, by_channel, by_text_id, duplicates }
+  }
+
+  /// Every text ID that's defined more than once, in the order they were
+  /// first repeated.
+  pub fn duplicates(&self) -> &[String] {
+    &self.duplicates
+  }
+
+  /// Finds the mapping with the given text ID.
+  pub fn get(&self, text_id: &str) -> Option<&NodeMapping> {
+    self.by_text_id.get(text_id).map(|&i| &self.mappings[i])
+  }
+
+  /// Finds every mapping that a data point from the given board, channel and
+  /// channel type corresponds to, in the order they were sent.
+  pub fn for_channel<'a>(&'a self, board_id: &str, channel: u32, channel_type: &ChannelType) -> impl Iterator<Item = &'a NodeMapping> + 'a {
+    self.by_channel
+      .get(board_id)
+      .and_then(|channels| channels.get(&(channel, mem::discriminant(channel_type))))
+      .into_iter()
+      .flatten()
+      .map(|&i| &self.mappings[i])
+  }
+
+  pub fn into_inner(self) -> Vec<NodeMapping> {
+    self.mappings
+  }
+}
+
+impl From<Vec<NodeMapping>> for Mappings {
+  fn from(mappings: Vec<NodeMapping>) -> Self {
+    Self::new(mappings)
+  }
+}
+
+impl Deref for Mappings {
+  type Target = [NodeMapping];
+
+  fn deref(&self) -> &Self::Target {
+    &self.mappings
+  }
+}
+
+impl<'a> IntoIterator for &'a Mappings {
+  type Item = &'a NodeMapping;
+  type IntoIter = slice::Iter<'a, NodeMapping>;
+
+  fn into_iter(self) -> Self::IntoIter {
+    self.mappings.iter()
+  }
+}
@@ -0,0 +1,90 @@
+use std::collections::HashMap;
+use common::comm::flight::SequenceDomainCommand;
+use crate::{config::{Config, ValveConflictAction}, sequence, status::StatusMessage};
+
+/// Tracks which sequence owns each valve, so that concurrent sequences can't
+/// silently fight over the same valve.
+///
+/// A sequence takes ownership of a valve by actuating it while nobody owns
+/// it, and releases all of its valves once it stops. Actuating a valve owned
+/// by another sequence is a conflict, which either hands the valve over or is
+/// rejected depending on `on_valve_conflict`. The abort sequence is always
+/// allowed to take a valve over, and commands from a process that isn't a
+/// known sequence never take ownership.
+pub struct ValveOwners {
+  on_conflict: ValveConflictAction,
+  owners: HashMap<String, String>,
+}
+
+impl ValveOwners {
+  pub fn new(config: &Config) -> Self {
+    ValveOwners {
+      on_conflict: config.on_valve_conflict,
+      owners: HashMap::new(),
+    }
+  }
+
+  /// Checks a command from the sequence named `sender`, or from an unknown
+  /// process if `None`, adding a report to `reports` for every conflict and
+  /// change of ownership. Returns whether the command should be carried out.
+  pub fn permit(&mut self, command: &SequenceDomainCommand, sender: Option<&str>, reports: &mut Vec<StatusMessage>) -> bool {
+    let SequenceDomainCommand::ActuateValve { valve, .. } = command else {
+      return true;
+    };
+
+    let owner = self.owners.get(valve);
+
+    if let Some(owner) = owner {
+      if Some(owner.as_str()) == sender {
+        return true;
+      }
+
+      let rejected = self.on_conflict == ValveConflictAction::Reject && sender != Some(sequence::ABORT);
+
+      reports.push(StatusMessage::ValveConflict {
+        valve: valve.clone(),
+        owner: owner.clone(),
+        sender: sender.map(str::to_string),
+        rejected,
+      });
+
+      if rejected {
+        return false;
+      }
+    }
+
+    if let Some(sender) = sender {
+      let previous = self.owners.insert(valve.clone(), sender.to_string());
+
+      reports.push(StatusMessage::ValveOwnerChanged {
+        valve: valve.clone(),
+        owner: Some(sender.to_string()),
+        previous,
+      });
+    }
+
+    true
+  }
+
+  /// Releases every valve owned by a sequence that has stopped.
+  pub fn release(&mut self, name: &str, reports: &mut Vec<StatusMessage>) {
+    self.owners.retain(|valve, owner| {
+      if owner != name {
+        return true;
+      }
+
+      reports.push(StatusMessage::ValveOwnerChanged {
+        valve: valve.clone(),
+        owner: None,
+        previous: Some(owner.clone()),
+      });
+
+      false
+    });
+  }
+
+  /// The name of the sequence that owns a valve, if any.
+  pub fn owner(&self, valve: &str) -> Option<&str> {
+    self.owners.get(valve).map(String::as_str)
+  }
+}
@@ -0,0 +1,87 @@
+//! Puts the flight computer to sleep until there is something to do.
+//!
+//! The boards' socket, the sequence command socket and the Servo stream are
+//! all non-blocking and are read until they would block on every tick, so the
+//! reactor only needs to know when any of them becomes readable again. mio's
+//! registrations are edge-triggered, which is why data must never be left
+//! behind in a socket without also asking for a zero timeout.
+
+use std::{io, net::{TcpStream, UdpSocket}, os::{fd::{AsRawFd, RawFd}, unix::net::UnixDatagram}, sync::Arc, time::Duration};
+use mio::{unix::SourceFd, Events, Interest, Poll, Token, Waker};
+
+const BOARDS: Token = Token(0);
+const SEQUENCES: Token = Token(1);
+const SERVO: Token = Token(2);
+const WAKER: Token = Token(3);
+
+pub struct Reactor {
+  poll: Poll,
+  events: Events,
+  waker: Arc<Waker>,
+  servo: Option<RawFd>,
+}
+
+impl Reactor {
+  /// Starts watching the boards' socket and the sequence command socket.
+  pub fn new(socket: &UdpSocket, command_socket: &UnixDatagram) -> io::Result<Self> {
+    let poll = Poll::new()?;
+    let registry = poll.registry();
+
+    registry.register(&mut SourceFd(&socket.as_raw_fd()), BOARDS, Interest::READABLE)?;
+    registry.register(&mut SourceFd(&command_socket.as_raw_fd()), SEQUENCES, Interest::READABLE)?;
+    let waker = Arc::new(Waker::new(registry, WAKER)?);
+
+    Ok(Reactor {
+      poll,
+      events: Events::with_capacity(16),
+      waker,
+      servo: None,
+    })
+  }
+
+  /// Used to wake the reactor from another thread, such as when a connection
+  /// attempt with Servo finishes.
+  pub fn waker(&self) -> Arc<Waker> {
+    Arc::clone(&self.waker)
+  }
+
+  /// Watches the stream that Servo is currently connected through, if any.
+  /// Must be called whenever the stream is replaced.
+  pub fn watch_servo(&mut self, stream: Option<&TcpStream>) -> io::Result<()> {
+    let fd = stream.map(|s| s.as_raw_fd());
+
+    if fd == self.servo {
+      return Ok(());
+    }
+
+    // a closed stream is removed from the epoll set by the kernel, and its
+    // descriptor may already belong to a new socket, so it's never deregistered
+    // explicitly
+    self.servo = None;
+
+    if let Some(fd) = fd {
+      let registry = self.poll.registry();
+
+      match registry.register(&mut SourceFd(&fd), SERVO, Interest::READABLE) {
+        Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => {
+          registry.reregister(&mut SourceFd(&fd), SERVO, Interest::READABLE)?;
+        },
+        result => result?,
+      };
+
+      self.servo = Some(fd);
+    }
+
+    Ok(())
+  }
+
+  /// Blocks until a watched socket becomes readable, the reactor is woken or
+  /// the timeout passes, whichever happens first.
+  pub fn wait(&mut self, timeout: Duration) -> io::Result<()> {
+    match self.poll.poll(&mut self.events, Some(timeout)) {
+      // a signal arrived, which the caller checks for on its own
+      Err(ref e) if e.kind() == io::ErrorKind::Interrupted => Ok(()),
+      result => result,
+    }
+  }
+}
@@ -0,0 +1,453 @@
+use std::{borrow::Cow, fmt, fs::{self, File}, io::{self, BufReader, BufWriter, Read, Write}, net::SocketAddr, path::{Path, PathBuf}, sync::mpsc::{self, Receiver, SyncSender, TrySendError}, thread, time::{Duration, Instant, SystemTime, UNIX_EPOCH}};
+use common::comm::{flight::{DataMessage, SequenceDomainCommand}, sam::SamControlMessage, FlightControlMessage, VehicleState};
+use serde::{Deserialize, Serialize};
+use crate::{config::RecorderConfig, sequence::OutputLine, status::StatusMessage};
+
+/// Written at the start of every recording so that readers can tell which
+/// version of the format a file uses.
+pub const MAGIC: [u8; 8] = *b"FCREC\0\0\x03";
+
+/// Every version of the format that can be read. Records have only been
+/// appended since the first version, so older files are still understood.
+const READABLE: [[u8; 8]; 3] = [*b"FCREC\0\0\x01", *b"FCREC\0\0\x02", MAGIC];
+
+/// The extension of recording files within the recording directory.
+const EXTENSION: &str = "rec";
+
+/// How many syncs may wait on the sync thread before more are skipped, which
+/// keeps a stalled disk from building up an unbounded backlog.
+const SYNC_QUEUE_SIZE: usize = 2;
+
+/// The largest payload that a reader accepts, which keeps a corrupted length
+/// from causing a huge allocation.
+const MAX_PAYLOAD_SIZE: usize = 16 * 1024 * 1024;
+
+/// Everything that the flight computer records.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum Record<'a> {
+  /// A message received from a board.
+  Telemetry(SocketAddr, Cow<'a, DataMessage<'a>>),
+
+  /// A message received from Servo.
+  Servo(Cow<'a, FlightControlMessage>),
+
+  /// A command received from a running sequence.
+  SequenceCommand(Cow<'a, SequenceDomainCommand>),
+
+  /// A snapshot of the vehicle state as computed by the FC.
+  State(Cow<'a, VehicleState>),
+
+  /// A status report raised by the FC.
+  Status(Cow<'a, StatusMessage>),
+
+  /// A line written by a running sequence.
+  SequenceOutput(Cow<'a, OutputLine>),
+
+  /// A command received from a sequence, along with who sent it. Supersedes
+  /// `SequenceCommand`, which is only found in older recordings.
+  AttributedSequenceCommand(Cow<'a, AttributedCommand>),
+
+  /// A message sent, or that failed to be sent, to a SAM.
+  SamMessage(Cow<'a, SamMessage>),
+
+  /// A valve command that wasn't sent to any SAM, as the valve isn't mapped.
+  UnmappedSamCommand(Cow<'a, SequenceDomainCommand>),
+}
+
+/// A command along with the sequence that sent it and whether the FC carried
+/// it out, as it may be rate limited or conflict with another sequence.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct AttributedCommand {
+  /// `None` if the sender isn't a known sequence.
+  pub sequence: Option<String>,
+
+  /// `None` if the kernel didn't attach the sender's credentials.
+  pub pid: Option<u32>,
+  pub carried_out: bool,
+  pub command: SequenceDomainCommand,
+}
+
+/// A message addressed to a SAM along with whether it was sent.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SamMessage {
+  pub board_id: String,
+  pub message: SamControlMessage,
+
+  /// Why the message couldn't be sent, or `None` if it was.
+  pub error: Option<String>,
+}
+
+/// A record along with when it was recorded.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Entry<'a> {
+  /// Microseconds since the Unix epoch.
+  pub timestamp: u64,
+  pub record: Record<'a>,
+}
+
+/// A black-box recorder which appends every record to a set of rotating files.
+///
+/// Each file starts with `MAGIC`, followed by frames made of a little-endian
+/// u32 payload length, a little-endian u32 CRC-32 of the payload and the
+/// postcard-serialized `Entry` itself. A frame cut short by a power loss or a
+/// crash fails either the length or the checksum, so it can be detected and
+/// discarded when reading the file back.
+///
+/// Waiting for the recording to reach the disk can take a long time on a busy
+/// or failing disk, so it's left to a background thread.
+///
+/// Recording is best-effort: if the recording directory can't be written to,
+/// the error is reported once and the recorder disables itself rather than
+/// interfering with the control cycle.
+pub struct Recorder {
+  config: RecorderConfig,
+  syncer: Option<Syncer>,
+  file: Option<BufWriter<File>>,
+  file_size: u64,
+  file_index: u32,
+  last_snapshot: Instant,
+  last_sync: Instant,
+}
+
+impl Recorder {
+  pub fn new(config: &RecorderConfig, now: Instant) -> Self {
+    let mut recorder = Recorder {
+      config: config.clone(),
+      syncer: None,
+      file: None,
+      file_size: 0,
+      file_index: 0,
+      last_snapshot: now,
+      last_sync: now,
+    };
+
+    if config.enabled {
+      let started = Syncer::spawn().map_err(Error::Io).and_then(|syncer| {
+        recorder.syncer = Some(syncer);
+        recorder.rotate()
+      });
+
+      if let Err(e) = started {
+        eprintln!("Couldn't start the flight recorder in '{}', continuing without it: {e}", config.directory.display());
+      }
+    }
+
+    recorder
+  }
+
+  pub fn telemetry(&mut self, from: SocketAddr, message: &DataMessage) {
+    self.record(Record::Telemetry(from, Cow::Borrowed(message)));
+  }
+
+  pub fn servo_message(&mut self, message: &FlightControlMessage) {
+    self.record(Record::Servo(Cow::Borrowed(message)));
+  }
+
+  pub fn sequence_command(&mut self, command: AttributedCommand) {
+    self.record(Record::AttributedSequenceCommand(Cow::Owned(command)));
+  }
+
+  pub fn sam_message(&mut self, message: SamMessage) {
+    self.record(Record::SamMessage(Cow::Owned(message)));
+  }
+
+  pub fn unmapped_sam_command(&mut self, command: &SequenceDomainCommand) {
+    self.record(Record::UnmappedSamCommand(Cow::Borrowed(command)));
+  }
+
+  pub fn status(&mut self, message: &StatusMessage) {
+    self.record(Record::Status(Cow::Borrowed(message)));
+  }
+
+  pub fn sequence_output(&mut self, line: &OutputLine) {
+    self.record(Record::SequenceOutput(Cow::Borrowed(line)));
+  }
+
+  /// Records the vehicle state if a snapshot is due as of `now`.
+  pub fn state(&mut self, state: &VehicleState, now: Instant) {
+    if now.saturating_duration_since(self.last_snapshot) < self.config.snapshot_interval {
+      return;
+    }
+
+    self.record(Record::State(Cow::Borrowed(state)));
+    self.last_snapshot = now;
+  }
+
+  /// Hands everything recorded so far to the operating system and, if it's
+  /// been long enough since the last time as of `now`, has the sync thread
+  /// wait for it to reach the disk. Should be ran once per control cycle.
+  pub fn flush(&mut self, now: Instant) {
+    let (Some(file), Some(syncer)) = (&mut self.file, &self.syncer) else {
+      return;
+    };
+
+    let mut result = file.flush();
+
+    if result.is_ok() && now.saturating_duration_since(self.last_sync) >= self.config.sync_interval {
+      result = syncer.sync(file.get_ref());
+      self.last_sync = now;
+    }
+
+    if let Err(e) = result {
+      self.disable(Error::Io(e));
+    }
+  }
+
+  fn record(&mut self, record: Record) {
+    if self.file.is_none() {
+      return;
+    }
+
+    let entry = Entry { timestamp: timestamp(), record };
+
+    if let Err(e) = self.write(&entry) {
+      self.disable(e);
+    }
+  }
+
+  fn write(&mut self, entry: &Entry) -> Result<()> {
+    let payload = postcard::to_allocvec(entry).map_err(Error::SerializationFailed)?;
+    let frame_size = 8 + payload.len() as u64;
+
+    if self.file_size + frame_size > self.config.max_file_size {
+      self.rotate()?;
+    }
+
+    let Some(file) = &mut self.file else {
+      return Ok(());
+    };
+
+    let mut frame = Vec::with_capacity(frame_size as usize);
+    frame.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    frame.extend_from_slice(&crc32fast::hash(&payload).to_le_bytes());
+    frame.extend_from_slice(&payload);
+
+    file.write_all(&frame)?;
+    self.file_size += frame_size;
+    Ok(())
+  }
+
+  /// Closes the current file, if any, and starts a new one, deleting the
+  /// oldest recordings beyond the configured limit.
+  fn rotate(&mut self) -> Result<()> {
+    if let (Some(mut file), Some(syncer)) = (self.file.take(), &self.syncer) {
+      file.flush()?;
+      syncer.sync(file.get_ref())?;
+    }
+
+    fs::create_dir_all(&self.config.directory)?;
+
+    let started = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    let path = self.config.directory.join(format!("flight-{started}-{:04}.{EXTENSION}", self.file_index));
+    self.file_index += 1;
+
+    let mut file = BufWriter::new(File::create(&path)?);
+    file.write_all(&MAGIC)?;
+    self.file = Some(file);
+    self.file_size = MAGIC.len() as u64;
+
+    println!("Recording flight data to '{}'.", path.display());
+
+    let mut recordings = list(&self.config.directory)?;
+    while recordings.len() > self.config.max_files {
+      let oldest = recordings.remove(0);
+
+      if let Err(e) = fs::remove_file(&oldest) {
+        eprintln!("Couldn't remove old recording '{}': {e}", oldest.display());
+      }
+    }
+
+    Ok(())
+  }
+
+  fn disable(&mut self, error: Error) {
+    eprintln!("The flight recorder failed and has been disabled: {error}");
+    self.file = None;
+  }
+}
+
+/// A background thread which waits for recordings to reach the disk.
+struct Syncer {
+  files: SyncSender<File>,
+  failures: Receiver<io::Error>,
+}
+
+impl Syncer {
+  fn spawn() -> io::Result<Self> {
+    let (files, queue) = mpsc::sync_channel::<File>(SYNC_QUEUE_SIZE);
+    let (failed, failures) = mpsc::channel();
+
+    thread::Builder::new()
+      .name("recorder-sync".to_string())
+      .spawn(move || {
+        // runs until the recorder is dropped or a sync fails
+        for file in queue {
+          if let Err(e) = file.sync_data() {
+            let _ = failed.send(e);
+            break;
+          }
+        }
+      })?;
+
+    Ok(Syncer { files, failures })
+  }
+
+  /// Queues a sync of everything written to the file so far, returning the
+  /// error of an earlier sync if one failed. The sync is skipped if the queue
+  /// is full, as a queued sync covers everything written before it runs.
+  fn sync(&self, file: &File) -> io::Result<()> {
+    if let Ok(e) = self.failures.try_recv() {
+      return Err(e);
+    }
+
+    match self.files.try_send(file.try_clone()?) {
+      Ok(()) | Err(TrySendError::Full(_)) => Ok(()),
+      // the thread only stops after reporting a failure
+      Err(TrySendError::Disconnected(_)) => Err(
+        self.failures.try_recv().unwrap_or_else(|_| io::Error::other("The sync thread stopped."))
+      ),
+    }
+  }
+}
+
+/// Reads the entries of a recording back in the order they were recorded.
+///
+/// Once a frame is found to be truncated or corrupted, the error is yielded
+/// and iteration stops, as the lengths of any following frames can't be
+/// trusted.
+pub struct Reader {
+  file: BufReader<File>,
+  done: bool,
+}
+
+impl Reader {
+  pub fn open(path: &Path) -> Result<Self> {
+    let mut file = BufReader::new(File::open(path)?);
+    let mut magic = [0; MAGIC.len()];
+
+    if fill(&mut file, &mut magic)? != MAGIC.len() || !READABLE.contains(&magic) {
+      return Err(Error::NotARecording);
+    }
+
+    Ok(Reader { file, done: false })
+  }
+
+  fn read_entry(&mut self) -> Result<Option<Entry<'static>>> {
+    let mut header = [0; 8];
+
+    match fill(&mut self.file, &mut header)? {
+      0 => return Ok(None),
+      8 => {},
+      _ => return Err(Error::Truncated),
+    };
+
+    let size = u32::from_le_bytes([header[0], header[1], header[2], header[3]]) as usize;
+    let checksum = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+
+    if size > MAX_PAYLOAD_SIZE {
+      return Err(Error::Corrupted);
+    }
+
+    let mut payload = vec![0; size];
+    if fill(&mut self.file, &mut payload)? != size {
+      return Err(Error::Truncated);
+    }
+
+    if crc32fast::hash(&payload) != checksum {
+      return Err(Error::Corrupted);
+    }
+
+    postcard::from_bytes::<Entry>(&payload)
+      .map(Some)
+      .map_err(Error::DeserializationFailed)
+  }
+}
+
+impl Iterator for Reader {
+  type Item = Result<Entry<'static>>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    if self.done {
+      return None;
+    }
+
+    match self.read_entry() {
+      Ok(Some(entry)) => Some(Ok(entry)),
+      Ok(None) => {
+        self.done = true;
+        None
+      },
+      Err(e) => {
+        self.done = true;
+        Some(Err(e))
+      },
+    }
+  }
+}
+
+/// Reads until the buffer is full or the end of the file is reached,
+/// returning how many bytes were read.
+fn fill(reader: &mut impl Read, buf: &mut [u8]) -> io::Result<usize> {
+  let mut read = 0;
+
+  while read < buf.len() {
+    match reader.read(&mut buf[read..]) {
+      Ok(0) => break,
+      Ok(n) => read += n,
+      Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+      Err(e) => return Err(e),
+    };
+  }
+
+  Ok(read)
+}
+
+/// Lists the recordings in a directory from oldest to newest.
+pub fn list(directory: &Path) -> io::Result<Vec<PathBuf>> {
+  let mut recordings: Vec<PathBuf> = fs::read_dir(directory)?
+    .filter_map(|entry| entry.ok().map(|e| e.path()))
+    .filter(|path| path.extension().is_some_and(|e| e == EXTENSION))
+    .collect();
+
+  // names embed the start time and a counter, so they sort chronologically
+  recordings.sort();
+  Ok(recordings)
+}
+
+fn timestamp() -> u64 {
+  SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .unwrap_or(Duration::ZERO)
+    .as_micros() as u64
+}
+
+type Result<T> = ::std::result::Result<T, Error>;
+
+#[derive(Debug)]
+pub enum Error {
+  SerializationFailed(postcard::Error),
+  DeserializationFailed(postcard::Error),
+  NotARecording,
+  Truncated,
+  Corrupted,
+  Io(io::Error),
+}
+
+impl From<io::Error> for Error {
+  fn from(error: io::Error) -> Self {
+    Error::Io(error)
+  }
+}
+
+impl fmt::Display for Error {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::SerializationFailed(e) => write!(f, "Couldn't serialize a record: {e}"),
+      Self::DeserializationFailed(e) => write!(f, "Couldn't deserialize a record: {e}"),
+      Self::NotARecording => write!(f, "The file doesn't start with the recording header."),
+      Self::Truncated => write!(f, "The recording ends with a partially written record."),
+      Self::Corrupted => write!(f, "A record is corrupted, as its length or checksum is invalid."),
+      Self::Io(e) => write!(f, "Couldn't access the recording: {e}"),
+    }
+  }
+}
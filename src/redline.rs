@@ -0,0 +1,83 @@
+use std::time::Instant;
+use common::comm::VehicleState;
+use crate::{config::{Config, RedlineConfig, SafingAction}, status::StatusMessage};
+
+/// Checks sensor readings against their configured limits every cycle.
+///
+/// A redline trips once its reading has been out of limits for the whole
+/// persistence window, and stays tripped until the reading is back within
+/// limits, so its action is only taken once per violation. A sensor without
+/// a reading is never out of limits.
+pub struct Redlines {
+  redlines: Vec<Redline>,
+}
+
+struct Redline {
+  text_id: String,
+  config: RedlineConfig,
+
+  /// When the reading was first seen out of limits, if it currently is.
+  violated_since: Option<Instant>,
+  tripped: bool,
+}
+
+impl Redlines {
+  pub fn new(config: &Config) -> Self {
+    let redlines = config.redlines
+      .iter()
+      .map(|(text_id, config)| Redline {
+        text_id: text_id.clone(),
+        config: config.clone(),
+        violated_since: None,
+        tripped: false,
+      })
+      .collect();
+
+    Redlines { redlines }
+  }
+
+  /// Evaluates every redline, adding a report to `reports` whenever one trips
+  /// or clears. Returns the actions of the redlines that just tripped.
+  pub fn check(&mut self, state: &VehicleState, now: Instant, reports: &mut Vec<StatusMessage>) -> Vec<SafingAction> {
+    let mut actions = Vec::new();
+
+    for redline in &mut self.redlines {
+      let Some(value) = state.sensor_readings.get(&redline.text_id).map(|m| m.value) else {
+        redline.violated_since = None;
+        continue;
+      };
+
+      // NaN is treated as out of limits, as it means the sensor is unusable
+      let within = !value.is_nan()
+        && redline.config.min.is_none_or(|min| value >= min)
+        && redline.config.max.is_none_or(|max| value <= max);
+
+      if within {
+        if redline.tripped {
+          reports.push(StatusMessage::RedlineCleared { text_id: redline.text_id.clone() });
+        }
+
+        redline.violated_since = None;
+        redline.tripped = false;
+        continue;
+      }
+
+      let since = *redline.violated_since.get_or_insert(now);
+
+      if !redline.tripped && now.duration_since(since) >= redline.config.persistence {
+        redline.tripped = true;
+        actions.push(redline.config.action);
+
+        reports.push(StatusMessage::RedlineTripped {
+          text_id: redline.text_id.clone(),
+          value,
+          min: redline.config.min,
+          max: redline.config.max,
+          action: redline.config.action,
+        });
+      }
+    }
+
+    actions
+  }
+}
@@ -1,11 +1,456 @@
 use common::comm::{SensorType, Sequence, flight::SequenceDomainCommand};
-use std::{collections::HashMap, io, os::unix::net::UnixDatagram, process::{Child, Command}};
-use crate::Mappings;
+use mio::Waker;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, fs, io::{self, BufRead, BufReader, Read}, mem, os::{fd::AsRawFd, unix::{net::UnixDatagram, process::{CommandExt, ExitStatusExt}}}, path::Path, ptr, process::{Child, Command, ExitStatus, Stdio}, sync::{atomic::{AtomicU64, Ordering}, mpsc::{self, Receiver, SyncSender, TrySendError}, Arc}, thread, time::{Duration, Instant, SystemTime, UNIX_EPOCH}};
+use crate::{config::{Config, SequenceConfig}, status::StatusMessage, Mappings};
 
-pub(crate) type Sequences = HashMap<String, Child>;
+/// The longest line of output that's kept, in bytes, so that every line fits
+/// in a single datagram to Servo. Anything beyond it is cut off.
+const MAX_LINE_LENGTH: usize = 1024;
 
-fn run(mappings: &Mappings, sequence: &Sequence) -> io::Result<Child> {
+/// How many lines of output are buffered until they're collected. Lines read
+/// while the buffer is full are dropped and counted instead, so that a
+/// sequence printing in a tight loop can't grow the FC's memory.
+pub const OUTPUT_CAPACITY: usize = 1024;
+
+/// The most lines of output that are collected by each call to `output`, so
+/// that forwarding them can't stall the control cycle.
+pub const MAX_OUTPUT_PER_TICK: usize = 64;
+
+/// How long the window that commands are counted over for rate limiting is.
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+
+/// The name of the sequence that's run when the vehicle aborts.
+pub const ABORT: &str = "abort";
+
+/// The code that a sequence with a memory limit exits with when it runs out of
+/// memory, which is told apart from other failures by its status.
+pub const MEMORY_EXIT_CODE: i32 = 86;
+
+/// Every sequence started by the FC, keyed by name. A sequence that has
+/// stopped is kept until another with the same name is started, so that its
+/// final status can be reported. If that hasn't happened by then, it's
+/// reported by the next call to `reap` instead.
+///
+/// The stdout and stderr of every sequence are read line by line on
+/// background threads into a bounded buffer, and collected with `output`.
+///
+/// The CPU time and memory of a sequence are limited by the kernel from the
+/// moment it's started, and its niceness is set right after. Its timeout is
+/// enforced by `reap`.
+pub struct Sequences {
+    configs: HashMap<String, SequenceConfig>,
+    default: SequenceConfig,
+    sequences: HashMap<String, Process>,
+
+    /// The final statuses of sequences that were replaced before they were
+    /// reported.
+    replaced: Vec<SequenceStatus>,
+
+    output_sender: SyncSender<OutputLine>,
+    output: Receiver<OutputLine>,
+
+    /// Whether the last call to `output` left lines in the buffer.
+    output_pending: bool,
+    waker: Option<Arc<Waker>>,
+}
+
+struct Process {
+    child: Child,
+    status: SequenceStatus,
+
+    /// Whether `status` has been handed out by `reap` since it last changed.
+    reported: bool,
+
+    /// When the sequence is killed if it's still running.
+    deadline: Option<Instant>,
+    timed_out: bool,
+
+    /// Whether the sequence has a memory limit, and so exits with
+    /// `MEMORY_EXIT_CODE` when it runs out.
+    memory_limited: bool,
+
+    /// When the current rate limiting window started, and how many commands
+    /// have been sent within it.
+    window_start: Instant,
+    window_commands: u32,
+
+    /// How many lines of output have been dropped since the last call to
+    /// `output`, counted by the threads reading them.
+    dropped_output: Arc<AtomicU64>,
+}
+
+/// Where a sequence is in its lifecycle, as reported to Servo.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct SequenceStatus {
+    pub name: String,
+    pub state: SequenceState,
+
+    /// Milliseconds since the Unix epoch.
+    pub started_at: u64,
+
+    /// Milliseconds since the Unix epoch, once the sequence has stopped.
+    pub stopped_at: Option<u64>,
+}
+
+/// A line that a sequence wrote to its stdout or stderr, without the newline.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct OutputLine {
+    pub name: String,
+    pub stream: OutputStream,
+    pub line: String,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum OutputStream {
+    Stdout,
+    Stderr,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum SequenceState {
+    Running,
+
+    /// The sequence exited on its own with the given code.
+    Exited(i32),
+
+    /// The sequence was terminated by the given signal, such as when it's
+    /// stopped or the vehicle aborts.
+    Killed(i32),
+
+    /// The sequence was killed for running past its timeout.
+    TimedOut,
+
+    /// The sequence was killed by the kernel for using up its CPU time.
+    CpuLimitExceeded,
+
+    /// The sequence failed to allocate memory beyond its limit and exited.
+    MemoryLimitExceeded,
+}
+
+impl Sequences {
+    pub fn new(config: &Config) -> Self {
+        let (output_sender, output) = mpsc::sync_channel(OUTPUT_CAPACITY);
+
+        Sequences {
+            configs: config.sequences.clone(),
+            default: SequenceConfig::default(),
+            sequences: HashMap::new(),
+            replaced: Vec::new(),
+            output_sender,
+            output,
+            output_pending: false,
+            waker: None,
+        }
+    }
+
+    /// Wakes the reactor whenever a sequence writes a line, so that it's
+    /// forwarded without waiting for the next deadline.
+    pub fn set_waker(&mut self, waker: Arc<Waker>) {
+        self.waker = Some(waker);
+    }
+
+    /// Up to `MAX_OUTPUT_PER_TICK` of the lines written by sequences since
+    /// the last call, in the order they were read. Adds a report to `reports`
+    /// for every sequence whose output was dropped as the buffer was full.
+    pub fn output(&mut self, reports: &mut Vec<StatusMessage>) -> Vec<OutputLine> {
+        let lines: Vec<OutputLine> = self.output.try_iter().take(MAX_OUTPUT_PER_TICK).collect();
+        self.output_pending = lines.len() == MAX_OUTPUT_PER_TICK;
+
+        for process in self.sequences.values() {
+            let dropped = process.dropped_output.swap(0, Ordering::Relaxed);
+
+            if dropped > 0 {
+                reports.push(StatusMessage::SequenceOutputDropped { name: process.status.name.clone(), lines: dropped });
+            }
+        }
+
+        lines
+    }
+
+    /// Whether output may be left over from the last call to `output`, in
+    /// which case it should be called again without waiting.
+    pub fn has_pending_output(&self) -> bool {
+        self.output_pending
+    }
+
+    /// Kills every sequence that has run past its timeout as of `now` and
+    /// collects the exit status of every sequence that has stopped. Returns the
+    /// status of each sequence that has started or stopped since the last call.
+    /// Should be ran once per control cycle.
+    pub fn reap(&mut self, now: Instant) -> Vec<SequenceStatus> {
+        let mut changed = mem::take(&mut self.replaced);
+
+        for process in self.sequences.values_mut() {
+            if process.status.state == SequenceState::Running {
+                if !process.timed_out && process.deadline.is_some_and(|d| now >= d) {
+                    println!("Sequence '{}' ran past its timeout, killing it...", process.status.name);
+                    process.timed_out = true;
+
+                    if let Err(e) = process.child.kill() {
+                        eprintln!("Couldn't kill sequence '{}': {e}", process.status.name);
+                    }
+                }
+
+                match process.child.try_wait() {
+                    Ok(Some(status)) => {
+                        process.status.state = state(status, process.timed_out, process.memory_limited);
+                        process.status.stopped_at = Some(timestamp());
+                        process.reported = false;
+                    },
+                    Ok(None) => {},
+                    Err(e) => eprintln!("Couldn't determine whether sequence '{}' is still running: {e}", process.status.name),
+                };
+            }
+
+            if !process.reported {
+                process.reported = true;
+                changed.push(process.status.clone());
+            }
+        }
+
+        changed
+    }
+
+    /// The soonest time at which a running sequence times out.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.sequences
+            .values()
+            .filter(|p| p.status.state == SequenceState::Running && !p.timed_out)
+            .filter_map(|p| p.deadline)
+            .min()
+    }
+
+    /// The name of the running sequence with the given PID.
+    pub fn name_of(&self, pid: u32) -> Option<&str> {
+        self.sequences
+            .values()
+            .find(|p| p.status.state == SequenceState::Running && p.child.id() == pid)
+            .map(|p| p.status.name.as_str())
+    }
+
+    /// Counts a command from the named sequence against its rate limit as of
+    /// `now`, adding a report to `reports` the first time within a window that
+    /// the limit is exceeded. Returns whether the command is within the limit.
+    pub fn admit(&mut self, name: &str, now: Instant, reports: &mut Vec<StatusMessage>) -> bool {
+        let Some(limit) = self.config(name).max_commands_per_second else {
+            return true;
+        };
+
+        let Some(process) = self.sequences.get_mut(name) else {
+            return true;
+        };
+
+        if now.saturating_duration_since(process.window_start) >= RATE_WINDOW {
+            process.window_start = now;
+            process.window_commands = 0;
+        }
+
+        process.window_commands = process.window_commands.saturating_add(1);
+
+        if process.window_commands <= limit {
+            return true;
+        }
+
+        if process.window_commands == limit + 1 {
+            reports.push(StatusMessage::SequenceRateLimited { name: name.to_string(), limit });
+        }
+
+        false
+    }
+
+    /// The last known status of every sequence.
+    pub fn statuses(&self) -> Vec<SequenceStatus> {
+        self.sequences.values().map(|p| p.status.clone()).collect()
+    }
+
+    /// Kills every sequence, which is reported once it's reaped.
+    pub fn kill_all(&mut self) {
+        for process in self.sequences.values_mut() {
+            if let Err(e) = process.child.kill() {
+                println!("Couldn't kill a sequence in preperation for abort, continuing normally: {e}");
+            }
+        }
+    }
+
+    /// Kills every sequence other than the named one, which is reported once
+    /// it's reaped.
+    pub fn kill_all_except(&mut self, name: &str) {
+        for process in self.sequences.values_mut().filter(|p| p.status.name != name) {
+            if let Err(e) = process.child.kill() {
+                println!("Couldn't kill a sequence in preperation for abort, continuing normally: {e}");
+            }
+        }
+    }
+
+    /// Whether the named sequence is still running, even if that hasn't been
+    /// reaped yet.
+    pub fn is_running(&mut self, name: &str) -> bool {
+        self.get_mut(name).is_some_and(|child| matches!(child.try_wait(), Ok(None)))
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (&String, &mut Child)> {
+        self.sequences.iter_mut().map(|(name, process)| (name, &mut process.child))
+    }
+
+    fn get_mut(&mut self, name: &str) -> Option<&mut Child> {
+        self.sequences.get_mut(name).map(|p| &mut p.child)
+    }
+
+    fn config(&self, name: &str) -> &SequenceConfig {
+        self.configs.get(name).unwrap_or(&self.default)
+    }
+
+    fn insert(&mut self, name: String, mut child: Child, now: Instant) {
+        let previous = self.sequences.remove(&name);
+
+        // output of the previous sequence that's still being read is counted
+        // along with the new one's
+        let dropped_output = previous.as_ref().map_or_else(Default::default, |p| p.dropped_output.clone());
+
+        if let Some(previous) = previous {
+            self.replace(previous);
+        }
+
+        if let Some(stdout) = child.stdout.take() {
+            self.forward(&name, OutputStream::Stdout, stdout, &dropped_output);
+        }
+
+        if let Some(stderr) = child.stderr.take() {
+            self.forward(&name, OutputStream::Stderr, stderr, &dropped_output);
+        }
+
+        let status = SequenceStatus {
+            name: name.clone(),
+            state: SequenceState::Running,
+            started_at: timestamp(),
+            stopped_at: None,
+        };
+
+        let config = self.config(&name);
+        let deadline = config.timeout.map(|timeout| now + timeout);
+        let memory_limited = config.memory_bytes.is_some();
+
+        self.sequences.insert(name, Process {
+            child,
+            status,
+            reported: false,
+            deadline,
+            timed_out: false,
+            memory_limited,
+            window_start: now,
+            window_commands: 0,
+            dropped_output,
+        });
+    }
+
+    /// Keeps the final status of a sequence that's being replaced by another of
+    /// the same name until it's reaped, unless it's already been reported.
+    fn replace(&mut self, mut process: Process) {
+        if process.status.state == SequenceState::Running {
+            match process.child.try_wait() {
+                Ok(Some(status)) => {
+                    process.status.state = state(status, process.timed_out, process.memory_limited);
+                    process.status.stopped_at = Some(timestamp());
+                    process.reported = false;
+                },
+                Ok(None) => eprintln!("Sequence '{}' was replaced while still running.", process.status.name),
+                Err(e) => eprintln!("Couldn't determine how the replaced sequence '{}' exited: {e}", process.status.name),
+            };
+        }
+
+        if !process.reported {
+            self.replaced.push(process.status);
+        }
+    }
+
+    /// Reads lines from one of a sequence's pipes until it's closed, which
+    /// happens once the sequence exits. Lines that don't fit in the buffer are
+    /// counted in `dropped`.
+    fn forward(&self, name: &str, stream: OutputStream, pipe: impl Read + Send + 'static, dropped: &Arc<AtomicU64>) {
+        let name = name.to_string();
+        let sender = self.output_sender.clone();
+        let dropped = dropped.clone();
+        let waker = self.waker.clone();
+
+        let spawned = thread::Builder::new()
+            .name(format!("sequence-{stream:?}").to_lowercase())
+            .spawn(move || {
+                let mut reader = BufReader::new(pipe);
+                let mut buf = Vec::new();
+
+                loop {
+                    buf.clear();
+
+                    match reader.read_until(b'\n', &mut buf) {
+                        Ok(0) => break,
+                        Ok(_) => {},
+                        Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                        Err(e) => {
+                            eprintln!("Couldn't read the output of sequence '{name}': {e}");
+                            break;
+                        },
+                    };
+
+                    while buf.last().is_some_and(|b| *b == b'\n' || *b == b'\r') {
+                        buf.pop();
+                    }
+
+                    buf.truncate(MAX_LINE_LENGTH);
+                    let line = String::from_utf8_lossy(&buf).into_owned();
+
+                    match sender.try_send(OutputLine { name: name.clone(), stream, line }) {
+                        Ok(()) => {},
+                        Err(TrySendError::Full(_)) => {
+                            dropped.fetch_add(1, Ordering::Relaxed);
+                            continue;
+                        },
+                        // the FC may be shutting down, which is fine
+                        Err(TrySendError::Disconnected(_)) => break,
+                    };
+
+                    if let Some(waker) = &waker {
+                        let _ = waker.wake();
+                    }
+                }
+            });
+
+        if let Err(e) = spawned {
+            eprintln!("Couldn't start reading the output of a sequence, it will fail to write any: {e}");
+        }
+    }
+}
+
+fn state(status: ExitStatus, timed_out: bool, memory_limited: bool) -> SequenceState {
+    match (status.code(), status.signal()) {
+        (Some(MEMORY_EXIT_CODE), _) if memory_limited => SequenceState::MemoryLimitExceeded,
+        (Some(code), _) => SequenceState::Exited(code),
+        (None, Some(libc::SIGKILL)) if timed_out => SequenceState::TimedOut,
+        (None, Some(libc::SIGXCPU)) => SequenceState::CpuLimitExceeded,
+        (None, Some(signal)) => SequenceState::Killed(signal),
+        // one of the two is always set on Unix
+        (None, None) => SequenceState::Exited(-1),
+    }
+}
+
+fn timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or(Duration::ZERO)
+        .as_millis() as u64
+}
+
+fn run(mappings: &Mappings, sequence: &Sequence, config: &SequenceConfig) -> io::Result<Child> {
     let mut script = String::from("from common import *;");
+
+    if config.memory_bytes.is_some() {
+        // an uncaught MemoryError would otherwise exit like any other error
+        script.push_str(&format!(
+            "import sys as _sys, os as _os;\
+             _sys.excepthook = lambda t, v, b: (_sys.__excepthook__(t, v, b), issubclass(t, MemoryError) and _os._exit({MEMORY_EXIT_CODE}));"
+        ));
+    }
+
     for mapping in mappings {
         let definition = match mapping.sensor_type {
             SensorType::Valve => format!("{0} = Valve('{0}');", mapping.text_id),
@@ -16,38 +461,97 @@ fn run(mappings: &Mappings, sequence: &Sequence) -> io::Result<Child> {
     }
     
     script.push_str(&sequence.script);
-    Command::new("python3")
+    let mut command = Command::new("python3");
+    command
         .args(["-c", &script])
-        .spawn()
+        // python only flushes each line as it's printed when told to
+        .env("PYTHONUNBUFFERED", "1")
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+
+    let SequenceConfig { cpu_seconds, memory_bytes, nice, .. } = *config;
+
+    if cpu_seconds.is_some() || memory_bytes.is_some() {
+        // SAFETY: only async-signal-safe functions are called in between
+        // forking and executing python
+        unsafe {
+            command.pre_exec(move || limit(cpu_seconds, memory_bytes));
+        }
+    }
+
+    let child = command.spawn()?;
+
+    // set from here so that a failure can be logged, such as when lowering
+    // the niceness without CAP_SYS_NICE, rather than failing the sequence
+    if let Some(nice) = nice {
+        if unsafe { libc::setpriority(libc::PRIO_PROCESS, child.id(), nice) } != 0 {
+            eprintln!("Couldn't set the niceness of sequence '{}' to {nice}, running it at the default: {}", sequence.name, io::Error::last_os_error());
+        }
+    }
+
+    Ok(child)
+}
+
+/// Limits the resources of the calling process, which is a sequence that's
+/// about to execute python.
+fn limit(cpu_seconds: Option<u64>, memory_bytes: Option<u64>) -> io::Result<()> {
+    if let Some(seconds) = cpu_seconds {
+        // the soft limit sends SIGXCPU, and the hard limit a second later
+        // sends SIGKILL in case the former is caught
+        let limit = libc::rlimit { rlim_cur: seconds as libc::rlim_t, rlim_max: seconds.saturating_add(1) as libc::rlim_t };
+
+        if unsafe { libc::setrlimit(libc::RLIMIT_CPU, &limit) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    if let Some(bytes) = memory_bytes {
+        let limit = libc::rlimit { rlim_cur: bytes as libc::rlim_t, rlim_max: bytes as libc::rlim_t };
+
+        if unsafe { libc::setrlimit(libc::RLIMIT_AS, &limit) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    Ok(())
 }
 
-pub(crate) fn execute(mappings: &Mappings, sequence: &Sequence, sequences: &mut Sequences) {
+/// Starts a sequence unless one with the same name is still running. Returns
+/// whether the sequence was started.
+pub fn execute(mappings: &Mappings, sequence: &Sequence, sequences: &mut Sequences, now: Instant) -> bool {
     if let Some(running) = sequences.get_mut(&sequence.name) {
         match running.try_wait() {
             Ok(Some(_)) => {},
             Ok(None) => {
                 println!("The '{}' sequence is already running. Stop it before re-attempting execution.", sequence.name);
-                return;
+                return false;
             },
             Err(e) => {
                 eprintln!("Another '{}' sequence was previously ran, but it's status couldn't be determined: {e}", sequence.name);
-                return;
+                return false;
             },
         }
     }
     
-    let process = match run(mappings, &sequence) {
+    let process = match run(mappings, &sequence, sequences.config(&sequence.name)) {
         Ok(c) => c,
         Err(e) => {
             eprintln!("Error in running python3: {e}");
-            return;
+            return false;
         }
     };
 
-    sequences.insert(sequence.name.clone(), process);
+    sequences.insert(sequence.name.clone(), process, now);
+    true
+}
+
+/// Reads the script at `path` as the abort sequence.
+pub fn load_abort(path: &Path) -> io::Result<Sequence> {
+    let script = fs::read_to_string(path)?;
+    Ok(Sequence { name: ABORT.to_string(), script })
 }
 
-pub(crate) fn kill(sequences: &mut Sequences, name: &String) -> io::Result<()> {
+pub fn kill(sequences: &mut Sequences, name: &String) -> io::Result<()> {
     let sequence = match sequences.get_mut(name) {
         Some(c) => {
             if let Ok(Some(_)) = c.try_wait() {
@@ -66,14 +570,40 @@ pub(crate) fn kill(sequences: &mut Sequences, name: &String) -> io::Result<()> {
     sequence.kill()
 }
 
-pub(crate) fn pull_commands<'a>(socket: &UnixDatagram) -> Vec<SequenceDomainCommand> {
+/// Makes the kernel attach the credentials of the sending process to every
+/// datagram received on the sequence command socket, so that commands can be
+/// traced back to the sequence that sent them.
+pub fn pass_credentials(socket: &UnixDatagram) -> io::Result<()> {
+    let enabled: libc::c_int = 1;
+
+    let result = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PASSCRED,
+            (&enabled as *const libc::c_int).cast(),
+            mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+/// Receives every pending command along with the PID of the process that
+/// sent it, if the kernel attached its credentials.
+pub fn pull_commands(socket: &UnixDatagram) -> Vec<(Option<u32>, SequenceDomainCommand)> {
     let mut buf: [u8; 1024] = [0; 1024];
     let mut commands = Vec::new();
 
     loop {
-        let size = match socket.recv(&mut buf) {
-            Ok(s) => s,
+        let (size, pid) = match receive(socket, &mut buf) {
+            Ok(received) => received,
             Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
             Err(e) => {
                 eprintln!("Error in receiving from sequence command socket: {e}");
                 break;
@@ -88,8 +618,48 @@ pub(crate) fn pull_commands<'a>(socket: &UnixDatagram) -> Vec<SequenceDomainComm
             }
         };
 
-        commands.push(command);
+        commands.push((pid, command));
     }
 
     commands
-}
\ No newline at end of file
+}
+
+/// Receives a single datagram, returning its size and the PID found in its
+/// SCM_CREDENTIALS control message, if any.
+fn receive(socket: &UnixDatagram, buf: &mut [u8]) -> io::Result<(usize, Option<u32>)> {
+    let mut iov = libc::iovec { iov_base: buf.as_mut_ptr().cast(), iov_len: buf.len() };
+
+    // u64s keep the buffer aligned for the control message headers within it
+    let mut control = [0u64; 8];
+
+    let mut header: libc::msghdr = unsafe { mem::zeroed() };
+    header.msg_iov = &mut iov;
+    header.msg_iovlen = 1;
+    header.msg_control = control.as_mut_ptr().cast();
+    header.msg_controllen = mem::size_of_val(&control) as _;
+
+    let size = unsafe { libc::recvmsg(socket.as_raw_fd(), &mut header, 0) };
+
+    if size < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut pid = None;
+
+    // SAFETY: the kernel filled in the control buffer that the header points
+    // to, and the macros never read beyond msg_controllen
+    unsafe {
+        let mut message = libc::CMSG_FIRSTHDR(&header);
+
+        while !message.is_null() {
+            if (*message).cmsg_level == libc::SOL_SOCKET && (*message).cmsg_type == libc::SCM_CREDENTIALS {
+                let credentials = ptr::read_unaligned(libc::CMSG_DATA(message).cast::<libc::ucred>());
+                pid = u32::try_from(credentials.pid).ok();
+            }
+
+            message = libc::CMSG_NXTHDR(&header, message);
+        }
+    }
+
+    Ok((size as usize, pid))
+}
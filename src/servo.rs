@@ -1,13 +1,14 @@
-use std::{fmt, io::{self, Read, Write}, net::{SocketAddr, TcpStream, ToSocketAddrs, UdpSocket}, time::Duration};
+use std::{fmt, io::{self, Read, Write}, mem, net::{SocketAddr, TcpStream, ToSocketAddrs, UdpSocket}, os::fd::AsRawFd, sync::{mpsc::{self, Receiver, TryRecvError}, Arc}, thread, time::{Duration, Instant}};
 use common::comm::{Computer, FlightControlMessage, VehicleState};
+use mio::Waker;
 use postcard::experimental::max_size::MaxSize;
 
-use crate::SERVO_DATA_PORT;
+use crate::{status::StatusMessage, SERVO_CONNECT_TIMEOUT, SERVO_INITIAL_BACKOFF, SERVO_KEEPALIVE_IDLE, SERVO_KEEPALIVE_INTERVAL, SERVO_KEEPALIVE_PROBES, SERVO_MAX_BACKOFF};
 
 type Result<T> = std::result::Result<T, ServoError>;
 
 #[derive(Debug)]
-pub(crate) enum ServoError {
+pub enum ServoError {
   ServoDisconnected,
   TransportFailed(io::Error),
   DeserializationFailed(postcard::Error),
@@ -23,7 +24,7 @@ impl fmt::Display for ServoError {
   }
 }
 
-pub(crate) fn establish(servo_addresses: &[impl ToSocketAddrs], prev_connected_servo_addr: Option<&SocketAddr>, chances: u8, timeout: Duration) -> Result<(TcpStream, SocketAddr)> {
+pub fn establish(servo_addresses: &[impl ToSocketAddrs], prev_connected_servo_addr: Option<&SocketAddr>, chances: u8, timeout: Duration) -> Result<(TcpStream, SocketAddr)> {
   // buffer containing the serialized identity message to be sent to the control server
   let mut identity = [0; Computer::POSTCARD_MAX_SIZE];
 
@@ -45,6 +46,7 @@ pub(crate) fn establish(servo_addresses: &[impl ToSocketAddrs], prev_connected_s
         match TcpStream::connect_timeout(addr, timeout) {
           Ok(mut s) => {
             s.set_nodelay(true).map_err(|e| ServoError::TransportFailed(e))?;
+            keep_alive(&s).map_err(ServoError::TransportFailed)?;
             s.set_nonblocking(true).map_err(|e| ServoError::TransportFailed(e))?;
 
             if let Err(e) = s.write_all(&identity) {
@@ -65,6 +67,7 @@ pub(crate) fn establish(servo_addresses: &[impl ToSocketAddrs], prev_connected_s
           match TcpStream::connect_timeout(addr, timeout) {
             Ok(mut s) => {
               s.set_nodelay(true).map_err(|e| ServoError::TransportFailed(e))?;
+              keep_alive(&s).map_err(ServoError::TransportFailed)?;
               s.set_nonblocking(true).map_err(|e| ServoError::TransportFailed(e))?;
 
               if let Err(e) = s.write_all(&identity) {
@@ -83,49 +86,346 @@ pub(crate) fn establish(servo_addresses: &[impl ToSocketAddrs], prev_connected_s
   Err(ServoError::TransportFailed(fatal_error))
 }
 
-// "pull" new information from servo
-pub(crate) fn pull(servo_stream: &mut TcpStream) -> Result<Option<FlightControlMessage>> {
-  let mut buffer = vec![0; u16::MAX as usize + 2];
-  let mut index: usize = 0;
-
-  while index < 2 {
-    index += match servo_stream.read(&mut buffer[index..]) {
-      Ok(s) if s == 0 => return Err(ServoError::ServoDisconnected),
-      Ok(s) => s,
-      Err(ref e) if e.kind() == io::ErrorKind::WouldBlock && index == 0 => return Ok(None),
-      Err(e) => return Err(ServoError::TransportFailed(e))
+/// Makes the kernel probe the connection with Servo whenever it's idle, so
+/// that a Servo which disappears without closing it, such as when its cable is
+/// pulled or its host loses power, surfaces as a failed read rather than
+/// leaving the connection half-open forever.
+fn keep_alive(stream: &TcpStream) -> io::Result<()> {
+  let options = [
+    (libc::SOL_SOCKET, libc::SO_KEEPALIVE, 1),
+    (libc::IPPROTO_TCP, libc::TCP_KEEPIDLE, SERVO_KEEPALIVE_IDLE.as_secs() as libc::c_int),
+    (libc::IPPROTO_TCP, libc::TCP_KEEPINTVL, SERVO_KEEPALIVE_INTERVAL.as_secs() as libc::c_int),
+    (libc::IPPROTO_TCP, libc::TCP_KEEPCNT, SERVO_KEEPALIVE_PROBES as libc::c_int),
+  ];
+
+  for (level, name, value) in options {
+    let result = unsafe {
+      libc::setsockopt(
+        stream.as_raw_fd(),
+        level,
+        name,
+        (&value as *const libc::c_int).cast(),
+        mem::size_of::<libc::c_int>() as libc::socklen_t,
+      )
     };
+
+    if result != 0 {
+      return Err(io::Error::last_os_error());
+    }
   }
-  
-  let mut buf: [u8; 2] = [0; 2];
-  buf.copy_from_slice(&buffer[0..2]);
-  let size = u16::from_be_bytes(buf);
-
-  while index < size as usize + 2 {
-    index += match servo_stream.read(&mut buffer[index..]) {
-      Ok(s) if s == 0 => return Err(ServoError::ServoDisconnected),
-      Ok(s) => s,
-      Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => 0,
-      Err(e) => return Err(ServoError::TransportFailed(e))
+
+  Ok(())
+}
+
+/// Where the connection with Servo currently stands.
+enum LinkState {
+  /// No connection exists and none is being attempted.
+  Disconnected,
+
+  /// A connection attempt is running on a background thread, which reports
+  /// its result through the receiver.
+  Connecting(Receiver<Result<(TcpStream, SocketAddr)>>),
+
+  /// Servo is connected and its messages are being read.
+  Connected {
+    stream: TcpStream,
+    address: SocketAddr,
+    reader: FrameReader,
+  },
+
+  /// The last connection attempt failed, so the next one waits until the
+  /// deadline has passed.
+  Backoff(Instant),
+}
+
+/// Manages the connection with Servo without ever blocking the control cycle.
+/// Connection attempts run in the background, and failed attempts are retried
+/// with an exponentially increasing delay.
+///
+/// Once a connection has been made, reconnection is only attempted with that
+/// same Servo address.
+pub struct ServoLink {
+  state: LinkState,
+  servo_addresses: Vec<String>,
+  last_address: Option<SocketAddr>,
+  backoff: Duration,
+  last_received: Instant,
+  waker: Option<Arc<Waker>>,
+}
+
+impl ServoLink {
+  pub fn new(servo_addresses: Vec<String>, now: Instant) -> Self {
+    ServoLink {
+      state: LinkState::Disconnected,
+      servo_addresses,
+      last_address: None,
+      backoff: SERVO_INITIAL_BACKOFF,
+      last_received: now,
+      waker: None,
+    }
+  }
+
+  /// Wakes the reactor whenever a background connection attempt finishes, so
+  /// that the result is handled without waiting for the next deadline.
+  pub fn set_waker(&mut self, waker: Arc<Waker>) {
+    self.waker = Some(waker);
+  }
+
+  /// Advances the connection and returns every message received from Servo
+  /// since the last poll. Never blocks. Backoff deadlines and the time of the
+  /// last contact with Servo are measured against `now`.
+  pub fn poll(&mut self, now: Instant) -> Vec<FlightControlMessage> {
+    match &mut self.state {
+      LinkState::Disconnected => self.start_connecting(now),
+      LinkState::Connecting(attempt) => match attempt.try_recv() {
+        Ok(Ok((stream, address))) => {
+          if self.last_address.is_some() {
+            println!("Connection with servo at {address} successfully re-established.");
+          } else {
+            println!("Connected to servo at {address} successfully.");
+          }
+
+          self.state = LinkState::Connected { stream, address, reader: FrameReader::new() };
+          self.last_address = Some(address);
+          self.last_received = now;
+          self.backoff = SERVO_INITIAL_BACKOFF;
+        },
+        Ok(Err(e)) => self.back_off(e, now),
+        Err(TryRecvError::Empty) => {},
+        Err(TryRecvError::Disconnected) => self.back_off(ServoError::ServoDisconnected, now),
+      },
+      LinkState::Connected { stream, reader, .. } => match pull(stream, reader) {
+        Ok(messages) => {
+          self.last_received = now;
+          return messages;
+        },
+        // a keepalive timing out fails the read, which is a disconnect as
+        // much as Servo closing the connection is
+        Err(e) => {
+          eprintln!("Issue in pulling data from Servo: {e}");
+          eprintln!("Attempting to reconnect to servo...");
+          self.start_connecting(now);
+        },
+      },
+      LinkState::Backoff(until) => {
+        if now >= *until {
+          self.start_connecting(now);
+        }
+      },
+    };
+
+    Vec::new()
+  }
+
+  /// The address of Servo, if it is currently connected.
+  pub fn address(&self) -> Option<SocketAddr> {
+    match self.state {
+      LinkState::Connected { address, .. } => Some(address),
+      _ => None,
+    }
+  }
+
+  pub fn is_connected(&self) -> bool {
+    matches!(self.state, LinkState::Connected { .. })
+  }
+
+  /// The last time that Servo was known to be connected, which is the last
+  /// poll before the connection was closed or its keepalives went unanswered.
+  /// Servo isn't required to send anything while connected, so this isn't
+  /// when its last message arrived.
+  pub fn last_received(&self) -> Instant {
+    self.last_received
+  }
+
+  /// The stream that Servo is currently connected through.
+  pub fn stream(&self) -> Option<&TcpStream> {
+    match &self.state {
+      LinkState::Connected { stream, .. } => Some(stream),
+      _ => None,
+    }
+  }
+
+  /// Whether the last poll stopped reading before the stream was drained, in
+  /// which case the link should be polled again without waiting for Servo.
+  pub fn has_pending(&self) -> bool {
+    matches!(&self.state, LinkState::Connected { reader, .. } if reader.saturated)
+  }
+
+  /// When the link must next be polled even if Servo sends nothing, which is
+  /// once the current backoff ends.
+  pub fn deadline(&self) -> Option<Instant> {
+    match self.state {
+      LinkState::Backoff(until) => Some(until),
+      _ => None,
+    }
+  }
+
+  fn start_connecting(&mut self, now: Instant) {
+    let (sender, receiver) = mpsc::channel();
+    let servo_addresses = self.servo_addresses.clone();
+    let last_address = self.last_address;
+    let waker = self.waker.clone();
+
+    let spawned = thread::Builder::new()
+      .name("servo-connect".to_string())
+      .spawn(move || {
+        let result = establish(&servo_addresses, last_address.as_ref(), 1, SERVO_CONNECT_TIMEOUT);
+
+        // the link may have been dropped in the meantime, which is fine
+        let _ = sender.send(result);
+
+        if let Some(waker) = waker {
+          let _ = waker.wake();
+        }
+      });
+
+    match spawned {
+      Ok(_) => self.state = LinkState::Connecting(receiver),
+      Err(e) => self.back_off(ServoError::TransportFailed(e), now),
+    };
+  }
+
+  fn back_off(&mut self, error: ServoError, now: Instant) {
+    eprintln!("Couldn't connect to servo: {error}. Retrying in {:?}...", self.backoff);
+
+    self.state = LinkState::Backoff(now + self.backoff);
+    self.backoff = (self.backoff * 2).min(SERVO_MAX_BACKOFF);
+  }
+}
+
+/// The most bytes read from the Servo stream in a single call to `pull`, so
+/// that a flood of messages can't stall the control cycle.
+const MAX_BYTES_PER_PULL: usize = 64 * 1024;
+
+/// Buffers the bytes received from Servo across calls to `pull` until they
+/// form complete frames. Each frame is a big-endian u16 length followed by a
+/// postcard-serialized FlightControlMessage of that length.
+///
+/// Frames are read in place, and the bytes they took up are only dropped once
+/// every complete frame has been read, so that a burst of small frames isn't
+/// copied once per frame.
+///
+/// A reader is tied to a single connection and must be replaced whenever the
+/// connection with Servo is re-established.
+#[derive(Default)]
+pub struct FrameReader {
+  buffer: Vec<u8>,
+
+  /// Where the first frame that hasn't been read yet starts in `buffer`.
+  start: usize,
+  saturated: bool,
+}
+
+impl FrameReader {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Appends bytes received from Servo.
+  pub fn extend(&mut self, bytes: &[u8]) {
+    self.buffer.extend_from_slice(bytes);
+  }
+
+  /// Deserializes every frame that has been fully received. Frames that can't
+  /// be deserialized are reported and skipped, as the length prefix still
+  /// allows the stream to stay in sync.
+  pub fn messages(&mut self) -> Vec<FlightControlMessage> {
+    let mut messages = Vec::new();
+
+    while let Some(frame) = self.next_frame() {
+      match postcard::from_bytes::<FlightControlMessage>(frame) {
+        Ok(m) => messages.push(m),
+        Err(e) => eprintln!("{}", ServoError::DeserializationFailed(e)),
+      };
+    }
+
+    self.buffer.drain(..self.start);
+    self.start = 0;
+    messages
+  }
+
+  /// How many bytes have been received without yet forming a complete frame.
+  pub fn pending(&self) -> usize {
+    self.buffer.len() - self.start
+  }
+
+  /// Returns the payload of the next frame in the buffer and moves past it,
+  /// if it has been fully received.
+  fn next_frame(&mut self) -> Option<&[u8]> {
+    let pending = &self.buffer[self.start..];
+
+    if pending.len() < 2 {
+      return None;
+    }
+
+    let size = u16::from_be_bytes([pending[0], pending[1]]) as usize;
+
+    if pending.len() < size + 2 {
+      return None;
+    }
+
+    let payload = self.start + 2..self.start + 2 + size;
+    self.start = payload.end;
+    Some(&self.buffer[payload])
+  }
+}
+
+/// "Pulls" new information from servo. Reads whatever Servo has sent without blocking and returns every message
+/// that has been completely received, which may be none. Partially received
+/// frames are kept in the reader until the rest arrives on a later call.
+pub fn pull(servo_stream: &mut TcpStream, reader: &mut FrameReader) -> Result<Vec<FlightControlMessage>> {
+  let mut chunk = [0; 4096];
+  let mut total: usize = 0;
+  let mut disconnected = false;
+
+  while total < MAX_BYTES_PER_PULL {
+    match servo_stream.read(&mut chunk) {
+      Ok(0) => {
+        disconnected = true;
+        break;
+      },
+      Ok(s) => {
+        reader.extend(&chunk[..s]);
+        total += s;
+      },
+      Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+      Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+      Err(e) => return Err(ServoError::TransportFailed(e)),
     };
   }
 
-  match postcard::from_bytes::<FlightControlMessage>(&buffer[2..]) {
-    Ok(m) => Ok(Some(m)),
-    Err(e) => Err(ServoError::DeserializationFailed(e)),
+  reader.saturated = total >= MAX_BYTES_PER_PULL;
+
+  let messages = reader.messages();
+
+  // hand over whatever arrived before the connection closed, as the
+  // disconnect will be noticed again on the next pull
+  if disconnected && messages.is_empty() {
+    return Err(ServoError::ServoDisconnected);
   }
+
+  Ok(messages)
 }
 
 // sends new VehicleState to servo. Refactor to use UDP
-pub(crate) fn push(socket: &UdpSocket, servo_socket: SocketAddr, state: &VehicleState) -> Result<usize> {
+pub fn push(socket: &UdpSocket, servo_socket: SocketAddr, servo_data_port: u16, state: &VehicleState) -> Result<usize> {
   
   let message = match postcard::to_allocvec(state) {
     Ok(v) => v,
     Err(e) => return Err(ServoError::DeserializationFailed(e)),
   };
 
-  match socket.send_to(&message, (servo_socket.ip(), SERVO_DATA_PORT)) {
+  match socket.send_to(&message, (servo_socket.ip(), servo_data_port)) {
     Ok(s) => Ok(s),
     Err(e) => Err(ServoError::TransportFailed(e)),
   }
-}
\ No newline at end of file
+}
+
+/// Sends Servo a status report on its status port.
+pub fn report(socket: &UdpSocket, servo_socket: SocketAddr, servo_status_port: u16, message: &StatusMessage) -> Result<usize> {
+  let message = postcard::to_allocvec(message).map_err(ServoError::DeserializationFailed)?;
+
+  socket
+    .send_to(&message, (servo_socket.ip(), servo_status_port))
+    .map_err(ServoError::TransportFailed)
+}
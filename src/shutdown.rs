@@ -0,0 +1,92 @@
+use std::{fs, io::{self, Write}, net::UdpSocket, path::Path, process::ExitCode, sync::{atomic::{AtomicBool, Ordering}, Arc}};
+use common::sequence::{MMAP_PATH, SOCKET_PATH};
+use signal_hook::{consts::{SIGINT, SIGTERM}, flag};
+use crate::{device::Devices, recorder::Recorder, sequence::Sequences};
+
+/// The files that mmap-sync creates next to `MMAP_PATH` to hold the shared
+/// VehicleState and the index of the currently readable copy.
+const MMAP_SUFFIXES: [&str; 4] = ["", "_state", "_data_0", "_data_1"];
+
+/// Set once SIGINT or SIGTERM has been received and the FC should stop.
+#[derive(Clone)]
+pub struct Shutdown(Arc<AtomicBool>);
+
+impl Shutdown {
+  /// Installs handlers for SIGINT and SIGTERM. The first signal requests a
+  /// graceful shutdown, while a second one terminates the process immediately
+  /// in case the graceful shutdown hangs.
+  pub fn register() -> io::Result<Self> {
+    let requested = Arc::new(AtomicBool::new(false));
+
+    for signal in [SIGINT, SIGTERM] {
+      flag::register_conditional_shutdown(signal, 1, Arc::clone(&requested))?;
+      flag::register(signal, Arc::clone(&requested))?;
+    }
+
+    Ok(Shutdown(requested))
+  }
+
+  pub fn is_requested(&self) -> bool {
+    self.0.load(Ordering::Relaxed)
+  }
+}
+
+/// Removes the sequence command socket if a previous run left it behind, as
+/// binding to an existing path fails.
+pub fn remove_stale_socket() -> io::Result<()> {
+  remove_if_exists(SOCKET_PATH)
+}
+
+/// Puts the vehicle in a safe state and releases every resource held by the
+/// FC. Returns the status that the process should exit with.
+pub fn stop(socket: &UdpSocket, devices: &Devices, sequences: &mut Sequences, recorder: &mut Recorder) -> ExitCode {
+  println!("\nShutting down...");
+  let mut clean = true;
+
+  devices.send_sam_safe_valves(socket, recorder);
+  println!("Sent safe valves command to all SAMs.");
+
+  for (name, sequence) in sequences.iter_mut() {
+    if let Ok(Some(_)) = sequence.try_wait() {
+      continue;
+    }
+
+    if let Err(e) = sequence.kill().and_then(|_| sequence.wait()) {
+      eprintln!("Couldn't kill sequence '{name}': {e}");
+      clean = false;
+    } else {
+      println!("Killed sequence '{name}'.");
+    }
+  }
+
+  if let Err(e) = remove_if_exists(SOCKET_PATH) {
+    eprintln!("Couldn't remove the sequence command socket at '{SOCKET_PATH}': {e}");
+    clean = false;
+  }
+
+  for suffix in MMAP_SUFFIXES {
+    let path = format!("{MMAP_PATH}{suffix}");
+
+    if let Err(e) = remove_if_exists(&path) {
+      eprintln!("Couldn't remove the shared VehicleState file at '{path}': {e}");
+      clean = false;
+    }
+  }
+
+  println!("Shutdown complete.");
+  let _ = io::stdout().flush();
+  let _ = io::stderr().flush();
+
+  if clean {
+    ExitCode::SUCCESS
+  } else {
+    ExitCode::FAILURE
+  }
+}
+
+fn remove_if_exists(path: impl AsRef<Path>) -> io::Result<()> {
+  match fs::remove_file(path) {
+    Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+    _ => Ok(()),
+  }
+}
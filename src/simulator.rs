@@ -0,0 +1,442 @@
+//! Simulated SAM, AHRS and BMS boards which speak the same UDP protocol as the
+//! real hardware, so that the FC can be exercised end to end on one machine.
+//!
+//! Every board binds its own loopback address, as the FC sends commands to a
+//! fixed port on the board's IP address.
+
+use std::{borrow::Cow, collections::HashMap, fmt, io, net::{IpAddr, SocketAddr, UdpSocket}, time::{Duration, Instant, SystemTime, UNIX_EPOCH}};
+use common::comm::{ahrs, bms, flight::DataMessage, sam::{self, ChannelType, SamControlMessage}, VehicleState};
+use serde::Deserialize;
+use crate::clock::{Clock, SystemClock};
+
+/// How often a board that isn't connected re-sends its identity to the FC.
+const IDENTITY_RATE: Duration = Duration::from_millis(500);
+
+/// How long a board waits for a heartbeat before it considers the FC lost
+/// and goes back to handshaking.
+const HEARTBEAT_TIME_TO_LIVE: Duration = Duration::from_secs(1);
+
+/// The voltage and current measured on a powered valve channel.
+const POWERED_VALVE: (f64, f64) = (24.0, 0.5);
+
+/// The voltage and current measured on an unpowered valve channel.
+const UNPOWERED_VALVE: (f64, f64) = (0.1, 0.0);
+
+/// Describes every simulated board, read from a TOML file.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SimulatorConfig {
+  /// Where the FC listens for board telemetry.
+  pub fc_address: SocketAddr,
+
+  /// The port that the FC sends board commands to.
+  pub command_port: u16,
+
+  pub boards: Vec<BoardConfig>,
+}
+
+impl Default for SimulatorConfig {
+  fn default() -> Self {
+    let board = |id: &str, address: [u8; 4]| BoardConfig {
+      id: id.to_string(),
+      address: IpAddr::from(address),
+      rate: Duration::from_millis(10),
+      sensors: Vec::new(),
+      valves: Vec::new(),
+      faults: Faults::default(),
+    };
+
+    let mut sam = board("sam-01", [127, 0, 0, 2]);
+    sam.sensors.push(Sensor { channel: 1, channel_type: ChannelType::CurrentLoop, value: 2.4, noise: 0.01 });
+    sam.valves.push(1);
+
+    SimulatorConfig {
+      fc_address: SocketAddr::from(([127, 0, 0, 1], 4573)),
+      command_port: 8378,
+      boards: vec![sam, board("ahrs-01", [127, 0, 0, 3]), board("bms-01", [127, 0, 0, 4])],
+    }
+  }
+}
+
+/// A single simulated board. Whether it behaves as a SAM, AHRS or BMS is
+/// determined by the prefix of its ID, as it is on the FC.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BoardConfig {
+  pub id: String,
+
+  /// The IP address that the board binds to, which must be unique.
+  pub address: IpAddr,
+
+  /// How often the board sends data.
+  #[serde(rename = "rate_ms", deserialize_with = "milliseconds", default = "default_rate")]
+  pub rate: Duration,
+
+  /// The sensor channels reported by a SAM.
+  #[serde(default)]
+  pub sensors: Vec<Sensor>,
+
+  /// The valve channels of a SAM, which report a voltage and current that
+  /// follow the commands sent by the FC.
+  #[serde(default)]
+  pub valves: Vec<u32>,
+
+  #[serde(default)]
+  pub faults: Faults,
+}
+
+/// A SAM channel reporting a constant value with uniform noise.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Sensor {
+  pub channel: u32,
+  pub channel_type: ChannelType,
+  pub value: f64,
+  #[serde(default)]
+  pub noise: f64,
+}
+
+/// Network faults injected into a board's traffic.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Faults {
+  /// The probability, from 0 to 1, that an outgoing packet is dropped.
+  pub packet_loss: f64,
+
+  /// How long outgoing packets are held before being sent.
+  #[serde(rename = "latency_ms", deserialize_with = "milliseconds")]
+  pub latency: Duration,
+
+  /// How long after starting the board drops off the network, if ever.
+  #[serde(rename = "disconnect_after_ms", deserialize_with = "optional_milliseconds")]
+  pub disconnect_after: Option<Duration>,
+
+  /// How long the board stays off the network once disconnected.
+  #[serde(rename = "disconnect_for_ms", deserialize_with = "milliseconds")]
+  pub disconnect_for: Duration,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Kind {
+  Sam,
+  Ahrs,
+  Bms,
+}
+
+/// A running simulated board. Like the FC, it reads the time from its clock,
+/// so a `ManualClock` controls when it sends data and when faults begin.
+pub struct SimulatedBoard<C: Clock = SystemClock> {
+  config: BoardConfig,
+  clock: C,
+  kind: Kind,
+  fc_address: SocketAddr,
+  data_socket: UdpSocket,
+  command_socket: UdpSocket,
+  connected: bool,
+  last_heartbeat: Instant,
+  last_identity: Option<Instant>,
+  last_data: Instant,
+  started: Instant,
+  powered: HashMap<u32, bool>,
+  outgoing: Vec<(Instant, Vec<u8>)>,
+  rng: XorShift,
+}
+
+impl<C: Clock> SimulatedBoard<C> {
+  pub fn new(config: BoardConfig, fc_address: SocketAddr, command_port: u16, clock: C) -> Result<Self> {
+    let kind = if config.id.starts_with("sam") {
+      Kind::Sam
+    } else if config.id.starts_with("ahrs") {
+      Kind::Ahrs
+    } else if config.id.starts_with("bms") {
+      Kind::Bms
+    } else {
+      return Err(Error::UnknownBoard(config.id));
+    };
+
+    let data_socket = UdpSocket::bind((config.address, 0))?;
+    data_socket.set_nonblocking(true)?;
+    let command_socket = UdpSocket::bind((config.address, command_port))?;
+    command_socket.set_nonblocking(true)?;
+
+    let seed = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos() as u64;
+    let powered = config.valves.iter().map(|&channel| (channel, false)).collect();
+    let now = clock.now();
+
+    Ok(SimulatedBoard {
+      clock,
+      kind,
+      fc_address,
+      data_socket,
+      command_socket,
+      connected: false,
+      last_heartbeat: now,
+      last_identity: None,
+      last_data: now,
+      started: now,
+      powered,
+      outgoing: Vec::new(),
+      rng: XorShift(seed.wrapping_add(config.id.bytes().map(u64::from).sum()) | 1),
+      config,
+    })
+  }
+
+  pub fn id(&self) -> &str {
+    &self.config.id
+  }
+
+  /// Whether the board has completed its handshake with the FC and is still
+  /// receiving heartbeats.
+  pub fn is_connected(&self) -> bool {
+    self.connected
+  }
+
+  /// Whether the given valve channel is currently powered.
+  pub fn is_powered(&self, channel: u32) -> Option<bool> {
+    self.powered.get(&channel).copied()
+  }
+
+  /// Handles everything received from the FC and sends whatever is due. Should
+  /// be called in a loop at a rate well above the board's data rate.
+  pub fn step(&mut self) -> Result<()> {
+    let now = self.clock.now();
+
+    if self.is_off_network(now) {
+      // drain the sockets so that nothing is handled once back online
+      let mut buf = [0; 1024];
+      while self.data_socket.recv_from(&mut buf).is_ok() {}
+      while self.command_socket.recv_from(&mut buf).is_ok() {}
+      self.outgoing.clear();
+      return Ok(());
+    }
+
+    self.receive_from_fc(now)?;
+    self.receive_commands()?;
+
+    if self.connected && now.duration_since(self.last_heartbeat) > HEARTBEAT_TIME_TO_LIVE {
+      println!("[{}] Lost the FC, handshaking again.", self.config.id);
+      self.connected = false;
+    }
+
+    if !self.connected {
+      if self.last_identity.is_none_or(|last| now.duration_since(last) >= IDENTITY_RATE) {
+        self.queue(&DataMessage::Identity(self.config.id.clone()), now)?;
+        self.last_identity = Some(now);
+      }
+    } else if now.duration_since(self.last_data) >= self.config.rate {
+      let message = self.data();
+      self.queue(&message, now)?;
+      self.last_data = now;
+    }
+
+    self.flush(now)
+  }
+
+  fn is_off_network(&self, now: Instant) -> bool {
+    let Some(after) = self.config.faults.disconnect_after else {
+      return false;
+    };
+
+    let elapsed = now.duration_since(self.started);
+    elapsed >= after && elapsed < after + self.config.faults.disconnect_for
+  }
+
+  fn receive_from_fc(&mut self, now: Instant) -> Result<()> {
+    let mut buf = [0; 1024];
+
+    loop {
+      let size = match self.data_socket.recv_from(&mut buf) {
+        Ok((size, _)) => size,
+        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+        Err(e) => return Err(Error::Transport(e)),
+      };
+
+      match postcard::from_bytes::<DataMessage>(&buf[..size]) {
+        Ok(DataMessage::Identity(fc)) => {
+          if !self.connected {
+            println!("[{}] Connected to {fc}.", self.config.id);
+          }
+
+          self.connected = true;
+          self.last_heartbeat = now;
+        },
+        Ok(DataMessage::FlightHeartbeat) => self.last_heartbeat = now,
+        Ok(other) => eprintln!("[{}] Received an unexpected message: {other:?}", self.config.id),
+        Err(e) => eprintln!("[{}] Couldn't decode a message from the FC: {e}", self.config.id),
+      };
+    }
+  }
+
+  fn receive_commands(&mut self) -> Result<()> {
+    let mut buf = [0; 1024];
+
+    loop {
+      let size = match self.command_socket.recv_from(&mut buf) {
+        Ok((size, _)) => size,
+        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+        Err(e) => return Err(Error::Transport(e)),
+      };
+
+      match self.kind {
+        Kind::Sam => match postcard::from_bytes::<SamControlMessage>(&buf[..size]) {
+          Ok(SamControlMessage::ActuateValve { channel, powered }) => {
+            match self.powered.get_mut(&channel) {
+              Some(state) => {
+                *state = powered;
+                println!("[{}] Valve channel {channel} is now {}.", self.config.id, if powered { "powered" } else { "unpowered" });
+              },
+              None => eprintln!("[{}] Received a command for nonexistent valve channel {channel}.", self.config.id),
+            };
+          },
+          Ok(SamControlMessage::SafeValves { .. }) => {
+            println!("[{}] Safing all valves.", self.config.id);
+            self.powered.values_mut().for_each(|p| *p = false);
+          },
+          Ok(other) => println!("[{}] Received {other:?}.", self.config.id),
+          Err(e) => eprintln!("[{}] Couldn't decode a SAM command: {e}", self.config.id),
+        },
+        Kind::Ahrs => match postcard::from_bytes::<ahrs::Command>(&buf[..size]) {
+          Ok(command) => println!("[{}] Received {command:?}.", self.config.id),
+          Err(e) => eprintln!("[{}] Couldn't decode an AHRS command: {e}", self.config.id),
+        },
+        Kind::Bms => match postcard::from_bytes::<bms::Command>(&buf[..size]) {
+          Ok(command) => println!("[{}] Received {command:?}.", self.config.id),
+          Err(e) => eprintln!("[{}] Couldn't decode a BMS command: {e}", self.config.id),
+        },
+      };
+    }
+  }
+
+  fn data(&mut self) -> DataMessage<'static> {
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs_f64();
+    let id = self.config.id.clone();
+
+    match self.kind {
+      Kind::Sam => {
+        let mut datapoints = Vec::new();
+
+        for sensor in &self.config.sensors {
+          datapoints.push(sam::DataPoint {
+            value: sensor.value + self.rng.noise(sensor.noise),
+            timestamp,
+            channel: sensor.channel,
+            channel_type: sensor.channel_type,
+          });
+        }
+
+        let mut valves: Vec<_> = self.powered.iter().map(|(&c, &p)| (c, p)).collect();
+        valves.sort_by_key(|(channel, _)| *channel);
+
+        for (channel, powered) in valves {
+          let (voltage, current) = if powered { POWERED_VALVE } else { UNPOWERED_VALVE };
+
+          datapoints.push(sam::DataPoint {
+            value: voltage + self.rng.noise(0.05),
+            timestamp,
+            channel,
+            channel_type: ChannelType::ValveVoltage,
+          });
+          datapoints.push(sam::DataPoint {
+            value: (current + self.rng.noise(0.005)).max(0.0),
+            timestamp,
+            channel,
+            channel_type: ChannelType::ValveCurrent,
+          });
+        }
+
+        DataMessage::Sam(id, Cow::Owned(datapoints))
+      },
+      Kind::Ahrs => DataMessage::Ahrs(id, Cow::Owned(vec![ahrs::DataPoint {
+        state: VehicleState::new().ahrs,
+        timestamp,
+      }])),
+      Kind::Bms => DataMessage::Bms(id, Cow::Owned(bms::DataPoint {
+        state: VehicleState::new().bms,
+        timestamp,
+      })),
+    }
+  }
+
+  /// Queues a message to be sent to the FC once the injected latency passes
+  /// after `now`, unless it's chosen to be lost.
+  fn queue(&mut self, message: &DataMessage, now: Instant) -> Result<()> {
+    if self.rng.chance(self.config.faults.packet_loss) {
+      return Ok(());
+    }
+
+    let serialized = postcard::to_allocvec(message).map_err(Error::Serialization)?;
+    self.outgoing.push((now + self.config.faults.latency, serialized));
+    Ok(())
+  }
+
+  fn flush(&mut self, now: Instant) -> Result<()> {
+    while let Some((release, _)) = self.outgoing.first() {
+      if *release > now {
+        break;
+      }
+
+      let (_, packet) = self.outgoing.remove(0);
+      self.data_socket.send_to(&packet, self.fc_address)?;
+    }
+
+    Ok(())
+  }
+}
+
+/// A small xorshift generator, which is plenty for noise and packet loss.
+struct XorShift(u64);
+
+impl XorShift {
+  fn next(&mut self) -> f64 {
+    self.0 ^= self.0 << 13;
+    self.0 ^= self.0 >> 7;
+    self.0 ^= self.0 << 17;
+    (self.0 >> 11) as f64 / (1u64 << 53) as f64
+  }
+
+  /// Uniform noise in the range [-amplitude, amplitude].
+  fn noise(&mut self, amplitude: f64) -> f64 {
+    (self.next() * 2.0 - 1.0) * amplitude
+  }
+
+  fn chance(&mut self, probability: f64) -> bool {
+    probability > 0.0 && self.next() < probability
+  }
+}
+
+fn default_rate() -> Duration {
+  Duration::from_millis(10)
+}
+
+fn milliseconds<'de, D: serde::Deserializer<'de>>(deserializer: D) -> std::result::Result<Duration, D::Error> {
+  u64::deserialize(deserializer).map(Duration::from_millis)
+}
+
+fn optional_milliseconds<'de, D: serde::Deserializer<'de>>(deserializer: D) -> std::result::Result<Option<Duration>, D::Error> {
+  Option::<u64>::deserialize(deserializer).map(|ms| ms.map(Duration::from_millis))
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug)]
+pub enum Error {
+  UnknownBoard(String),
+  Serialization(postcard::Error),
+  Transport(io::Error),
+}
+
+impl From<io::Error> for Error {
+  fn from(error: io::Error) -> Self {
+    Error::Transport(error)
+  }
+}
+
+impl fmt::Display for Error {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::UnknownBoard(id) => write!(f, "'{id}' doesn't start with sam, ahrs or bms."),
+      Self::Serialization(e) => write!(f, "Couldn't serialize a message: {e}"),
+      Self::Transport(e) => write!(f, "The simulated board's socket raised an error: {e}"),
+    }
+  }
+}
@@ -1,24 +1,25 @@
+use std::time::Instant;
 use common::comm::{ahrs, bms, flight::DataMessage, sam::{self, ChannelType, Unit}, CompositeValveState, Measurement, SensorType, ValveState, VehicleState};
-use crate::{Mappings, MMAP_GRACE_PERIOD};
+use crate::{calibration::Calibrations, valve::ValveEstimators, Mappings, MMAP_GRACE_PERIOD};
 use mmap_sync::synchronizer::{Synchronizer, SynchronizerError};
 
-pub(crate) fn sync_sequences(sync: &mut Synchronizer, state: &VehicleState) -> Result<(usize, bool), SynchronizerError> {
+pub fn sync_sequences(sync: &mut Synchronizer, state: &VehicleState) -> Result<(usize, bool), SynchronizerError> {
   sync.write(state, MMAP_GRACE_PERIOD)
 }
 
-pub(crate) trait Ingestible {
-  fn ingest(&self, vehicle_state: &mut VehicleState, mappings: &Mappings);
+pub trait Ingestible {
+  fn ingest(&self, vehicle_state: &mut VehicleState, mappings: &Mappings, calibrations: &Calibrations, valves: &mut ValveEstimators, now: Instant);
 }
 
 impl<'a> Ingestible for DataMessage<'a> {
-  fn ingest(&self, vehicle_state: &mut VehicleState, mappings: &Mappings) {
+  fn ingest(&self, vehicle_state: &mut VehicleState, mappings: &Mappings, calibrations: &Calibrations, valves: &mut ValveEstimators, now: Instant) {
     match self {
       DataMessage::Sam(id, datapoints) => {
           if !id.starts_with("sam") {
             println!("Detected a SAM data message without a SAM signature.");
           }
           
-          process_sam_data(id, vehicle_state, datapoints.to_vec(), mappings)
+          process_sam_data(id, vehicle_state, datapoints.to_vec(), mappings, calibrations, valves, now)
       },
       DataMessage::Ahrs(id, datapoints) => {
           if !id.starts_with("ahrs") {
@@ -39,30 +40,45 @@ impl<'a> Ingestible for DataMessage<'a> {
   }
 }
 
-pub(crate) fn process_bms_data(state: &mut VehicleState, datapoint: bms::DataPoint) {
+pub fn process_bms_data(state: &mut VehicleState, datapoint: bms::DataPoint) {
   state.bms = datapoint.state;
 }
 
-pub(crate) fn process_ahrs_data(state: &mut VehicleState, datapoints: Vec<ahrs::DataPoint>) {
+pub fn process_ahrs_data(state: &mut VehicleState, datapoints: Vec<ahrs::DataPoint>) {
   if let Some(data) = datapoints.last() {
     state.ahrs = data.state;
   }
 }
 
-// TODO: Optimize this function?
-pub(crate) fn process_sam_data(board_id: &str, state: &mut VehicleState, datapoints: Vec<sam::DataPoint>, mappings: &Mappings) {
+pub fn process_sam_data(board_id: &str, state: &mut VehicleState, datapoints: Vec<sam::DataPoint>, mappings: &Mappings, calibrations: &Calibrations, valves: &mut ValveEstimators, now: Instant) {
   for data_point in datapoints {
-    for mapping in mappings {
-      let corresponds = data_point.channel == mapping.channel
-        && mapping.sensor_type.channel_types().contains(&data_point.channel_type)
-        && board_id == mapping.board_id;
+    for mapping in mappings.for_channel(board_id, data_point.channel, &data_point.channel_type) {
+      let mut text_id = mapping.text_id.clone();
+
+      // configured calibrations take the place of the formulas below. a value
+      // that can't be calibrated is skipped rather than published raw, as the
+      // formulas would label it with the wrong unit
+      let calibration = match mapping.sensor_type {
+        SensorType::Valve => None,
+        _ => calibrations.get(&text_id),
+      };
+
+      if let Some(calibration) = calibration {
+        let Some(measurement) = calibration.apply(data_point.value, &state.sensor_readings) else {
+          continue;
+        };
 
-      if !corresponds {
+        // as with the formulas, only pressures and loads are zeroed by the
+        // mapping's offset
+        let value = match mapping.sensor_type {
+          SensorType::Pt | SensorType::LoadCell => measurement.value - mapping.calibrated_offset,
+          _ => measurement.value,
+        };
+
+        set_reading(state, text_id, Measurement { value, ..measurement });
         continue;
       }
 
-      let mut text_id = mapping.text_id.clone();
-
       let measurement = match mapping.sensor_type {
         SensorType::RailVoltage => Measurement {
           value: data_point.value,
@@ -153,11 +169,13 @@ pub(crate) fn process_sam_data(board_id: &str, state: &mut VehicleState, datapoi
             }
           };
 
-          let actual_state = estimate_valve_state(
+          let actual_state = valves.estimate(
+            &mapping.text_id,
             voltage,
             current,
             mapping.powered_threshold,
             mapping.normally_closed,
+            now,
           );
 
           if let Some(existing) =
@@ -178,53 +196,16 @@ pub(crate) fn process_sam_data(board_id: &str, state: &mut VehicleState, datapoi
         }
       };
 
-      // replace item without cloning string if already present
-      if let Some(existing) = state.sensor_readings.get_mut(&text_id) {
-        *existing = measurement;
-      } else {
-        state.sensor_readings.insert(text_id, measurement);
-      }
+      set_reading(state, text_id, measurement);
     }
   }
 }
 
-/// Estimates the state of a valve given its voltage, current, and the current
-/// threshold at which it is considered powered.
-fn estimate_valve_state(
-  voltage: f64,
-  current: f64,
-  powered_threshold: Option<f64>,
-  normally_closed: Option<bool>,
-) -> ValveState {
-  // calculate the actual state of the valve, assuming that it's normally closed
-  let mut estimated = match powered_threshold {
-    Some(powered) => {
-      if current < powered {
-        // valve is unpowered
-        if voltage < 4.0 {
-          ValveState::Closed
-        } else {
-          ValveState::Disconnected
-        }
-      } else {
-        // valve is powered
-        if voltage < 20.0 {
-          ValveState::Fault
-        } else {
-          ValveState::Open
-        }
-      }
-    }
-    None => ValveState::Fault,
-  };
-
-  if normally_closed == Some(false) {
-    estimated = match estimated {
-      ValveState::Open => ValveState::Closed,
-      ValveState::Closed => ValveState::Open,
-      other => other,
-    };
+fn set_reading(state: &mut VehicleState, text_id: String, measurement: Measurement) {
+  // replace item without cloning string if already present
+  if let Some(existing) = state.sensor_readings.get_mut(&text_id) {
+    *existing = measurement;
+  } else {
+    state.sensor_readings.insert(text_id, measurement);
   }
-
-  estimated
-}
\ No newline at end of file
+}
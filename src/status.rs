@@ -0,0 +1,124 @@
+//! Reports about the FC's own view of the vehicle which don't fit in a
+//! VehicleState, such as alarms. They are sent to Servo's status port as
+//! postcard-serialized datagrams and recorded alongside everything else.
+
+use common::comm::ValveState;
+use serde::{Deserialize, Serialize};
+use crate::{config::{CommsLossAction, SafingAction}, sequence::{OutputLine, SequenceStatus}};
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub enum StatusMessage {
+  /// A valve hasn't reached its commanded state within its settle time.
+  ValveMismatch {
+    valve: String,
+    commanded: ValveState,
+    actual: ValveState,
+  },
+
+  /// A valve that was previously reported as mismatched has either reached
+  /// its commanded state or been commanded again.
+  ValveMismatchCleared {
+    valve: String,
+  },
+
+  /// A sensor has been out of its limits for longer than its persistence
+  /// window, and the action has been taken.
+  RedlineTripped {
+    text_id: String,
+    value: f64,
+    min: Option<f64>,
+    max: Option<f64>,
+    action: SafingAction,
+  },
+
+  /// A sensor that tripped its redline is back within its limits.
+  RedlineCleared {
+    text_id: String,
+  },
+
+  /// A board has been silent for longer than its time to live, and the
+  /// action has been taken.
+  BoardLost {
+    board_id: String,
+    action: CommsLossAction,
+  },
+
+  /// A board that was lost is sending data again.
+  BoardRestored {
+    board_id: String,
+  },
+
+  /// Servo has been silent for `silent_ms`, reaching a stage of Servo loss
+  /// whose action has been taken. Silence is measured from when the
+  /// connection was last known to be alive, so it includes the few seconds of
+  /// unanswered keepalives it takes to notice a Servo that vanished without
+  /// closing it.
+  ServoLost {
+    silent_ms: u64,
+    action: SafingAction,
+  },
+
+  /// Servo is connected again after reaching at least one stage of Servo
+  /// loss.
+  ServoRestored,
+
+  /// The versions of the mappings and abort sequence that the FC is using,
+  /// sent whenever Servo connects and whenever either changes. A version is
+  /// the CRC-32 of the postcard serialization, and is `None` if there are no
+  /// mappings or no abort sequence.
+  Versions {
+    mappings: Option<u32>,
+    abort_sequence: Option<u32>,
+  },
+
+  /// A sequence has started or stopped. The status of every known sequence
+  /// is also sent whenever Servo connects.
+  Sequence(SequenceStatus),
+
+  /// A sequence actuated a valve owned by another sequence. `sender` is
+  /// `None` if the command didn't come from a known sequence.
+  ValveConflict {
+    valve: String,
+    owner: String,
+    sender: Option<String>,
+    rejected: bool,
+  },
+
+  /// A valve was taken over by a sequence, or released if `owner` is `None`.
+  ValveOwnerChanged {
+    valve: String,
+    owner: Option<String>,
+    previous: Option<String>,
+  },
+
+  /// A sequence sent more than `limit` commands within a second, so the rest
+  /// are being dropped. Sent at most once per second per sequence.
+  SequenceRateLimited {
+    name: String,
+    limit: u32,
+  },
+
+  /// A line written by a sequence to its stdout or stderr.
+  SequenceOutput(OutputLine),
+
+  /// A trigger received from Servo wasn't registered, as its condition
+  /// couldn't be parsed or reads a sensor that isn't mapped.
+  TriggerRejected {
+    name: String,
+    reason: String,
+  },
+
+  /// Lines written by a sequence were dropped as they were written faster
+  /// than they could be forwarded. Sent at most once per control cycle per
+  /// sequence.
+  SequenceOutputDropped {
+    name: String,
+    lines: u64,
+  },
+
+  /// Mappings received from Servo define the given text IDs more than once.
+  /// Only the first definition of each is used.
+  DuplicateMappings {
+    text_ids: Vec<String>,
+  },
+}
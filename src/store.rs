@@ -0,0 +1,134 @@
+//! Keeps the mappings and abort sequence last uploaded by Servo on disk, so
+//! that a flight computer which restarts mid-test comes back up with them.
+//!
+//! Each is kept in its own file made of `MAGIC`, a little-endian u32 CRC-32 of
+//! the payload and the postcard-serialized payload itself. Files are replaced
+//! atomically by writing a temporary file, syncing it and renaming it over the
+//! old one, so a power loss leaves either the old or the new copy behind.
+//!
+//! The checksum doubles as the version of a copy reported to Servo: it's the
+//! CRC-32 of the postcard serialization of the mappings or sequence, which
+//! Servo can compute over its own copy to tell whether the two match.
+
+use std::{fmt, fs::{self, File}, io::{self, Write}};
+use common::comm::{NodeMapping, Sequence};
+use serde::{de::DeserializeOwned, Serialize};
+use crate::config::StoreConfig;
+
+/// Written at the start of every stored file so that a file from another
+/// version of the format is never mistaken for a valid one.
+pub const MAGIC: [u8; 8] = *b"FCSTO\0\0\x01";
+
+const MAPPINGS: &str = "mappings.bin";
+const ABORT_SEQUENCE: &str = "abort_sequence.bin";
+
+pub struct Store {
+  config: StoreConfig,
+}
+
+impl Store {
+  pub fn new(config: &StoreConfig) -> Self {
+    Store { config: config.clone() }
+  }
+
+  pub fn save_mappings(&self, mappings: &[NodeMapping]) -> Result<()> {
+    self.save(MAPPINGS, &mappings)
+  }
+
+  pub fn save_abort_sequence(&self, sequence: &Sequence) -> Result<()> {
+    self.save(ABORT_SEQUENCE, sequence)
+  }
+
+  /// The stored mappings, or `None` if none have been stored.
+  pub fn load_mappings(&self) -> Result<Option<Vec<NodeMapping>>> {
+    self.load(MAPPINGS)
+  }
+
+  /// The stored abort sequence, or `None` if none has been stored.
+  pub fn load_abort_sequence(&self) -> Result<Option<Sequence>> {
+    self.load(ABORT_SEQUENCE)
+  }
+
+  fn save<T: Serialize + ?Sized>(&self, name: &str, value: &T) -> Result<()> {
+    if !self.config.enabled {
+      return Ok(());
+    }
+
+    let payload = postcard::to_allocvec(value).map_err(Error::SerializationFailed)?;
+    let mut contents = Vec::with_capacity(MAGIC.len() + 4 + payload.len());
+    contents.extend_from_slice(&MAGIC);
+    contents.extend_from_slice(&crc32fast::hash(&payload).to_le_bytes());
+    contents.extend_from_slice(&payload);
+
+    fs::create_dir_all(&self.config.directory)?;
+    let path = self.config.directory.join(name);
+    let temporary = path.with_extension("tmp");
+
+    let mut file = File::create(&temporary)?;
+    file.write_all(&contents)?;
+    file.sync_all()?;
+    fs::rename(&temporary, &path)?;
+
+    // the rename itself only survives a power loss once the directory is synced
+    File::open(&self.config.directory)?.sync_all()?;
+    Ok(())
+  }
+
+  fn load<T: DeserializeOwned>(&self, name: &str) -> Result<Option<T>> {
+    if !self.config.enabled {
+      return Ok(None);
+    }
+
+    let contents = match fs::read(self.config.directory.join(name)) {
+      Ok(contents) => contents,
+      Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+      Err(e) => return Err(Error::Io(e)),
+    };
+
+    let Some((header, payload)) = contents.split_at_checked(MAGIC.len() + 4) else {
+      return Err(Error::Corrupted);
+    };
+
+    let checksum = u32::from_le_bytes([header[8], header[9], header[10], header[11]]);
+
+    if header[..MAGIC.len()] != MAGIC || crc32fast::hash(payload) != checksum {
+      return Err(Error::Corrupted);
+    }
+
+    postcard::from_bytes(payload)
+      .map(Some)
+      .map_err(Error::DeserializationFailed)
+  }
+}
+
+/// The version of mappings or a sequence that's reported to Servo.
+pub fn version<T: Serialize + ?Sized>(value: &T) -> Option<u32> {
+  postcard::to_allocvec(value).ok().map(|payload| crc32fast::hash(&payload))
+}
+
+type Result<T> = ::std::result::Result<T, Error>;
+
+#[derive(Debug)]
+pub enum Error {
+  SerializationFailed(postcard::Error),
+  DeserializationFailed(postcard::Error),
+  Corrupted,
+  Io(io::Error),
+}
+
+impl From<io::Error> for Error {
+  fn from(error: io::Error) -> Self {
+    Error::Io(error)
+  }
+}
+
+impl fmt::Display for Error {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::SerializationFailed(e) => write!(f, "Couldn't serialize the stored copy: {e}"),
+      Self::DeserializationFailed(e) => write!(f, "Couldn't deserialize the stored copy: {e}"),
+      Self::Corrupted => write!(f, "The stored copy is corrupted, as its header or checksum is invalid."),
+      Self::Io(e) => write!(f, "Couldn't access the stored copy: {e}"),
+    }
+  }
+}
@@ -0,0 +1,453 @@
+use std::{collections::HashMap, fmt, iter::Peekable, str::Chars, time::Instant};
+use common::comm::{SensorType, Sequence, Trigger, VehicleState};
+use crate::{sequence::{self, Sequences}, status::StatusMessage, Mappings};
+
+/// All triggers received from Servo, keyed by their name.
+pub type Triggers = HashMap<String, ArmedTrigger>;
+
+/// A trigger along with its parsed condition and the result of its last
+/// evaluation, which is needed to fire only when the condition becomes true.
+pub struct ArmedTrigger {
+  trigger: Trigger,
+  condition: Expression,
+  was_true: bool,
+  last_error: Option<String>,
+}
+
+/// Stores a trigger received from Servo, replacing any trigger of the same
+/// name. Triggers whose condition can't be parsed or reads a sensor that isn't
+/// mapped are rejected and reported, leaving any trigger of the same name in
+/// place.
+pub fn register(triggers: &mut Triggers, trigger: Trigger, mappings: &Mappings, reports: &mut Vec<StatusMessage>) {
+  let condition = match arm(&trigger.condition, mappings) {
+    Ok(c) => c,
+    Err(reason) => {
+      eprintln!("Rejected trigger '{}': {reason}", trigger.name);
+      reports.push(StatusMessage::TriggerRejected { name: trigger.name, reason });
+      return;
+    }
+  };
+
+  println!("Registered trigger '{}' with condition '{}'.", trigger.name, trigger.condition);
+
+  triggers.insert(trigger.name.clone(), ArmedTrigger {
+    trigger,
+    condition,
+    was_true: false,
+    last_error: None,
+  });
+}
+
+/// Parses a condition and checks that every sensor it reads is mapped, as a
+/// condition over an unknown sensor could never be evaluated.
+fn arm(condition: &str, mappings: &Mappings) -> Result<Expression, String> {
+  let condition = Expression::parse(condition).map_err(|e| e.to_string())?;
+
+  for text_id in condition.sensors() {
+    match mappings.get(text_id) {
+      Some(mapping) if matches!(mapping.sensor_type, SensorType::Valve) => {
+        return Err(format!("'{text_id}' is a valve, which has no reading. Use '{text_id}_V' or '{text_id}_I' instead."));
+      },
+      Some(_) => {},
+      None if is_valve_reading(text_id, mappings) => {},
+      None => return Err(format!("No sensor named '{text_id}' is mapped.")),
+    };
+  }
+
+  Ok(condition)
+}
+
+/// Whether the text ID names the voltage (`<valve>_V`) or current (`<valve>_I`)
+/// reading of a mapped valve.
+fn is_valve_reading(text_id: &str, mappings: &Mappings) -> bool {
+  let Some(valve) = text_id.strip_suffix("_V").or_else(|| text_id.strip_suffix("_I")) else {
+    return false;
+  };
+
+  mappings.get(valve).is_some_and(|mapping| matches!(mapping.sensor_type, SensorType::Valve))
+}
+
+/// Evaluates every trigger against the current vehicle state, executing the
+/// script of each active trigger whose condition went from false to true.
+pub fn check(triggers: &mut Triggers, state: &VehicleState, mappings: &Mappings, sequences: &mut Sequences, now: Instant) {
+  for armed in triggers.values_mut() {
+    let is_true = match armed.condition.evaluate(state) {
+      Ok(value) => {
+        armed.last_error = None;
+        value != 0.0
+      },
+      Err(e) => {
+        // only report an error once, as conditions are evaluated every cycle
+        if armed.last_error.as_ref() != Some(&e) {
+          eprintln!("Couldn't evaluate the condition of trigger '{}': {e}", armed.trigger.name);
+          armed.last_error = Some(e);
+        }
+
+        false
+      }
+    };
+
+    let rising_edge = is_true && !armed.was_true;
+    armed.was_true = is_true;
+
+    if !rising_edge || !armed.trigger.active {
+      continue;
+    }
+
+    println!("Trigger '{}' fired.", armed.trigger.name);
+
+    let sequence = Sequence {
+      name: armed.trigger.name.clone(),
+      script: armed.trigger.script.clone(),
+    };
+
+    sequence::execute(mappings, &sequence, sequences, now);
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Operator {
+  Add,
+  Subtract,
+  Multiply,
+  Divide,
+  Less,
+  LessEqual,
+  Greater,
+  GreaterEqual,
+  Equal,
+  NotEqual,
+}
+
+impl Operator {
+  fn apply(self, left: f64, right: f64) -> f64 {
+    let truth = |b: bool| if b { 1.0 } else { 0.0 };
+
+    match self {
+      Self::Add => left + right,
+      Self::Subtract => left - right,
+      Self::Multiply => left * right,
+      Self::Divide => left / right,
+      Self::Less => truth(left < right),
+      Self::LessEqual => truth(left <= right),
+      Self::Greater => truth(left > right),
+      Self::GreaterEqual => truth(left >= right),
+      Self::Equal => truth(left == right),
+      Self::NotEqual => truth(left != right),
+    }
+  }
+
+  fn is_comparison(self) -> bool {
+    !matches!(self, Self::Add | Self::Subtract | Self::Multiply | Self::Divide)
+  }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+  Number(f64),
+  Identifier(String),
+  Operator(Operator),
+  And,
+  Or,
+  Not,
+  OpenParen,
+  CloseParen,
+}
+
+/// A trigger condition, written as a Python expression over sensor readings,
+/// e.g. `PT1 > 500 and not (FU_TANK_RTD < 80)`.
+///
+/// Identifiers refer to the value of the sensor reading with the same text ID.
+/// Comparisons may be chained as in Python, and `True` and `False` are
+/// available as literals. Every value is a number, with zero being false.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expression {
+  Number(f64),
+  Sensor(String),
+  Negate(Box<Expression>),
+  Not(Box<Expression>),
+  And(Box<Expression>, Box<Expression>),
+  Or(Box<Expression>, Box<Expression>),
+  Binary(Operator, Box<Expression>, Box<Expression>),
+  /// A chain of comparisons such as `a < b <= c`, which holds only if every
+  /// adjacent pair holds.
+  Chain(Box<Expression>, Vec<(Operator, Expression)>),
+}
+
+impl Expression {
+  pub fn parse(source: &str) -> Result<Self, ParseError> {
+    let tokens = tokenize(source)?;
+    let mut parser = Parser { tokens, position: 0 };
+    let expression = parser.or()?;
+
+    if let Some(token) = parser.peek() {
+      return Err(ParseError::Unexpected(format!("{token:?}")));
+    }
+
+    Ok(expression)
+  }
+
+  /// Every sensor that the expression reads, in the order they appear.
+  pub fn sensors(&self) -> Vec<&str> {
+    match self {
+      Self::Number(_) => Vec::new(),
+      Self::Sensor(text_id) => vec![text_id.as_str()],
+      Self::Negate(e) | Self::Not(e) => e.sensors(),
+      Self::And(l, r) | Self::Or(l, r) | Self::Binary(_, l, r) => [l.sensors(), r.sensors()].concat(),
+      Self::Chain(first, rest) => {
+        let mut sensors = first.sensors();
+        sensors.extend(rest.iter().flat_map(|(_, e)| e.sensors()));
+        sensors
+      },
+    }
+  }
+
+  pub fn evaluate(&self, state: &VehicleState) -> Result<f64, String> {
+    let truth = |b: bool| if b { 1.0 } else { 0.0 };
+
+    let value = match self {
+      Self::Number(n) => *n,
+      Self::Sensor(text_id) => match state.sensor_readings.get(text_id) {
+        Some(measurement) => measurement.value,
+        None => return Err(format!("no reading for '{text_id}' has been received")),
+      },
+      Self::Negate(e) => -e.evaluate(state)?,
+      Self::Not(e) => truth(e.evaluate(state)? == 0.0),
+      Self::And(l, r) => truth(l.evaluate(state)? != 0.0 && r.evaluate(state)? != 0.0),
+      Self::Or(l, r) => truth(l.evaluate(state)? != 0.0 || r.evaluate(state)? != 0.0),
+      Self::Binary(op, l, r) => op.apply(l.evaluate(state)?, r.evaluate(state)?),
+      Self::Chain(first, rest) => {
+        let mut left = first.evaluate(state)?;
+
+        for (op, expression) in rest {
+          let right = expression.evaluate(state)?;
+
+          if op.apply(left, right) == 0.0 {
+            return Ok(0.0);
+          }
+
+          left = right;
+        }
+
+        1.0
+      },
+    };
+
+    Ok(value)
+  }
+}
+
+#[derive(Debug)]
+pub enum ParseError {
+  UnexpectedEnd,
+  Unexpected(String),
+  InvalidNumber(String),
+  UnknownCharacter(char),
+}
+
+impl fmt::Display for ParseError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::UnexpectedEnd => write!(f, "The condition ended unexpectedly."),
+      Self::Unexpected(token) => write!(f, "Encountered an unexpected {token}."),
+      Self::InvalidNumber(n) => write!(f, "'{n}' is not a valid number."),
+      Self::UnknownCharacter(c) => write!(f, "'{c}' is not allowed in a condition."),
+    }
+  }
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, ParseError> {
+  let mut tokens = Vec::new();
+  let mut chars: Peekable<Chars> = source.chars().peekable();
+
+  while let Some(&c) = chars.peek() {
+    if c.is_whitespace() {
+      chars.next();
+      continue;
+    }
+
+    if c.is_ascii_digit() || c == '.' {
+      let mut number = String::new();
+
+      while let Some(&c) = chars.peek() {
+        let is_exponent_sign = (c == '-' || c == '+') && number.ends_with(['e', 'E']);
+
+        if !(c.is_ascii_alphanumeric() || c == '.' || c == '_' || is_exponent_sign) {
+          break;
+        }
+
+        number.push(c);
+        chars.next();
+      }
+
+      let value = number.replace('_', "").parse::<f64>()
+        .map_err(|_| ParseError::InvalidNumber(number))?;
+
+      tokens.push(Token::Number(value));
+      continue;
+    }
+
+    if c.is_alphabetic() || c == '_' {
+      let mut word = String::new();
+
+      while let Some(&c) = chars.peek() {
+        if !(c.is_alphanumeric() || c == '_') {
+          break;
+        }
+
+        word.push(c);
+        chars.next();
+      }
+
+      tokens.push(match word.as_str() {
+        "and" => Token::And,
+        "or" => Token::Or,
+        "not" => Token::Not,
+        "True" => Token::Number(1.0),
+        "False" => Token::Number(0.0),
+        _ => Token::Identifier(word),
+      });
+      continue;
+    }
+
+    chars.next();
+    let followed_by_equals = chars.peek() == Some(&'=');
+
+    let token = match c {
+      '(' => Token::OpenParen,
+      ')' => Token::CloseParen,
+      '+' => Token::Operator(Operator::Add),
+      '-' => Token::Operator(Operator::Subtract),
+      '*' => Token::Operator(Operator::Multiply),
+      '/' => Token::Operator(Operator::Divide),
+      '<' if followed_by_equals => Token::Operator(Operator::LessEqual),
+      '<' => Token::Operator(Operator::Less),
+      '>' if followed_by_equals => Token::Operator(Operator::GreaterEqual),
+      '>' => Token::Operator(Operator::Greater),
+      '=' if followed_by_equals => Token::Operator(Operator::Equal),
+      '!' if followed_by_equals => Token::Operator(Operator::NotEqual),
+      c => return Err(ParseError::UnknownCharacter(c)),
+    };
+
+    let is_two_characters = matches!(
+      token,
+      Token::Operator(Operator::LessEqual | Operator::GreaterEqual | Operator::Equal | Operator::NotEqual)
+    );
+
+    if is_two_characters {
+      chars.next();
+    }
+
+    tokens.push(token);
+  }
+
+  Ok(tokens)
+}
+
+struct Parser {
+  tokens: Vec<Token>,
+  position: usize,
+}
+
+impl Parser {
+  fn peek(&self) -> Option<&Token> {
+    self.tokens.get(self.position)
+  }
+
+  fn next(&mut self) -> Result<Token, ParseError> {
+    let token = self.tokens.get(self.position).cloned().ok_or(ParseError::UnexpectedEnd)?;
+    self.position += 1;
+    Ok(token)
+  }
+
+  fn or(&mut self) -> Result<Expression, ParseError> {
+    let mut left = self.and()?;
+
+    while self.peek() == Some(&Token::Or) {
+      self.position += 1;
+      left = Expression::Or(Box::new(left), Box::new(self.and()?));
+    }
+
+    Ok(left)
+  }
+
+  fn and(&mut self) -> Result<Expression, ParseError> {
+    let mut left = self.not()?;
+
+    while self.peek() == Some(&Token::And) {
+      self.position += 1;
+      left = Expression::And(Box::new(left), Box::new(self.not()?));
+    }
+
+    Ok(left)
+  }
+
+  fn not(&mut self) -> Result<Expression, ParseError> {
+    if self.peek() == Some(&Token::Not) {
+      self.position += 1;
+      return Ok(Expression::Not(Box::new(self.not()?)));
+    }
+
+    self.comparison()
+  }
+
+  fn comparison(&mut self) -> Result<Expression, ParseError> {
+    let first = self.sum()?;
+    let mut rest = Vec::new();
+
+    while let Some(&Token::Operator(op)) = self.peek() {
+      if !op.is_comparison() {
+        break;
+      }
+
+      self.position += 1;
+      rest.push((op, self.sum()?));
+    }
+
+    if rest.is_empty() {
+      Ok(first)
+    } else {
+      Ok(Expression::Chain(Box::new(first), rest))
+    }
+  }
+
+  fn sum(&mut self) -> Result<Expression, ParseError> {
+    let mut left = self.product()?;
+
+    while let Some(&Token::Operator(op @ (Operator::Add | Operator::Subtract))) = self.peek() {
+      self.position += 1;
+      left = Expression::Binary(op, Box::new(left), Box::new(self.product()?));
+    }
+
+    Ok(left)
+  }
+
+  fn product(&mut self) -> Result<Expression, ParseError> {
+    let mut left = self.unary()?;
+
+    while let Some(&Token::Operator(op @ (Operator::Multiply | Operator::Divide))) = self.peek() {
+      self.position += 1;
+      left = Expression::Binary(op, Box::new(left), Box::new(self.unary()?));
+    }
+
+    Ok(left)
+  }
+
+  fn unary(&mut self) -> Result<Expression, ParseError> {
+    match self.next()? {
+      Token::Operator(Operator::Subtract) => Ok(Expression::Negate(Box::new(self.unary()?))),
+      Token::Operator(Operator::Add) => self.unary(),
+      Token::Number(n) => Ok(Expression::Number(n)),
+      Token::Identifier(text_id) => Ok(Expression::Sensor(text_id)),
+      Token::OpenParen => {
+        let inner = self.or()?;
+
+        match self.next()? {
+          Token::CloseParen => Ok(inner),
+          token => Err(ParseError::Unexpected(format!("{token:?}"))),
+        }
+      },
+      token => Err(ParseError::Unexpected(format!("{token:?}"))),
+    }
+  }
+}
@@ -0,0 +1,246 @@
+use std::{collections::HashMap, time::{Duration, Instant}};
+use common::comm::{ValveState, VehicleState};
+use crate::{config::{Config, ValveConfig}, status::StatusMessage};
+
+/// Estimates the actual state of every valve from the voltage and current
+/// measured on its SAM channel.
+///
+/// Each valve remembers its previous estimate so that hysteresis can keep a
+/// reading hovering around a threshold from flipping the estimate back and
+/// forth, and so that a new estimate is only reported once it has held for
+/// the valve's debounce time.
+pub struct ValveEstimators {
+  configs: HashMap<String, ValveConfig>,
+  default: ValveConfig,
+  estimators: HashMap<String, Estimator>,
+}
+
+struct Estimator {
+  /// Whether the current was last found to be above the powered threshold.
+  powered: bool,
+
+  /// The last estimate, before debouncing and before accounting for normally
+  /// open valves.
+  last: ValveState,
+
+  /// An estimate that differs from the reported one, along with when it was
+  /// first made.
+  pending: Option<(ValveState, Instant)>,
+
+  /// The estimate that's reported as the valve's actual state.
+  reported: ValveState,
+}
+
+impl ValveEstimators {
+  pub fn new(config: &Config) -> Self {
+    ValveEstimators {
+      configs: config.valves.clone(),
+      default: ValveConfig::default(),
+      estimators: HashMap::new(),
+    }
+  }
+
+  /// Estimates the state of a valve given its latest voltage and current, and
+  /// the current threshold and normal state from its mapping. Returns the
+  /// state that should be reported, which only changes once a new estimate
+  /// has held for the valve's debounce time.
+  pub fn estimate(
+    &mut self,
+    text_id: &str,
+    voltage: f64,
+    current: f64,
+    powered_threshold: Option<f64>,
+    normally_closed: Option<bool>,
+    now: Instant,
+  ) -> ValveState {
+    let config = self.configs.get(text_id).unwrap_or(&self.default);
+
+    if !self.estimators.contains_key(text_id) {
+      self.estimators.insert(text_id.to_string(), Estimator::new());
+    }
+
+    let estimator = self.estimators
+      .get_mut(text_id)
+      .expect("The estimator was just inserted if it didn't exist.");
+
+    let mut estimated = estimator.classify(config, voltage, current, powered_threshold);
+
+    if normally_closed == Some(false) {
+      estimated = match estimated {
+        ValveState::Open => ValveState::Closed,
+        ValveState::Closed => ValveState::Open,
+        other => other,
+      };
+    }
+
+    estimator.debounce(estimated, config.debounce, now)
+  }
+}
+
+impl Estimator {
+  fn new() -> Self {
+    Estimator {
+      powered: false,
+      last: ValveState::Undetermined,
+      pending: None,
+      reported: ValveState::Undetermined,
+    }
+  }
+
+  /// Classifies the readings assuming that the valve is normally closed. Each
+  /// threshold is shifted by its hysteresis in whichever direction favors the
+  /// previous classification.
+  fn classify(&mut self, config: &ValveConfig, voltage: f64, current: f64, powered_threshold: Option<f64>) -> ValveState {
+    let Some(threshold) = config.powered_current.or(powered_threshold) else {
+      self.last = ValveState::Fault;
+      return self.last;
+    };
+
+    self.powered = if self.powered {
+      current >= threshold - config.current_hysteresis
+    } else {
+      current >= threshold
+    };
+
+    let hysteresis = config.voltage_hysteresis;
+
+    self.last = if self.powered {
+      let faulted = if self.last == ValveState::Fault {
+        voltage < config.powered_voltage
+      } else {
+        voltage < config.powered_voltage - hysteresis
+      };
+
+      if faulted { ValveState::Fault } else { ValveState::Open }
+    } else {
+      let disconnected = if self.last == ValveState::Disconnected {
+        voltage >= config.unpowered_voltage - hysteresis
+      } else {
+        voltage >= config.unpowered_voltage
+      };
+
+      if disconnected { ValveState::Disconnected } else { ValveState::Closed }
+    };
+
+    self.last
+  }
+
+  fn debounce(&mut self, estimated: ValveState, debounce: Duration, now: Instant) -> ValveState {
+    if estimated == self.reported {
+      self.pending = None;
+      return self.reported;
+    }
+
+    let since = match self.pending {
+      Some((pending, since)) if pending == estimated => since,
+      _ => {
+        self.pending = Some((estimated, now));
+        now
+      },
+    };
+
+    if now.duration_since(since) >= debounce {
+      self.reported = estimated;
+      self.pending = None;
+    }
+
+    self.reported
+  }
+}
+
+/// Flags valves whose actual state doesn't reach their commanded state within
+/// the valve's settle time.
+///
+/// The settle time restarts whenever a valve is commanded, even to the state
+/// it was already commanded to, and whenever its actual state matches the
+/// commanded state, so only a mismatch that lasts for the whole settle time is
+/// flagged. Each mismatch is flagged once, and is cleared when the valve
+/// reaches its commanded state or is commanded again.
+pub struct MismatchDetector {
+  configs: HashMap<String, ValveConfig>,
+  default: ValveConfig,
+  trackers: HashMap<String, Tracker>,
+}
+
+struct Tracker {
+  commanded: ValveState,
+
+  /// When the valve was last commanded or last matched its commanded state.
+  since: Instant,
+  flagged: bool,
+}
+
+impl MismatchDetector {
+  pub fn new(config: &Config) -> Self {
+    MismatchDetector {
+      configs: config.valves.clone(),
+      default: ValveConfig::default(),
+      trackers: HashMap::new(),
+    }
+  }
+
+  /// Restarts the settle time of a valve that was just commanded, clearing its
+  /// mismatch if it was flagged. A valve that hasn't been checked yet starts
+  /// its settle time once it is.
+  pub fn commanded(&mut self, valve: &str, now: Instant, reports: &mut Vec<StatusMessage>) {
+    let Some(tracker) = self.trackers.get_mut(valve) else {
+      return;
+    };
+
+    if tracker.flagged {
+      reports.push(StatusMessage::ValveMismatchCleared { valve: valve.to_string() });
+    }
+
+    tracker.since = now;
+    tracker.flagged = false;
+  }
+
+  /// Compares the commanded and actual state of every valve, adding a report
+  /// to `reports` whenever a mismatch is flagged or cleared. Returns whether a
+  /// valve which should abort on a mismatch was just flagged.
+  pub fn check(&mut self, state: &VehicleState, now: Instant, reports: &mut Vec<StatusMessage>) -> bool {
+    let mut should_abort = false;
+
+    for (valve, composite) in &state.valve_states {
+      // valves that have never been commanded have nothing to match
+      if composite.commanded == ValveState::Undetermined {
+        continue;
+      }
+
+      if !self.trackers.contains_key(valve) {
+        let tracker = Tracker { commanded: composite.commanded, since: now, flagged: false };
+        self.trackers.insert(valve.clone(), tracker);
+      }
+
+      let tracker = self.trackers
+        .get_mut(valve)
+        .expect("The tracker was just inserted if it didn't exist.");
+
+      if tracker.commanded != composite.commanded || composite.actual == composite.commanded {
+        if tracker.flagged {
+          reports.push(StatusMessage::ValveMismatchCleared { valve: valve.clone() });
+        }
+
+        tracker.commanded = composite.commanded;
+        tracker.since = now;
+        tracker.flagged = false;
+        continue;
+      }
+
+      let config = self.configs.get(valve).unwrap_or(&self.default);
+
+      if !tracker.flagged && now.duration_since(tracker.since) >= config.settle_time {
+        tracker.flagged = true;
+        should_abort |= config.abort_on_mismatch;
+
+        reports.push(StatusMessage::ValveMismatch {
+          valve: valve.clone(),
+          commanded: composite.commanded,
+          actual: composite.actual,
+        });
+      }
+    }
+
+    should_abort
+  }
+}
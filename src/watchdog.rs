@@ -0,0 +1,75 @@
+use std::time::{Duration, Instant};
+use crate::{config::{Config, SafingAction, ServoLossStage}, status::StatusMessage};
+
+/// Escalates through the configured stages of Servo loss as Servo stays
+/// silent, ending with safing valves after `servo_to_fc_time_to_live`.
+///
+/// Every stage is taken once per loss of Servo, in order of when it's
+/// reached, and the stages start over once Servo reconnects.
+pub struct ServoWatchdog {
+  /// Sorted by `after`, with stages of equal `after` in configured order.
+  stages: Vec<ServoLossStage>,
+
+  /// How many stages have been taken since Servo was last connected.
+  reached: usize,
+  time_to_live: Duration,
+}
+
+impl ServoWatchdog {
+  pub fn new(config: &Config) -> Self {
+    let mut stages = config.servo_loss.clone();
+
+    stages.push(ServoLossStage {
+      after: config.servo_to_fc_time_to_live,
+      action: SafingAction::SafeValves,
+    });
+
+    stages.sort_by_key(|stage| stage.after);
+
+    ServoWatchdog { stages, reached: 0, time_to_live: config.servo_to_fc_time_to_live }
+  }
+
+  /// Takes every stage that Servo has been silent for longer than, adding a
+  /// report to `reports` for each. Returns the actions of the stages that
+  /// were just reached.
+  pub fn check(&mut self, silent_for: Duration, reports: &mut Vec<StatusMessage>) -> Vec<SafingAction> {
+    let mut actions = Vec::new();
+
+    while let Some(stage) = self.stages.get(self.reached) {
+      if silent_for <= stage.after {
+        break;
+      }
+
+      reports.push(StatusMessage::ServoLost {
+        silent_ms: silent_for.as_millis() as u64,
+        action: stage.action,
+      });
+
+      actions.push(stage.action);
+      self.reached += 1;
+    }
+
+    actions
+  }
+
+  /// Starts over from the first stage, reporting that Servo is back if any
+  /// stage had been reached.
+  pub fn reset(&mut self, reports: &mut Vec<StatusMessage>) {
+    if self.reached > 0 {
+      reports.push(StatusMessage::ServoRestored);
+    }
+
+    self.reached = 0;
+  }
+
+  /// When the next stage will be reached if nothing is heard from Servo
+  /// after `last_received`.
+  pub fn deadline(&self, last_received: Instant) -> Option<Instant> {
+    self.stages.get(self.reached).map(|stage| last_received + stage.after)
+  }
+
+  /// Whether Servo has been silent for longer than `servo_to_fc_time_to_live`.
+  pub fn is_lost(&self) -> bool {
+    self.stages[..self.reached].iter().any(|stage| stage.after >= self.time_to_live)
+  }
+}
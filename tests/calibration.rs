@@ -0,0 +1,143 @@
+use std::{collections::HashMap, time::Instant};
+use common::comm::{sam::{ChannelType, DataPoint, Unit}, Measurement, NodeMapping, SensorType, VehicleState};
+use flight_computer::{calibration::{Calibration, Calibrations, ColdJunction, ThermocoupleType}, config::Config, mappings::Mappings, state, valve::ValveEstimators};
+use support::mapping;
+
+mod support;
+
+/// 0 °C in Kelvin.
+const ZERO_CELSIUS: f64 = 273.15;
+
+/// Calibrates a raw value without any other readings.
+fn apply(calibration: &Calibration, raw: f64) -> Option<f64> {
+  calibration.apply(raw, &HashMap::new()).map(|m| m.value)
+}
+
+fn assert_close(actual: Option<f64>, expected: f64, tolerance: f64) {
+  let actual = actual.expect("The value couldn't be calibrated.");
+  assert!((actual - expected).abs() <= tolerance, "Expected {expected} ± {tolerance}, got {actual}.");
+}
+
+fn thermocouple(kind: ThermocoupleType, cold_junction: ColdJunction) -> Calibration {
+  Calibration::Thermocouple { kind, cold_junction }
+}
+
+#[test]
+fn thermocouples_match_the_nist_tables() {
+  // (°C, mV) with the reference junction at 0 °C, from the NIST ITS-90 tables
+  let type_k = [(-200.0, -5.891), (-100.0, -3.554), (0.0, 0.0), (100.0, 4.096), (500.0, 20.644), (1000.0, 41.276)];
+  let type_t = [(-200.0, -5.603), (-100.0, -3.379), (0.0, 0.0), (100.0, 4.279), (300.0, 14.862), (400.0, 20.872)];
+
+  for (kind, table) in [(ThermocoupleType::K, type_k), (ThermocoupleType::T, type_t)] {
+    let calibration = thermocouple(kind, ColdJunction::Fixed(ZERO_CELSIUS));
+
+    for (celsius, millivolts) in table {
+      // the inverse functions are accurate to within 0.06 °C over their range
+      assert_close(apply(&calibration, millivolts / 1000.0), celsius + ZERO_CELSIUS, 0.1);
+    }
+  }
+}
+
+#[test]
+fn thermocouples_are_compensated_for_their_cold_junction() {
+  // type K reads 1.000 mV at 25 °C, which the junction is at
+  let fixed = thermocouple(ThermocoupleType::K, ColdJunction::Fixed(25.0 + ZERO_CELSIUS));
+  assert_close(apply(&fixed, (4.096 - 1.000) / 1000.0), 100.0 + ZERO_CELSIUS, 0.1);
+
+  let sensor = thermocouple(ThermocoupleType::K, ColdJunction::Sensor("BOARD_RTD".to_string()));
+  let mut readings = HashMap::new();
+  assert!(sensor.apply(0.003096, &readings).is_none());
+
+  readings.insert("BOARD_RTD".to_string(), Measurement { value: 25.0 + ZERO_CELSIUS, unit: Unit::Kelvin });
+  assert_close(sensor.apply(0.003096, &readings).map(|m| m.value), 100.0 + ZERO_CELSIUS, 0.1);
+
+  // a junction that isn't read in Kelvin can't be compensated for
+  readings.insert("BOARD_RTD".to_string(), Measurement { value: 1.2, unit: Unit::Volts });
+  assert!(sensor.apply(0.003096, &readings).is_none());
+}
+
+#[test]
+fn thermocouples_beyond_their_range_cant_be_calibrated() {
+  let type_k = thermocouple(ThermocoupleType::K, ColdJunction::Fixed(ZERO_CELSIUS));
+  assert!(apply(&type_k, 0.060).is_none());
+  assert!(apply(&type_k, -0.006).is_none());
+
+  let type_t = thermocouple(ThermocoupleType::T, ColdJunction::Fixed(ZERO_CELSIUS));
+  assert!(apply(&type_t, 0.021).is_none());
+}
+
+#[test]
+fn polynomials_are_given_lowest_order_first() {
+  let calibration = Calibration::Polynomial { coefficients: vec![1.0, 2.0, 3.0], unit: Unit::Psi };
+  assert_close(apply(&calibration, 0.0), 1.0, 1e-9);
+  assert_close(apply(&calibration, 2.0), 17.0, 1e-9);
+  assert_close(apply(&calibration, -1.0), 2.0, 1e-9);
+}
+
+#[test]
+fn tables_interpolate_between_points_and_extrapolate_beyond_them() {
+  let calibration = Calibration::Table { points: vec![(0.0, 0.0), (1.0, 10.0), (3.0, 50.0)], unit: Unit::Pounds };
+
+  // exactly on every point, including both ends
+  assert_close(apply(&calibration, 0.0), 0.0, 1e-9);
+  assert_close(apply(&calibration, 1.0), 10.0, 1e-9);
+  assert_close(apply(&calibration, 3.0), 50.0, 1e-9);
+
+  assert_close(apply(&calibration, 0.5), 5.0, 1e-9);
+  assert_close(apply(&calibration, 2.0), 30.0, 1e-9);
+
+  // the outermost two points are extrapolated from
+  assert_close(apply(&calibration, -1.0), -10.0, 1e-9);
+  assert_close(apply(&calibration, 4.0), 70.0, 1e-9);
+}
+
+#[test]
+fn current_loops_span_from_4_to_20_milliamps() {
+  let calibration = Calibration::CurrentLoop { min: 0.0, max: 1000.0, unit: Unit::Psi, shunt_ohms: 200.0 };
+  assert_close(apply(&calibration, 0.8), 0.0, 1e-9);
+  assert_close(apply(&calibration, 2.4), 500.0, 1e-9);
+  assert_close(apply(&calibration, 4.0), 1000.0, 1e-9);
+
+  let shunt = Calibration::CurrentLoop { min: -10.0, max: 10.0, unit: Unit::Psi, shunt_ohms: 250.0 };
+  assert_close(apply(&shunt, 1.0), -10.0, 1e-9);
+  assert_close(apply(&shunt, 5.0), 10.0, 1e-9);
+}
+
+/// Ingests a single data point from `sam-01` with the given calibrations.
+fn ingest(mapping: NodeMapping, channel_type: ChannelType, value: f64, calibrations: Calibrations) -> VehicleState {
+  let mut state = VehicleState::new();
+  let data_point = DataPoint { value, timestamp: 0.0, channel: mapping.channel, channel_type };
+  let mut valves = ValveEstimators::new(&Config::default());
+
+  state::process_sam_data("sam-01", &mut state, vec![data_point], &Mappings::new(vec![mapping]), &calibrations, &mut valves, Instant::now());
+  state
+}
+
+#[test]
+fn values_that_cant_be_calibrated_are_skipped() {
+  let calibrations = Calibrations::from([("TC1".to_string(), thermocouple(ThermocoupleType::K, ColdJunction::Fixed(ZERO_CELSIUS)))]);
+
+  let state = ingest(mapping("TC1", SensorType::Tc, 1), ChannelType::Tc, 0.060, calibrations.clone());
+  assert!(!state.sensor_readings.contains_key("TC1"));
+
+  let state = ingest(mapping("TC1", SensorType::Tc, 1), ChannelType::Tc, 0.004096, calibrations);
+  assert_eq!(state.sensor_readings["TC1"].unit, Unit::Kelvin);
+}
+
+#[test]
+fn only_pressures_and_loads_are_offset_once_calibrated() {
+  let linear = Calibration::Polynomial { coefficients: vec![0.0, 100.0], unit: Unit::Psi };
+  let offset = |text_id, sensor_type| NodeMapping { calibrated_offset: 5.0, ..mapping(text_id, sensor_type, 1) };
+
+  let calibrations = Calibrations::from([("PT1".to_string(), linear.clone())]);
+  let state = ingest(offset("PT1", SensorType::Pt), ChannelType::CurrentLoop, 1.0, calibrations);
+  assert_eq!(state.sensor_readings["PT1"].value, 95.0);
+
+  let calibrations = Calibrations::from([("LC1".to_string(), linear.clone())]);
+  let state = ingest(offset("LC1", SensorType::LoadCell), ChannelType::DifferentialSignal, 1.0, calibrations);
+  assert_eq!(state.sensor_readings["LC1"].value, 95.0);
+
+  let calibrations = Calibrations::from([("RTD1".to_string(), linear)]);
+  let state = ingest(offset("RTD1", SensorType::Rtd), ChannelType::Rtd, 1.0, calibrations);
+  assert_eq!(state.sensor_readings["RTD1"].value, 100.0);
+}
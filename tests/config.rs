@@ -0,0 +1,199 @@
+use std::{env, fs, path::PathBuf, process, time::Duration};
+use common::comm::sam::Unit;
+use flight_computer::{calibration::Calibration, config::{Config, Error, RedlineConfig, SafingAction, SequenceConfig, ServoLossStage, ValveConfig}};
+
+fn parse(args: &[&str]) -> Result<Config, Error> {
+  Config::parse(args.iter().map(|a| a.to_string()))
+}
+
+fn write_config(name: &str, contents: &str) -> PathBuf {
+  let path = env::temp_dir().join(format!("fc-config-{}-{name}.toml", process::id()));
+  fs::write(&path, contents).unwrap();
+  path
+}
+
+/// Panics unless validation rejects the configuration as invalid.
+fn assert_invalid(config: Config, what: &str) {
+  match config.validate() {
+    Err(Error::Invalid(_)) => {},
+    other => panic!("Expected {what} to be invalid, but validation returned {other:?}"),
+  };
+}
+
+#[test]
+fn no_arguments_give_the_defaults() {
+  let config = parse(&[]).unwrap();
+  let default = Config::default();
+
+  assert_eq!(config.servo_addresses, default.servo_addresses);
+  assert_eq!(config.fc_address, default.fc_address);
+  assert_eq!(config.identity, "flight-01");
+  assert_eq!(config.device_command_port, 8378);
+  assert_eq!(config.servo_data_port, 7201);
+  assert_eq!(config.servo_status_port, 7202);
+  assert_eq!(config.time_to_live, Duration::from_millis(350));
+  assert_eq!(config.fc_to_servo_rate, Duration::from_millis(10));
+  assert_eq!(config.send_heartbeat_rate, Duration::from_millis(50));
+  assert_eq!(config.servo_to_fc_time_to_live, Duration::from_secs(600));
+  assert_eq!(config.sequence_status_rate, Duration::from_secs(1));
+  assert!(config.servo_loss.is_empty());
+  assert!(config.default_abort_sequence.is_none());
+  assert!(config.recorder.enabled && config.store.enabled);
+  assert!(config.sequences.is_empty() && config.calibrations.is_empty() && config.valves.is_empty());
+  assert!(config.redlines.is_empty() && config.boards.is_empty());
+}
+
+#[test]
+fn flags_override_the_config_file() {
+  let path = write_config("overrides", r#"
+    servo_addresses = ["servo.local:5025"]
+    identity = "flight-02"
+    servo_data_port = 9000
+    time_to_live_ms = 500
+
+    [recorder]
+    enabled = false
+
+    [[servo_loss]]
+    after_ms = 1000
+    action = "abort"
+  "#);
+  let path = path.to_str().unwrap();
+
+  let config = parse(&["--config", path]).unwrap();
+  assert_eq!(config.servo_addresses, ["servo.local:5025"]);
+  assert_eq!(config.identity, "flight-02");
+  assert_eq!(config.servo_data_port, 9000);
+  assert_eq!(config.time_to_live, Duration::from_millis(500));
+  assert!(!config.recorder.enabled);
+  assert_eq!(config.servo_loss[0].action, SafingAction::Abort);
+
+  // anything the file leaves out keeps its default
+  assert_eq!(config.servo_status_port, 7202);
+  assert_eq!(config.recorder.max_files, 64);
+
+  let config = parse(&["--servo", "a.local:1", "--identity", "flight-03", "--config", path, "--servo", "b.local:2", "--servo-data-port", "9001"]).unwrap();
+  assert_eq!(config.servo_addresses, ["a.local:1", "b.local:2"]);
+  assert_eq!(config.identity, "flight-03");
+  assert_eq!(config.servo_data_port, 9001);
+  assert_eq!(config.time_to_live, Duration::from_millis(500));
+}
+
+#[test]
+fn malformed_arguments_are_rejected() {
+  assert!(matches!(parse(&["--identity"]), Err(Error::MissingValue(flag)) if flag == "--identity"));
+  assert!(matches!(parse(&["--verbose", "true"]), Err(Error::UnknownFlag(flag)) if flag == "--verbose"));
+  assert!(matches!(parse(&["--servo-data-port", "seventy"]), Err(Error::InvalidValue(flag, _)) if flag == "--servo-data-port"));
+  assert!(matches!(parse(&["--device-command-port", "70000"]), Err(Error::InvalidValue(..))));
+  assert!(matches!(parse(&["--fc-address", "localhost"]), Err(Error::InvalidValue(..))));
+  assert!(matches!(parse(&["--config", "/nonexistent/fc.toml"]), Err(Error::Read(..))));
+
+  let path = write_config("unknown-field", "identitty = \"flight-02\"");
+  assert!(matches!(parse(&["--config", path.to_str().unwrap()]), Err(Error::Parse(..))));
+
+  let path = write_config("wrong-type", "[recorder]\nmax_files = \"many\"");
+  assert!(matches!(parse(&["--config", path.to_str().unwrap()]), Err(Error::Parse(..))));
+
+  // the result is validated after every override is applied
+  assert!(matches!(parse(&["--servo-status-port", "0"]), Err(Error::Invalid(_))));
+}
+
+#[test]
+fn servo_and_identity_settings_are_validated() {
+  assert!(Config::default().validate().is_ok());
+
+  assert_invalid(Config { servo_addresses: Vec::new(), ..Config::default() }, "no servo addresses");
+
+  for address in ["localhost", ":5025", "localhost:", "localhost:0", "localhost:port", "localhost:65536"] {
+    assert_invalid(Config { servo_addresses: vec![address.to_string()], ..Config::default() }, address);
+  }
+
+  assert_invalid(Config { identity: String::new(), ..Config::default() }, "an empty identity");
+  assert_invalid(Config { device_command_port: 0, ..Config::default() }, "a zero device command port");
+  assert_invalid(Config { servo_data_port: 0, ..Config::default() }, "a zero servo data port");
+  assert_invalid(Config { servo_status_port: 0, ..Config::default() }, "a zero servo status port");
+}
+
+#[test]
+fn durations_are_validated() {
+  assert_invalid(Config { time_to_live: Duration::ZERO, ..Config::default() }, "a zero time to live");
+  assert_invalid(Config { fc_to_servo_rate: Duration::ZERO, ..Config::default() }, "a zero telemetry rate");
+  assert_invalid(Config { send_heartbeat_rate: Duration::ZERO, ..Config::default() }, "a zero heartbeat rate");
+  assert_invalid(Config { servo_to_fc_time_to_live: Duration::ZERO, ..Config::default() }, "a zero servo time to live");
+
+  let servo_loss = vec![ServoLossStage { after: Duration::ZERO, action: SafingAction::Warn }];
+  assert_invalid(Config { servo_loss, ..Config::default() }, "an immediate stage of servo loss");
+
+  let config = Config { send_heartbeat_rate: Duration::from_millis(350), ..Config::default() };
+  assert_invalid(config, "heartbeats as slow as the time to live");
+}
+
+#[test]
+fn sequence_settings_are_validated() {
+  let config = Config { default_abort_sequence: Some(PathBuf::from("/nonexistent/abort.py")), ..Config::default() };
+  assert_invalid(config, "a missing default abort sequence");
+
+  let limits = [
+    SequenceConfig { timeout: Some(Duration::ZERO), ..SequenceConfig::default() },
+    SequenceConfig { cpu_seconds: Some(0), ..SequenceConfig::default() },
+    SequenceConfig { memory_bytes: Some(0), ..SequenceConfig::default() },
+    SequenceConfig { max_commands_per_second: Some(0), ..SequenceConfig::default() },
+    SequenceConfig { nice: Some(-21), ..SequenceConfig::default() },
+    SequenceConfig { nice: Some(20), ..SequenceConfig::default() },
+  ];
+
+  for limit in limits {
+    let mut config = Config::default();
+    config.sequences.insert("press".to_string(), limit.clone());
+    assert_invalid(config, &format!("{limit:?}"));
+  }
+
+  let mut config = Config::default();
+  config.sequences.insert("press".to_string(), SequenceConfig { nice: Some(-20), ..SequenceConfig::default() });
+  assert!(config.validate().is_ok());
+}
+
+#[test]
+fn recorder_settings_are_validated() {
+  let mut config = Config::default();
+  config.recorder.max_files = 0;
+  assert_invalid(config, "a recorder without files");
+
+  let mut config = Config::default();
+  config.recorder.max_file_size = 1023;
+  assert_invalid(config, "a recorder with tiny files");
+}
+
+#[test]
+fn sensor_and_valve_settings_are_validated() {
+  let mut config = Config::default();
+  config.calibrations.insert("PT1".to_string(), Calibration::Polynomial { coefficients: Vec::new(), unit: Unit::Psi });
+  assert_invalid(config, "an empty polynomial");
+
+  let valves = [
+    ValveConfig { unpowered_voltage: -1.0, ..ValveConfig::default() },
+    ValveConfig { voltage_hysteresis: f64::NAN, ..ValveConfig::default() },
+    ValveConfig { powered_current: Some(0.0), ..ValveConfig::default() },
+    ValveConfig { unpowered_voltage: 20.0, powered_voltage: 20.0, ..ValveConfig::default() },
+    ValveConfig { unpowered_voltage: 24.0, powered_voltage: 20.0, ..ValveConfig::default() },
+    ValveConfig { powered_current: Some(0.5), current_hysteresis: 0.5, ..ValveConfig::default() },
+  ];
+
+  for valve in valves {
+    let mut config = Config::default();
+    config.valves.insert("IPV".to_string(), valve.clone());
+    assert_invalid(config, &format!("{valve:?}"));
+  }
+
+  let mut config = Config::default();
+  config.valves.insert("IPV".to_string(), ValveConfig { powered_current: Some(0.5), current_hysteresis: 0.1, voltage_hysteresis: 1.0, ..ValveConfig::default() });
+  assert!(config.validate().is_ok());
+
+  let redlines = [(None, None), (Some(10.0), Some(10.0)), (Some(20.0), Some(10.0)), (Some(f64::NAN), None), (None, Some(f64::INFINITY))];
+
+  for (min, max) in redlines {
+    let mut config = Config::default();
+    config.redlines.insert("PT1".to_string(), RedlineConfig { min, max, persistence: Duration::ZERO, action: SafingAction::Warn });
+    assert_invalid(config, &format!("a redline from {min:?} to {max:?}"));
+  }
+}
@@ -0,0 +1,512 @@
+use std::{fs, net::UdpSocket, os::unix::net::UnixDatagram, path::PathBuf, process, thread, time::{Duration, Instant}};
+use common::comm::{flight::{DataMessage, SequenceDomainCommand}, sam::{ChannelType, DataPoint, SamControlMessage}, NodeMapping, SensorType, ValveState};
+use flight_computer::{clock::{Clock, ManualClock}, computer::FlightComputer, config::{BoardConfig, CommsLossAction, Config, SafingAction, SequenceConfig, ServoLossStage, ValveConfig}, recorder::{self, Reader, Record}, sequence::SequenceState, status::StatusMessage};
+use mmap_sync::synchronizer::Synchronizer;
+use support::{mapping, mock_servo::MockServo, python_bytes, stub_python_library};
+
+mod support;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A SAM which has completed its handshake with the flight computer.
+struct Board {
+  data: UdpSocket,
+  commands: UdpSocket,
+}
+
+impl Board {
+  fn bind() -> Self {
+    let data = UdpSocket::bind(("127.0.0.1", 0)).unwrap();
+    let commands = UdpSocket::bind(("127.0.0.1", 0)).unwrap();
+    commands.set_read_timeout(Some(TIMEOUT)).unwrap();
+    Board { data, commands }
+  }
+
+  fn receive_command(&self) -> SamControlMessage {
+    let mut buf = [0; 1024];
+    let size = self.commands.recv(&mut buf).expect("The board never received a command.");
+    postcard::from_bytes(&buf[..size]).unwrap()
+  }
+
+  /// Ticks the flight computer until the board receives a command.
+  fn await_command(&self, fc: &mut FlightComputer<ManualClock>) -> SamControlMessage {
+    let mut buf = [0; 1024];
+    let deadline = Instant::now() + TIMEOUT;
+    self.commands.set_nonblocking(true).unwrap();
+
+    let size = loop {
+      fc.tick();
+
+      if let Ok(size) = self.commands.recv(&mut buf) {
+        break size;
+      }
+
+      assert!(Instant::now() < deadline, "The board never received a command.");
+      thread::sleep(Duration::from_millis(1));
+    };
+
+    self.commands.set_nonblocking(false).unwrap();
+    postcard::from_bytes(&buf[..size]).unwrap()
+  }
+
+  /// Discards everything the flight computer has sent so far, such as
+  /// heartbeats.
+  fn drain_commands(&self) {
+    self.commands.set_nonblocking(true).unwrap();
+    while self.commands.recv(&mut [0; 1024]).is_ok() {}
+    self.commands.set_nonblocking(false).unwrap();
+  }
+}
+
+fn flight_computer(name: &str, board: &Board, clock: &ManualClock) -> FlightComputer<ManualClock> {
+  flight_computer_with(name, board, clock, offline())
+}
+
+/// The default configuration, without any Servo to connect to.
+fn offline() -> Config {
+  Config { servo_addresses: Vec::new(), ..Config::default() }
+}
+
+fn flight_computer_with(name: &str, board: &Board, clock: &ManualClock, config: Config) -> FlightComputer<ManualClock> {
+  let mut config = Config {
+    device_command_port: board.commands.local_addr().unwrap().port(),
+    servo_to_fc_time_to_live: Duration::from_secs(1),
+    ..config
+  };
+  let directory = directory(name);
+  let _ = fs::remove_dir_all(&directory);
+  fs::create_dir_all(&directory).unwrap();
+  config.recorder.directory = directory.join("recordings");
+  config.store.enabled = false;
+
+  let socket = UdpSocket::bind(("127.0.0.1", 0)).unwrap();
+  socket.set_nonblocking(true).unwrap();
+  let command_socket = UnixDatagram::bind(directory.join("commands.sock")).unwrap();
+  command_socket.set_nonblocking(true).unwrap();
+  let mmap_path = std::env::temp_dir().join(format!("fc-test-{}-{name}", process::id()));
+  let synchronizer = Synchronizer::new(mmap_path.as_os_str());
+
+  let mut fc = FlightComputer::new(config, clock.clone(), socket, command_socket, synchronizer).unwrap();
+  let address = fc_address(&fc);
+
+  let identity = postcard::to_allocvec(&DataMessage::Identity("sam-01".to_string())).unwrap();
+  board.data.send_to(&identity, address).unwrap();
+
+  // telemetry arrives in real time, so only the wall clock moves while waiting
+  let deadline = Instant::now() + TIMEOUT;
+  while fc.devices().iter().next().is_none() {
+    assert!(Instant::now() < deadline, "The board was never registered.");
+    fc.tick();
+    thread::sleep(Duration::from_millis(1));
+  }
+
+  fc
+}
+
+/// Connects the flight computer to the mock, ticking it until it notices.
+fn connect(fc: &mut FlightComputer<ManualClock>, mock: &mut MockServo) {
+  // the FC connects in the background, so the mock can accept before it ticks
+  mock.assert_accepts(TIMEOUT);
+  tick_until(fc, "the FC to connect to Servo", |fc| fc.servo().is_connected());
+}
+
+/// Where a test's flight computer keeps its recordings and command socket.
+fn directory(name: &str) -> PathBuf {
+  std::env::temp_dir().join(format!("fc-test-{}-{name}-files", process::id()))
+}
+
+/// Ticks the flight computer in real time until the condition holds.
+fn tick_until(fc: &mut FlightComputer<ManualClock>, what: &str, condition: impl Fn(&FlightComputer<ManualClock>) -> bool) {
+  let deadline = Instant::now() + TIMEOUT;
+
+  while !condition(fc) {
+    assert!(Instant::now() < deadline, "Timed out waiting for {what}.");
+    fc.tick();
+    thread::sleep(Duration::from_millis(1));
+  }
+}
+
+fn fc_address(fc: &FlightComputer<ManualClock>) -> std::net::SocketAddr {
+  fc.socket().local_addr().unwrap()
+}
+
+#[test]
+fn board_disconnects_after_time_to_live() {
+  let clock = ManualClock::new();
+  let board = Board::bind();
+  let mut fc = flight_computer("board-ttl", &board, &clock);
+  let time_to_live = Config::default().time_to_live;
+
+  clock.advance(time_to_live);
+  fc.tick();
+  assert!(!fc.devices().iter().next().unwrap().is_disconnected(clock.now()));
+
+  clock.advance(Duration::from_millis(1));
+  fc.tick();
+  assert!(fc.devices().iter().next().unwrap().is_disconnected(clock.now()));
+}
+
+#[test]
+fn valves_are_safed_once_servo_is_silent_for_too_long() {
+  let clock = ManualClock::new();
+  let board = Board::bind();
+  let mut fc = flight_computer("servo-ttl", &board, &clock);
+
+  clock.advance(Duration::from_secs(1));
+  fc.tick();
+  assert!(!fc.is_servo_lost());
+
+  clock.advance(Duration::from_millis(1));
+  fc.tick();
+  assert!(fc.is_servo_lost());
+  assert!(matches!(board.receive_command(), SamControlMessage::SafeValves { .. }));
+
+  // the vehicle is only safed once per loss of Servo
+  board.commands.set_read_timeout(Some(Duration::from_millis(50))).unwrap();
+  clock.advance(Duration::from_secs(1));
+  fc.tick();
+  assert!(board.commands.recv(&mut [0; 1024]).is_err());
+}
+
+#[test]
+fn losing_a_board_applies_its_policy_once() {
+  let clock = ManualClock::new();
+  let board = Board::bind();
+  let mut config = offline();
+  config.boards.insert("sam-01".to_string(), BoardConfig { on_disconnect: CommsLossAction::SafeValves });
+  let mut fc = flight_computer_with("board-policy", &board, &clock, config);
+  board.drain_commands();
+
+  clock.advance(Config::default().time_to_live + Duration::from_millis(1));
+  fc.tick();
+  assert!(matches!(board.receive_command(), SamControlMessage::SafeValves { .. }));
+
+  board.commands.set_read_timeout(Some(Duration::from_millis(50))).unwrap();
+  clock.advance(Duration::from_millis(100));
+  fc.tick();
+  assert!(board.commands.recv(&mut [0; 1024]).is_err());
+}
+
+#[test]
+fn aborting_without_an_abort_sequence_safes_valves() {
+  let clock = ManualClock::new();
+  let board = Board::bind();
+  let config = Config {
+    servo_loss: vec![ServoLossStage { after: Duration::from_millis(100), action: SafingAction::Abort }],
+    ..offline()
+  };
+  let mut fc = flight_computer_with("fallback-abort", &board, &clock, config);
+  board.drain_commands();
+
+  clock.advance(Duration::from_millis(101));
+  fc.tick();
+  assert!(matches!(board.receive_command(), SamControlMessage::SafeValves { .. }));
+}
+
+#[test]
+fn servo_commands_the_flight_computer_until_it_goes_silent() {
+  let clock = ManualClock::new();
+  let board = Board::bind();
+  let mut mock = MockServo::bind().unwrap();
+  let config = Config {
+    servo_addresses: vec![mock.address().to_string()],
+    servo_data_port: mock.data_port(),
+    servo_status_port: mock.status_port(),
+    ..Config::default()
+  };
+  let mut fc = flight_computer_with("servo-e2e", &board, &clock, config);
+
+  connect(&mut fc, &mut mock);
+
+  mock.assert_status(TIMEOUT, "the FC to report its versions", |s| {
+    matches!(s, StatusMessage::Versions { mappings: None, abort_sequence: None })
+  });
+
+  clock.advance(Config::default().fc_to_servo_rate + Duration::from_millis(1));
+  fc.tick();
+  mock.assert_state(TIMEOUT, "the vehicle telemetry", |_| true);
+
+  // without an abort sequence, aborting safes the valves
+  board.drain_commands();
+  mock.send_abort().unwrap();
+  assert!(matches!(board.await_command(&mut fc), SamControlMessage::SafeValves { .. }));
+  assert!(!fc.is_servo_lost());
+
+  drop(mock);
+  tick_until(&mut fc, "the FC to notice Servo disconnecting", |fc| !fc.servo().is_connected());
+  board.drain_commands();
+
+  clock.advance(Duration::from_secs(1) + Duration::from_millis(1));
+  fc.tick();
+  assert!(fc.is_servo_lost());
+  assert!(matches!(board.receive_command(), SamControlMessage::SafeValves { .. }));
+}
+
+#[test]
+fn commands_beyond_a_sequences_rate_are_dropped_except_aborts() {
+  stub_python_library();
+  let clock = ManualClock::new();
+  let board = Board::bind();
+  let mut mock = MockServo::bind().unwrap();
+  let mut config = Config {
+    servo_addresses: vec![mock.address().to_string()],
+    servo_data_port: mock.data_port(),
+    servo_status_port: mock.status_port(),
+    ..Config::default()
+  };
+  config.sequences.insert("burst".to_string(), SequenceConfig { max_commands_per_second: Some(3), ..SequenceConfig::default() });
+  let mut fc = flight_computer_with("rate-limit", &board, &clock, config);
+  connect(&mut fc, &mut mock);
+  board.drain_commands();
+
+  let actuate = postcard::to_allocvec(&SequenceDomainCommand::ActuateValve { valve: "IPV".to_string(), state: ValveState::Open }).unwrap();
+  let abort = postcard::to_allocvec(&SequenceDomainCommand::Abort).unwrap();
+  let script = format!(
+    "import socket, time\n\
+     s = socket.socket(socket.AF_UNIX, socket.SOCK_DGRAM)\n\
+     for _ in range(10): s.sendto({}, {:?})\n\
+     s.sendto({}, {:?})\n\
+     time.sleep(10)\n",
+    python_bytes(&actuate),
+    directory("rate-limit").join("commands.sock"),
+    python_bytes(&abort),
+    directory("rate-limit").join("commands.sock"),
+  );
+  mock.send_sequence("burst", &script).unwrap();
+
+  // the whole burst lands within one window, as the clock never moves
+  assert!(matches!(board.await_command(&mut fc), SamControlMessage::SafeValves { .. }));
+
+  let recordings = recorder::list(&directory("rate-limit").join("recordings")).unwrap();
+  let commands: Vec<_> = Reader::open(&recordings[0])
+    .unwrap()
+    .filter_map(|entry| match entry.unwrap().record {
+      Record::AttributedSequenceCommand(command) => Some(command.into_owned()),
+      _ => None,
+    })
+    .collect();
+
+  assert_eq!(commands.len(), 11);
+  assert!(commands.iter().all(|c| c.sequence.as_deref() == Some("burst")));
+  assert_eq!(commands.iter().filter(|c| c.carried_out).count(), 4);
+  assert!(commands[..3].iter().all(|c| c.carried_out));
+  assert!(commands[3..10].iter().all(|c| !c.carried_out));
+  assert!(matches!(commands[10].command, SequenceDomainCommand::Abort) && commands[10].carried_out);
+
+  let mut statuses = mock.assert_status(TIMEOUT, "the sequence to be reported as rate limited", |s| {
+    matches!(s, StatusMessage::SequenceRateLimited { name, limit: 3 } if name == "burst")
+  });
+  statuses.extend(mock.receive_statuses().unwrap());
+
+  assert_eq!(statuses.iter().filter(|s| matches!(s, StatusMessage::SequenceRateLimited { .. })).count(), 1);
+}
+
+#[test]
+fn sequence_statuses_are_resent_until_the_sequence_is_stopped() {
+  stub_python_library();
+  let clock = ManualClock::new();
+  let board = Board::bind();
+  let mut mock = MockServo::bind().unwrap();
+  let config = Config {
+    servo_addresses: vec![mock.address().to_string()],
+    servo_data_port: mock.data_port(),
+    servo_status_port: mock.status_port(),
+    ..Config::default()
+  };
+  let mut fc = flight_computer_with("sequence-statuses", &board, &clock, config);
+  connect(&mut fc, &mut mock);
+
+  let running = |s: &StatusMessage| matches!(s, StatusMessage::Sequence(status) if status.name == "idle" && status.state == SequenceState::Running);
+  mock.send_sequence("idle", "import time; time.sleep(10)").unwrap();
+
+  let deadline = Instant::now() + TIMEOUT;
+  while !mock.receive_statuses().unwrap().iter().any(running) {
+    assert!(Instant::now() < deadline, "The sequence was never reported as running.");
+    fc.tick();
+    thread::sleep(Duration::from_millis(1));
+  }
+
+  // nothing changed, but the status is sent again once the rate has passed
+  clock.advance(Config::default().sequence_status_rate);
+  fc.tick();
+  mock.assert_status(TIMEOUT, "the running sequence to be reported again", running);
+
+  mock.send_stop_sequence("idle").unwrap();
+
+  let deadline = Instant::now() + TIMEOUT;
+  while !mock.receive_statuses().unwrap().iter().any(|s| matches!(s, StatusMessage::Sequence(status) if status.state == SequenceState::Killed(libc::SIGKILL))) {
+    assert!(Instant::now() < deadline, "The sequence was never reported as killed.");
+    fc.tick();
+    thread::sleep(Duration::from_millis(1));
+  }
+}
+
+#[test]
+fn repeated_aborts_let_the_running_abort_sequence_finish() {
+  stub_python_library();
+  let clock = ManualClock::new();
+  let board = Board::bind();
+  let mut mock = MockServo::bind().unwrap();
+  let config = Config {
+    servo_addresses: vec![mock.address().to_string()],
+    servo_data_port: mock.data_port(),
+    servo_status_port: mock.status_port(),
+    ..Config::default()
+  };
+  let mut fc = flight_computer_with("repeated-abort", &board, &clock, config);
+  connect(&mut fc, &mut mock);
+
+  let aborting = |s: &StatusMessage| matches!(s, StatusMessage::Sequence(status) if status.name == "abort");
+  mock.send_sequence("abort", "import time; time.sleep(10)").unwrap();
+  mock.send_abort().unwrap();
+
+  let deadline = Instant::now() + TIMEOUT;
+  while !mock.receive_statuses().unwrap().iter().any(aborting) {
+    assert!(Instant::now() < deadline, "The abort sequence was never started.");
+    fc.tick();
+    thread::sleep(Duration::from_millis(1));
+  }
+
+  board.drain_commands();
+  mock.send_abort().unwrap();
+  mock.send_abort().unwrap();
+
+  // long enough for a killed sequence to be reaped and reported, while the
+  // clock stands still so that statuses aren't re-sent
+  let mut statuses = Vec::new();
+  let until = Instant::now() + Duration::from_millis(500);
+  while Instant::now() < until {
+    fc.tick();
+    statuses.extend(mock.receive_statuses().unwrap());
+    thread::sleep(Duration::from_millis(1));
+  }
+
+  assert!(!statuses.iter().any(aborting), "The abort sequence was restarted or stopped: {statuses:?}");
+
+  // and the valves aren't safed in its place
+  board.commands.set_nonblocking(true).unwrap();
+  let mut buf = [0; 1024];
+  while let Ok(size) = board.commands.recv(&mut buf) {
+    let command = postcard::from_bytes::<SamControlMessage>(&buf[..size]);
+    assert!(!matches!(command, Ok(SamControlMessage::SafeValves { .. })));
+  }
+
+  mock.send_stop_sequence("abort").unwrap();
+
+  let deadline = Instant::now() + TIMEOUT;
+  while !mock.receive_statuses().unwrap().iter().any(aborting) {
+    assert!(Instant::now() < deadline, "The abort sequence was never stopped.");
+    fc.tick();
+    thread::sleep(Duration::from_millis(1));
+  }
+}
+
+#[test]
+fn safed_valves_arent_flagged_as_mismatched() {
+  stub_python_library();
+  let clock = ManualClock::new();
+  let board = Board::bind();
+  let mut mock = MockServo::bind().unwrap();
+  let config = Config {
+    servo_addresses: vec![mock.address().to_string()],
+    servo_data_port: mock.data_port(),
+    servo_status_port: mock.status_port(),
+    ..Config::default()
+  };
+  let mut fc = flight_computer_with("safe-mismatch", &board, &clock, config);
+  connect(&mut fc, &mut mock);
+
+  let valve = NodeMapping { powered_threshold: Some(0.5), ..mapping("IPV", SensorType::Valve, 1) };
+  mock.send_mappings(vec![valve]).unwrap();
+  tick_until(&mut fc, "the mappings to arrive", |fc| fc.mappings().get("IPV").is_some());
+
+  // commanded open, but unpowered as it will be once safed
+  let open = postcard::to_allocvec(&SequenceDomainCommand::ActuateValve { valve: "IPV".to_string(), state: ValveState::Open }).unwrap();
+  let script = format!(
+    "import socket; socket.socket(socket.AF_UNIX, socket.SOCK_DGRAM).sendto({}, {:?})",
+    python_bytes(&open),
+    directory("safe-mismatch").join("commands.sock"),
+  );
+  mock.send_sequence("open", &script).unwrap();
+  board.drain_commands();
+  assert!(matches!(board.await_command(&mut fc), SamControlMessage::ActuateValve { channel: 1, powered: true }));
+
+  let unpowered = [ChannelType::ValveVoltage, ChannelType::ValveCurrent]
+    .map(|channel_type| DataPoint { value: 0.0, timestamp: 0.0, channel: 1, channel_type });
+  let telemetry = postcard::to_allocvec(&DataMessage::Sam("sam-01".to_string(), unpowered.to_vec().into())).unwrap();
+  board.data.send_to(&telemetry, fc_address(&fc)).unwrap();
+  tick_until(&mut fc, "the valve to be read as closed", |fc| fc.state().valve_states.get("IPV").is_some_and(|v| v.actual == ValveState::Closed));
+
+  // without an abort sequence, aborting safes the valves
+  mock.send_abort().unwrap();
+  assert!(matches!(board.await_command(&mut fc), SamControlMessage::SafeValves { .. }));
+  assert_eq!(fc.state().valve_states["IPV"].commanded, ValveState::Closed);
+
+  clock.advance(ValveConfig::default().settle_time * 2);
+  fc.tick();
+  fc.tick();
+
+  let statuses = mock.receive_statuses().unwrap();
+  assert!(!statuses.iter().any(|s| matches!(s, StatusMessage::ValveMismatch { .. })), "{statuses:?}");
+}
+
+#[test]
+fn messages_sent_to_sams_are_recorded() {
+  stub_python_library();
+  let clock = ManualClock::new();
+  let board = Board::bind();
+  let mut mock = MockServo::bind().unwrap();
+  let config = Config {
+    servo_addresses: vec![mock.address().to_string()],
+    servo_data_port: mock.data_port(),
+    servo_status_port: mock.status_port(),
+    ..Config::default()
+  };
+  let mut fc = flight_computer_with("sam-records", &board, &clock, config);
+  connect(&mut fc, &mut mock);
+
+  mock.send_mappings(vec![mapping("IPV", SensorType::Valve, 1)]).unwrap();
+  tick_until(&mut fc, "the mappings to arrive", |fc| fc.mappings().get("IPV").is_some());
+  board.drain_commands();
+
+  let mapped = postcard::to_allocvec(&SequenceDomainCommand::ActuateValve { valve: "IPV".to_string(), state: ValveState::Open }).unwrap();
+  let unmapped = postcard::to_allocvec(&SequenceDomainCommand::ActuateValve { valve: "GHOST".to_string(), state: ValveState::Open }).unwrap();
+  let abort = postcard::to_allocvec(&SequenceDomainCommand::Abort).unwrap();
+  let script = format!(
+    "import socket; s = socket.socket(socket.AF_UNIX, socket.SOCK_DGRAM); [s.sendto(m, {:?}) for m in ({}, {}, {})]",
+    directory("sam-records").join("commands.sock"),
+    python_bytes(&mapped),
+    python_bytes(&unmapped),
+    python_bytes(&abort),
+  );
+  mock.send_sequence("sender", &script).unwrap();
+
+  assert!(matches!(board.await_command(&mut fc), SamControlMessage::ActuateValve { channel: 1, powered: true }));
+  assert!(matches!(board.await_command(&mut fc), SamControlMessage::SafeValves { .. }));
+
+  let recordings = recorder::list(&directory("sam-records").join("recordings")).unwrap();
+  let entries: Vec<_> = Reader::open(&recordings[0]).unwrap().map(|entry| entry.unwrap().record).collect();
+
+  let sent: Vec<_> = entries
+    .iter()
+    .filter_map(|record| match record {
+      Record::SamMessage(sent) => Some(sent.as_ref()),
+      _ => None,
+    })
+    .collect();
+
+  assert_eq!(sent.len(), 2);
+  assert!(sent.iter().all(|s| s.board_id == "sam-01" && s.error.is_none()));
+  assert!(matches!(sent[0].message, SamControlMessage::ActuateValve { channel: 1, powered: true }));
+  assert!(matches!(sent[1].message, SamControlMessage::SafeValves { .. }));
+
+  let unmapped: Vec<_> = entries
+    .iter()
+    .filter_map(|record| match record {
+      Record::UnmappedSamCommand(command) => Some(command.as_ref()),
+      _ => None,
+    })
+    .collect();
+
+  assert!(matches!(unmapped[..], [SequenceDomainCommand::ActuateValve { valve, .. }] if valve == "GHOST"));
+}
@@ -0,0 +1,62 @@
+use common::comm::{sam::ChannelType, NodeMapping, SensorType};
+use flight_computer::mappings::Mappings;
+use support::mapping;
+
+mod support;
+
+/// The text IDs of the mappings of a channel on `sam-01`.
+fn text_ids(mappings: &Mappings, channel: u32, channel_type: ChannelType) -> Vec<String> {
+  mappings.for_channel("sam-01", channel, &channel_type).map(|m| m.text_id.clone()).collect()
+}
+
+#[test]
+fn mappings_are_found_by_text_id() {
+  let mappings = Mappings::new(vec![mapping("PT1", SensorType::Pt, 1), mapping("IPV", SensorType::Valve, 2)]);
+
+  assert_eq!(mappings.get("IPV").map(|m| m.channel), Some(2));
+  assert!(mappings.get("PT2").is_none());
+  assert_eq!(mappings.len(), 2);
+  assert!(mappings.duplicates().is_empty());
+}
+
+#[test]
+fn mappings_are_found_by_every_channel_type_of_their_sensor() {
+  let mappings = Mappings::new(vec![mapping("PT1", SensorType::Pt, 1), mapping("IPV", SensorType::Valve, 1)]);
+
+  assert_eq!(text_ids(&mappings, 1, ChannelType::CurrentLoop), ["PT1"]);
+  assert_eq!(text_ids(&mappings, 1, ChannelType::ValveCurrent), ["IPV"]);
+  assert_eq!(text_ids(&mappings, 1, ChannelType::ValveVoltage), ["IPV"]);
+
+  assert!(text_ids(&mappings, 2, ChannelType::CurrentLoop).is_empty());
+  assert!(text_ids(&mappings, 1, ChannelType::Tc).is_empty());
+  assert_eq!(mappings.for_channel("sam-02", 1, &ChannelType::CurrentLoop).count(), 0);
+}
+
+#[test]
+fn mappings_of_the_same_channel_are_found_in_the_order_they_were_sent() {
+  let mappings = Mappings::new(vec![
+    mapping("PT2", SensorType::Pt, 1),
+    mapping("PT1", SensorType::Pt, 1),
+    NodeMapping { board_id: "sam-02".to_string(), ..mapping("PT3", SensorType::Pt, 1) },
+  ]);
+
+  assert_eq!(text_ids(&mappings, 1, ChannelType::CurrentLoop), ["PT2", "PT1"]);
+  assert_eq!(mappings.for_channel("sam-02", 1, &ChannelType::CurrentLoop).map(|m| m.text_id.as_str()).collect::<Vec<_>>(), ["PT3"]);
+}
+
+#[test]
+fn duplicate_text_ids_are_listed_and_the_first_definition_wins() {
+  let mappings = Mappings::new(vec![
+    mapping("PT1", SensorType::Pt, 1),
+    mapping("PT1", SensorType::Pt, 2),
+    mapping("IPV", SensorType::Valve, 3),
+    mapping("PT1", SensorType::Pt, 4),
+  ]);
+
+  assert_eq!(mappings.get("PT1").map(|m| m.channel), Some(1));
+  assert_eq!(mappings.duplicates(), ["PT1"]);
+
+  // every definition is still kept, and found by its channel
+  assert_eq!(mappings.len(), 4);
+  assert_eq!(text_ids(&mappings, 4, ChannelType::CurrentLoop), ["PT1"]);
+}
@@ -0,0 +1,78 @@
+use common::comm::{flight::SequenceDomainCommand, ValveState};
+use flight_computer::{config::{Config, ValveConflictAction}, ownership::ValveOwners, sequence, status::StatusMessage};
+
+fn actuate(valve: &str) -> SequenceDomainCommand {
+  SequenceDomainCommand::ActuateValve { valve: valve.to_string(), state: ValveState::Open }
+}
+
+#[test]
+fn conflicting_actuations_are_rejected_until_the_owner_stops() {
+  let config = Config { on_valve_conflict: ValveConflictAction::Reject, ..Config::default() };
+  let mut owners = ValveOwners::new(&config);
+  let mut reports = Vec::new();
+
+  assert!(owners.permit(&actuate("FUEL"), Some("fill"), &mut reports));
+  assert!(owners.permit(&actuate("FUEL"), Some("fill"), &mut reports));
+  assert_eq!(owners.owner("FUEL"), Some("fill"));
+
+  assert!(!owners.permit(&actuate("FUEL"), Some("vent"), &mut reports));
+  assert!(!owners.permit(&actuate("FUEL"), None, &mut reports));
+  assert_eq!(owners.owner("FUEL"), Some("fill"));
+
+  // the abort sequence is never locked out
+  assert!(owners.permit(&actuate("FUEL"), Some(sequence::ABORT), &mut reports));
+  assert_eq!(owners.owner("FUEL"), Some(sequence::ABORT));
+
+  owners.release(sequence::ABORT, &mut reports);
+  assert!(owners.permit(&actuate("FUEL"), Some("vent"), &mut reports));
+
+  let conflicts = reports.iter().filter(|r| matches!(r, StatusMessage::ValveConflict { .. })).count();
+  assert_eq!(conflicts, 3);
+}
+
+#[test]
+fn conflicting_actuations_hand_the_valve_over_when_warning() {
+  let mut owners = ValveOwners::new(&Config::default());
+  let mut reports = Vec::new();
+
+  assert!(owners.permit(&actuate("LOX"), Some("fill"), &mut reports));
+  assert!(owners.permit(&actuate("LOX"), Some("vent"), &mut reports));
+  assert_eq!(owners.owner("LOX"), Some("vent"));
+
+  assert_eq!(reports[1], StatusMessage::ValveConflict {
+    valve: "LOX".to_string(),
+    owner: "fill".to_string(),
+    sender: Some("vent".to_string()),
+    rejected: false,
+  });
+
+  assert_eq!(reports[2], StatusMessage::ValveOwnerChanged {
+    valve: "LOX".to_string(),
+    owner: Some("vent".to_string()),
+    previous: Some("fill".to_string()),
+  });
+
+  // an unknown process is warned about but never takes the valve over
+  assert!(owners.permit(&actuate("LOX"), None, &mut reports));
+  assert_eq!(owners.owner("LOX"), Some("vent"));
+
+  owners.release("vent", &mut reports);
+  assert_eq!(owners.owner("LOX"), None);
+}
+
+#[test]
+fn only_actuations_take_valves_over() {
+  let config = Config { on_valve_conflict: ValveConflictAction::Reject, ..Config::default() };
+  let mut owners = ValveOwners::new(&config);
+  let mut reports = Vec::new();
+
+  assert!(owners.permit(&actuate("FUEL"), Some("fill"), &mut reports));
+  assert!(owners.permit(&SequenceDomainCommand::Abort, Some("vent"), &mut reports));
+  assert_eq!(owners.owner("FUEL"), Some("fill"));
+  assert_eq!(reports.len(), 1);
+
+  // releasing a sequence that owns nothing changes nothing
+  owners.release("vent", &mut reports);
+  assert_eq!(owners.owner("FUEL"), Some("fill"));
+  assert_eq!(reports.len(), 1);
+}
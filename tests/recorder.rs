@@ -0,0 +1,102 @@
+use std::{fs::{self, OpenOptions}, path::{Path, PathBuf}, process, time::Duration};
+use common::comm::VehicleState;
+use flight_computer::{clock::{Clock, ManualClock}, config::{RecorderConfig, SafingAction}, recorder::{self, Error, Reader, Record, Recorder}, status::StatusMessage};
+
+fn recorder(name: &str, clock: &ManualClock) -> (Recorder, PathBuf) {
+  let directory = std::env::temp_dir().join(format!("fc-recorder-{}-{name}", process::id()));
+  let _ = fs::remove_dir_all(&directory);
+  let config = RecorderConfig { directory: directory.clone(), ..RecorderConfig::default() };
+  (Recorder::new(&config, clock.now()), directory)
+}
+
+/// The only recording in the directory.
+fn recording(directory: &Path) -> PathBuf {
+  let recordings = recorder::list(directory).unwrap();
+  assert_eq!(recordings.len(), 1);
+  recordings[0].clone()
+}
+
+fn record_statuses(recorder: &mut Recorder, clock: &ManualClock, count: u64) {
+  for silent_ms in 0..count {
+    recorder.status(&StatusMessage::ServoLost { silent_ms, action: SafingAction::Warn });
+  }
+
+  recorder.flush(clock.now());
+}
+
+#[test]
+fn reading_stops_cleanly_at_a_truncated_frame() {
+  let clock = ManualClock::new();
+  let (mut recorder, directory) = recorder("truncated", &clock);
+  record_statuses(&mut recorder, &clock, 3);
+  let path = recording(&directory);
+  let length = fs::metadata(&path).unwrap().len();
+
+  // cut into the payload of the last frame, as a power loss might
+  OpenOptions::new().write(true).open(&path).unwrap().set_len(length - 3).unwrap();
+  let entries: Vec<_> = Reader::open(&path).unwrap().collect();
+  assert_eq!(entries.len(), 3);
+  assert!(entries[..2].iter().all(Result::is_ok));
+  assert!(matches!(entries[2], Err(Error::Truncated)));
+
+  // and into the header of the last frame, which is 8 bytes long
+  let frame_size = (length - 8) / 3;
+  OpenOptions::new().write(true).open(&path).unwrap().set_len(length - frame_size + 5).unwrap();
+  let entries: Vec<_> = Reader::open(&path).unwrap().collect();
+  assert_eq!(entries.len(), 3);
+  assert!(matches!(entries[2], Err(Error::Truncated)));
+
+  // a file cut exactly in between frames just ends early
+  OpenOptions::new().write(true).open(&path).unwrap().set_len(length - frame_size).unwrap();
+  let entries: Vec<_> = Reader::open(&path).unwrap().collect();
+  assert_eq!(entries.len(), 2);
+  assert!(entries.iter().all(Result::is_ok));
+}
+
+#[test]
+fn snapshots_are_taken_by_the_injected_clock() {
+  let clock = ManualClock::new();
+  let (mut recorder, directory) = recorder("snapshots", &clock);
+  let interval = RecorderConfig::default().snapshot_interval;
+  let state = VehicleState::new();
+
+  recorder.state(&state, clock.now());
+  clock.advance(interval - Duration::from_millis(1));
+  recorder.state(&state, clock.now());
+  clock.advance(Duration::from_millis(1));
+  recorder.state(&state, clock.now());
+  recorder.state(&state, clock.now());
+  clock.advance(interval);
+  recorder.state(&state, clock.now());
+  recorder.flush(clock.now());
+
+  let snapshots = Reader::open(&recording(&directory))
+    .unwrap()
+    .filter(|entry| matches!(entry.as_ref().unwrap().record, Record::State(_)))
+    .count();
+
+  assert_eq!(snapshots, 2);
+}
+
+#[test]
+fn recordings_of_older_versions_are_still_read() {
+  let clock = ManualClock::new();
+  let (mut recorder, directory) = recorder("versions", &clock);
+  record_statuses(&mut recorder, &clock, 2);
+  let path = recording(&directory);
+  let mut contents = fs::read(&path).unwrap();
+  assert_eq!(contents[..8], recorder::MAGIC);
+
+  contents[..8].copy_from_slice(b"FCREC\0\0\x01");
+  fs::write(&path, &contents).unwrap();
+  assert_eq!(Reader::open(&path).unwrap().filter(Result::is_ok).count(), 2);
+
+  // newer versions may contain records that can't be read yet
+  contents[..8].copy_from_slice(b"FCREC\0\0\x02");
+  fs::write(&path, &contents).unwrap();
+  assert_eq!(Reader::open(&path).unwrap().filter(Result::is_ok).count(), 2);
+
+  contents[..8].copy_from_slice(b"FCREC\0\0\x04");
+  fs::write(&path, &contents).unwrap();
+  assert!(matches!(Reader::open(&path), Err(Error::NotARecording)));
+}
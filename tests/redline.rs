@@ -0,0 +1,67 @@
+use std::time::{Duration, Instant};
+use common::comm::{sam::Unit, Measurement, VehicleState};
+use flight_computer::{config::{Config, RedlineConfig, SafingAction}, redline::Redlines, status::StatusMessage};
+
+const PERSISTENCE: Duration = Duration::from_millis(50);
+
+fn redlines() -> Redlines {
+  let mut config = Config::default();
+  config.redlines.insert("CHAMBER_PT".to_string(), RedlineConfig {
+    min: None,
+    max: Some(500.0),
+    persistence: PERSISTENCE,
+    action: SafingAction::Abort,
+  });
+
+  Redlines::new(&config)
+}
+
+fn set_reading(state: &mut VehicleState, value: f64) {
+  state.sensor_readings.insert("CHAMBER_PT".to_string(), Measurement { value, unit: Unit::Psi });
+}
+
+#[test]
+fn spikes_shorter_than_persistence_are_ignored() {
+  let mut redlines = redlines();
+  let mut state = VehicleState::new();
+  let mut reports = Vec::new();
+  let start = Instant::now();
+
+  set_reading(&mut state, 900.0);
+  assert!(redlines.check(&state, start, &mut reports).is_empty());
+
+  set_reading(&mut state, 400.0);
+  assert!(redlines.check(&state, start + PERSISTENCE - Duration::from_millis(1), &mut reports).is_empty());
+
+  // the window restarts once the reading is back within limits
+  set_reading(&mut state, 900.0);
+  assert!(redlines.check(&state, start + PERSISTENCE, &mut reports).is_empty());
+  assert!(reports.is_empty());
+}
+
+#[test]
+fn persistent_violation_trips_once_and_clears() {
+  let mut redlines = redlines();
+  let mut state = VehicleState::new();
+  let mut reports = Vec::new();
+  let start = Instant::now();
+
+  set_reading(&mut state, 900.0);
+  redlines.check(&state, start, &mut reports);
+  assert_eq!(redlines.check(&state, start + PERSISTENCE, &mut reports), vec![SafingAction::Abort]);
+  assert!(redlines.check(&state, start + PERSISTENCE * 2, &mut reports).is_empty());
+
+  set_reading(&mut state, 100.0);
+  redlines.check(&state, start + PERSISTENCE * 3, &mut reports);
+
+  assert_eq!(reports, vec![
+    StatusMessage::RedlineTripped {
+      text_id: "CHAMBER_PT".to_string(),
+      value: 900.0,
+      min: None,
+      max: Some(500.0),
+      action: SafingAction::Abort,
+    },
+    StatusMessage::RedlineCleared { text_id: "CHAMBER_PT".to_string() },
+  ]);
+}
@@ -0,0 +1,271 @@
+use std::{os::unix::net::UnixDatagram, process, thread, time::{Duration, Instant}};
+use common::comm::{flight::SequenceDomainCommand, Sequence};
+use flight_computer::{config::{Config, SequenceConfig}, mappings::Mappings, sequence::{self, OutputLine, OutputStream, SequenceState, SequenceStatus, Sequences, MAX_OUTPUT_PER_TICK, OUTPUT_CAPACITY}, status::StatusMessage};
+use support::stub_python_library;
+
+mod support;
+
+#[test]
+fn commands_are_attributed_to_the_sending_process() {
+  let (sequence_side, fc_side) = UnixDatagram::pair().unwrap();
+  fc_side.set_nonblocking(true).unwrap();
+  sequence::pass_credentials(&fc_side).unwrap();
+
+  let command = postcard::to_allocvec(&SequenceDomainCommand::Abort).unwrap();
+  sequence_side.send(&command).unwrap();
+
+  let commands = sequence::pull_commands(&fc_side);
+  assert_eq!(commands.len(), 1);
+  assert_eq!(commands[0].0, Some(process::id()));
+}
+
+/// Starts a sequence without any mappings.
+fn start(sequences: &mut Sequences, name: &str, script: &str) {
+  stub_python_library();
+  let sequence = Sequence { name: name.to_string(), script: script.to_string() };
+  assert!(sequence::execute(&Mappings::default(), &sequence, sequences, Instant::now()));
+}
+
+/// Reaps the sequences until the named one has exited, returning every
+/// status reported along the way.
+fn await_exit(sequences: &mut Sequences, name: &str) -> Vec<SequenceStatus> {
+  await_exit_as_of(sequences, name, Instant::now())
+}
+
+/// Reaps the sequences as of `now` until the named one has exited, returning
+/// every status reported along the way.
+fn await_exit_as_of(sequences: &mut Sequences, name: &str, now: Instant) -> Vec<SequenceStatus> {
+  let deadline = Instant::now() + Duration::from_secs(5);
+  let mut reported = Vec::new();
+
+  loop {
+    reported.extend(sequences.reap(now));
+
+    let exited = sequences.statuses().iter().any(|s| s.name == name && s.state != SequenceState::Running);
+    if exited {
+      return reported;
+    }
+
+    assert!(Instant::now() < deadline, "Sequence '{name}' never exited.");
+    thread::sleep(Duration::from_millis(1));
+  }
+}
+
+/// Collects output until at least `count` lines have been written.
+fn collect_output(sequences: &mut Sequences, count: usize) -> Vec<OutputLine> {
+  let deadline = Instant::now() + Duration::from_secs(5);
+  let mut lines = Vec::new();
+
+  while lines.len() < count {
+    assert!(Instant::now() < deadline, "Only received {lines:?}.");
+    lines.extend(sequences.output(&mut Vec::new()));
+    thread::sleep(Duration::from_millis(1));
+  }
+
+  lines
+}
+
+#[test]
+fn output_is_collected_line_by_line_from_both_streams() {
+  let mut sequences = Sequences::new(&Config::default());
+  start(&mut sequences, "greet", "import sys\nprint('hello')\nprint('oops', file=sys.stderr)\nprint('bye')");
+  await_exit(&mut sequences, "greet");
+
+  let lines = collect_output(&mut sequences, 3);
+  let line = |stream, line: &str| OutputLine { name: "greet".to_string(), stream, line: line.to_string() };
+  let stdout: Vec<_> = lines.iter().filter(|l| l.stream == OutputStream::Stdout).cloned().collect();
+  assert_eq!(stdout, [line(OutputStream::Stdout, "hello"), line(OutputStream::Stdout, "bye")]);
+  assert!(lines.contains(&line(OutputStream::Stderr, "oops")));
+}
+
+#[test]
+fn output_beyond_the_buffer_is_dropped_and_reported() {
+  const LINES: usize = 5000;
+
+  let mut sequences = Sequences::new(&Config::default());
+  start(&mut sequences, "chatty", &format!("[print(i) for i in range({LINES})]"));
+  await_exit(&mut sequences, "chatty");
+
+  // gives the readers time to go through everything left in the pipes
+  thread::sleep(Duration::from_millis(500));
+
+  let mut reports = Vec::new();
+  let first = sequences.output(&mut reports);
+  assert_eq!(first.len(), MAX_OUTPUT_PER_TICK);
+  assert!(sequences.has_pending_output());
+  assert_eq!(reports, [StatusMessage::SequenceOutputDropped { name: "chatty".to_string(), lines: (LINES - OUTPUT_CAPACITY) as u64 }]);
+
+  let mut lines = first;
+  reports.clear();
+
+  while sequences.has_pending_output() {
+    let batch = sequences.output(&mut reports);
+    assert!(batch.len() <= MAX_OUTPUT_PER_TICK);
+    lines.extend(batch);
+  }
+
+  // the oldest lines are kept, and the rest dropped
+  let expected: Vec<String> = (0..OUTPUT_CAPACITY).map(|i| i.to_string()).collect();
+  let received: Vec<String> = lines.into_iter().map(|l| l.line).collect();
+  assert_eq!(received, expected);
+  assert!(reports.is_empty());
+}
+
+/// The states reported for the named sequence, in order.
+fn states(reported: &[SequenceStatus], name: &str) -> Vec<SequenceState> {
+  reported.iter().filter(|s| s.name == name).map(|s| s.state).collect()
+}
+
+#[test]
+fn starting_and_finishing_are_each_reported_once() {
+  let mut sequences = Sequences::new(&Config::default());
+  start(&mut sequences, "quick", "pass");
+
+  let mut reported = await_exit(&mut sequences, "quick");
+  reported.extend(sequences.reap(Instant::now()));
+  assert_eq!(states(&reported, "quick"), [SequenceState::Running, SequenceState::Exited(0)]);
+
+  let status = &reported[1];
+  assert!(status.stopped_at.is_some_and(|stopped| stopped >= status.started_at));
+}
+
+#[test]
+fn failures_are_reported_with_their_exit_code() {
+  let mut sequences = Sequences::new(&Config::default());
+  start(&mut sequences, "exits", "raise SystemExit(3)");
+  start(&mut sequences, "raises", "raise RuntimeError('oops')");
+
+  let mut reported = await_exit(&mut sequences, "exits");
+  reported.extend(await_exit(&mut sequences, "raises"));
+  assert_eq!(states(&reported, "exits"), [SequenceState::Running, SequenceState::Exited(3)]);
+  assert_eq!(states(&reported, "raises"), [SequenceState::Running, SequenceState::Exited(1)]);
+}
+
+#[test]
+fn stopped_sequences_are_reported_as_killed() {
+  let mut sequences = Sequences::new(&Config::default());
+  start(&mut sequences, "sleepy", "import time; time.sleep(10)");
+  sequences.reap(Instant::now());
+
+  sequence::kill(&mut sequences, &"sleepy".to_string()).unwrap();
+  let reported = await_exit(&mut sequences, "sleepy");
+  assert_eq!(states(&reported, "sleepy"), [SequenceState::Killed(libc::SIGKILL)]);
+}
+
+#[test]
+fn a_sequence_restarted_before_being_reaped_still_reports_how_it_exited() {
+  let mut sequences = Sequences::new(&Config::default());
+  start(&mut sequences, "again", "raise SystemExit(2)");
+  assert_eq!(states(&sequences.reap(Instant::now()), "again"), [SequenceState::Running]);
+
+  // the first run can only be replaced once it has exited
+  let deadline = Instant::now() + Duration::from_secs(5);
+  let sequence = Sequence { name: "again".to_string(), script: "pass".to_string() };
+
+  while !sequence::execute(&Mappings::default(), &sequence, &mut sequences, Instant::now()) {
+    assert!(Instant::now() < deadline, "The first run never exited.");
+    thread::sleep(Duration::from_millis(1));
+  }
+
+  let mut reported = sequences.reap(Instant::now());
+  assert_eq!(states(&reported, "again"), [SequenceState::Exited(2), SequenceState::Running]);
+
+  reported.extend(await_exit(&mut sequences, "again"));
+  assert_eq!(states(&reported, "again").last(), Some(&SequenceState::Exited(0)));
+}
+
+/// Sequences where the named one has the given limits.
+fn limited(name: &str, limits: SequenceConfig) -> Sequences {
+  let mut config = Config::default();
+  config.sequences.insert(name.to_string(), limits);
+  Sequences::new(&config)
+}
+
+#[test]
+fn sequences_past_their_timeout_are_killed_and_reported() {
+  let timeout = Duration::from_millis(50);
+  let mut sequences = limited("slow", SequenceConfig { timeout: Some(timeout), ..SequenceConfig::default() });
+  let started = Instant::now();
+  start(&mut sequences, "slow", "import time; time.sleep(10)");
+  let deadline = sequences.deadline().unwrap();
+  assert!(deadline >= started + timeout);
+
+  // the timeout is measured by the given time rather than the wall clock
+  sequences.reap(deadline - Duration::from_millis(1));
+  assert_eq!(sequences.statuses()[0].state, SequenceState::Running);
+
+  let reported = await_exit_as_of(&mut sequences, "slow", deadline);
+  assert_eq!(states(&reported, "slow"), [SequenceState::TimedOut]);
+  assert_eq!(sequences.deadline(), None);
+}
+
+#[test]
+fn sequences_past_their_cpu_time_are_killed_and_reported() {
+  let mut sequences = limited("spin", SequenceConfig { cpu_seconds: Some(1), ..SequenceConfig::default() });
+  start(&mut sequences, "spin", "any(iter(int, 1))");
+
+  let reported = await_exit(&mut sequences, "spin");
+  assert_eq!(states(&reported, "spin"), [SequenceState::Running, SequenceState::CpuLimitExceeded]);
+}
+
+#[test]
+fn sequences_past_their_memory_are_reported_once_they_fail_to_allocate() {
+  let limits = SequenceConfig { memory_bytes: Some(256 * 1024 * 1024), ..SequenceConfig::default() };
+  let mut sequences = limited("hungry", limits.clone());
+  start(&mut sequences, "hungry", "bytearray(1024 ** 3)");
+
+  let reported = await_exit(&mut sequences, "hungry");
+  assert_eq!(states(&reported, "hungry"), [SequenceState::Running, SequenceState::MemoryLimitExceeded]);
+
+  // other failures of a sequence with a memory limit are reported as usual
+  let mut sequences = limited("fails", limits);
+  start(&mut sequences, "fails", "raise RuntimeError('oops')");
+  assert_eq!(states(&await_exit(&mut sequences, "fails"), "fails"), [SequenceState::Running, SequenceState::Exited(1)]);
+}
+
+#[test]
+fn sequences_run_at_their_niceness() {
+  let mut sequences = limited("nice", SequenceConfig { nice: Some(5), ..SequenceConfig::default() });
+  start(&mut sequences, "nice", "import os; print(os.nice(0))");
+  await_exit(&mut sequences, "nice");
+
+  assert_eq!(collect_output(&mut sequences, 1)[0].line, "5");
+}
+
+#[test]
+fn sequences_start_even_if_their_niceness_cant_be_set() {
+  // fails without CAP_SYS_NICE, which mustn't keep the sequence from running
+  let mut sequences = limited("eager", SequenceConfig { nice: Some(-20), ..SequenceConfig::default() });
+  start(&mut sequences, "eager", "pass");
+
+  let reported = await_exit(&mut sequences, "eager");
+  assert_eq!(states(&reported, "eager"), [SequenceState::Running, SequenceState::Exited(0)]);
+}
+
+#[test]
+fn commands_beyond_the_rate_are_refused_until_the_window_passes() {
+  let mut sequences = limited("burst", SequenceConfig { max_commands_per_second: Some(3), ..SequenceConfig::default() });
+  stub_python_library();
+
+  // the first window starts along with the sequence
+  let now = Instant::now();
+  let sequence = Sequence { name: "burst".to_string(), script: "import time; time.sleep(10)".to_string() };
+  assert!(sequence::execute(&Mappings::default(), &sequence, &mut sequences, now));
+
+  let mut reports = Vec::new();
+  let admitted: Vec<bool> = (0..5).map(|_| sequences.admit("burst", now, &mut reports)).collect();
+  assert_eq!(admitted, [true, true, true, false, false]);
+  assert_eq!(reports, [StatusMessage::SequenceRateLimited { name: "burst".to_string(), limit: 3 }]);
+
+  // the next window starts a second after the first
+  assert!(!sequences.admit("burst", now + Duration::from_millis(999), &mut reports));
+  assert!(sequences.admit("burst", now + Duration::from_secs(1), &mut reports));
+  assert_eq!(reports.len(), 1);
+
+  // sequences without a limit, or unknown ones, are never refused
+  assert!((0..100).all(|_| sequences.admit("other", now, &mut reports)));
+  assert_eq!(reports.len(), 1);
+
+  sequences.kill_all();
+  await_exit(&mut sequences, "burst");
+}
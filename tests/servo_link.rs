@@ -0,0 +1,178 @@
+use std::{mem, net::UdpSocket, os::fd::AsRawFd, thread, time::{Duration, Instant}};
+use common::comm::{FlightControlMessage, VehicleState};
+use flight_computer::servo::{self, FrameReader, ServoLink};
+use support::mock_servo::MockServo;
+
+mod support;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Polls the link until it yields at least one message.
+fn poll_messages(link: &mut ServoLink) -> Vec<FlightControlMessage> {
+  let deadline = Instant::now() + TIMEOUT;
+
+  loop {
+    let messages = link.poll(Instant::now());
+
+    if !messages.is_empty() {
+      return messages;
+    }
+
+    assert!(Instant::now() < deadline, "Timed out waiting for a message from the mock Servo.");
+    thread::sleep(Duration::from_millis(1));
+  }
+}
+
+fn connect(mock: &mut MockServo) -> ServoLink {
+  let mut link = ServoLink::new(vec![mock.address().to_string()], Instant::now());
+  link.poll(Instant::now());
+  mock.assert_accepts(TIMEOUT);
+
+  let deadline = Instant::now() + TIMEOUT;
+  while !link.is_connected() {
+    assert!(Instant::now() < deadline, "Timed out waiting for the link to connect.");
+    link.poll(Instant::now());
+    thread::sleep(Duration::from_millis(1));
+  }
+
+  link
+}
+
+#[test]
+fn messages_and_telemetry_flow_both_ways() {
+  let mut mock = MockServo::bind().unwrap();
+  let mut link = connect(&mut mock);
+
+  mock.send_mappings(Vec::new()).unwrap();
+  mock.send_stop_sequence("test").unwrap();
+
+  let mut messages = poll_messages(&mut link);
+  if messages.len() < 2 {
+    messages.extend(poll_messages(&mut link));
+  }
+
+  assert!(matches!(messages[0], FlightControlMessage::Mappings(ref m) if m.is_empty()));
+  assert!(matches!(messages[1], FlightControlMessage::StopSequence(ref n) if n == "test"));
+
+  let socket = UdpSocket::bind(("127.0.0.1", 0)).unwrap();
+  let address = link.address().expect("The link is connected.");
+  servo::push(&socket, address, mock.data_port(), &VehicleState::new()).unwrap();
+
+  mock.assert_state(TIMEOUT, "an empty VehicleState", |state| state.sensor_readings.is_empty());
+  assert_eq!(mock.states_received(), 1);
+}
+
+#[test]
+fn link_reconnects_after_servo_disconnects() {
+  let mut mock = MockServo::bind().unwrap();
+  let mut link = connect(&mut mock);
+
+  mock.disconnect();
+
+  let deadline = Instant::now() + TIMEOUT;
+  while link.is_connected() {
+    assert!(Instant::now() < deadline, "The link never noticed the disconnect.");
+    link.poll(Instant::now());
+    thread::sleep(Duration::from_millis(1));
+  }
+
+  // the link reconnects in the background after backing off
+  let deadline = Instant::now() + TIMEOUT;
+  while !mock.is_connected() {
+    assert!(Instant::now() < deadline, "The link never reconnected.");
+    link.poll(Instant::now());
+    let _ = mock.accept(Duration::from_millis(10));
+  }
+
+  mock.send_abort().unwrap();
+  assert!(matches!(poll_messages(&mut link)[..], [FlightControlMessage::Abort]));
+}
+
+/// A message as Servo frames it on the wire.
+fn frame(message: &FlightControlMessage) -> Vec<u8> {
+  let payload = postcard::to_allocvec(message).unwrap();
+  let mut frame = (payload.len() as u16).to_be_bytes().to_vec();
+  frame.extend_from_slice(&payload);
+  frame
+}
+
+#[test]
+fn partial_frames_are_kept_until_complete() {
+  let mut reader = FrameReader::default();
+  let frame = frame(&FlightControlMessage::StopSequence("press".to_string()));
+
+  // even the length prefix may arrive split
+  for (i, byte) in frame[..frame.len() - 1].iter().enumerate() {
+    reader.extend(&[*byte]);
+    assert!(reader.messages().is_empty());
+    assert_eq!(reader.pending(), i + 1);
+  }
+
+  reader.extend(&frame[frame.len() - 1..]);
+  assert!(matches!(reader.messages()[..], [FlightControlMessage::StopSequence(ref n)] if n == "press"));
+  assert_eq!(reader.pending(), 0);
+}
+
+#[test]
+fn several_frames_in_one_read_are_all_returned_in_order() {
+  let mut reader = FrameReader::new();
+  let mut bytes = frame(&FlightControlMessage::Abort);
+  bytes.extend(frame(&FlightControlMessage::StopSequence("press".to_string())));
+  bytes.extend(frame(&FlightControlMessage::Mappings(Vec::new())));
+
+  // along with the start of the next one
+  let next = frame(&FlightControlMessage::Abort);
+  bytes.extend_from_slice(&next[..1]);
+  reader.extend(&bytes);
+
+  let messages = reader.messages();
+  assert_eq!(messages.len(), 3);
+  assert!(matches!(messages[0], FlightControlMessage::Abort));
+  assert!(matches!(messages[1], FlightControlMessage::StopSequence(ref n) if n == "press"));
+  assert!(matches!(messages[2], FlightControlMessage::Mappings(ref m) if m.is_empty()));
+  assert_eq!(reader.pending(), 1);
+
+  reader.extend(&next[1..]);
+  assert!(matches!(reader.messages()[..], [FlightControlMessage::Abort]));
+}
+
+#[test]
+fn frames_with_an_oversized_length_are_skipped_without_losing_sync() {
+  let mut reader = FrameReader::new();
+
+  // a length far beyond any real message, followed by a little garbage
+  reader.extend(&u16::MAX.to_be_bytes());
+  reader.extend(&[0xff; 10]);
+  assert!(reader.messages().is_empty());
+  assert_eq!(reader.pending(), 12);
+
+  // once the whole bogus frame has arrived, it's dropped as undecodable and
+  // the frame after it is read as usual
+  reader.extend(&vec![0xff; u16::MAX as usize - 10]);
+  reader.extend(&frame(&FlightControlMessage::Abort));
+  assert!(matches!(reader.messages()[..], [FlightControlMessage::Abort]));
+  assert_eq!(reader.pending(), 0);
+}
+
+/// Reads an integer socket option of the stream.
+fn option(stream: &std::net::TcpStream, level: libc::c_int, name: libc::c_int) -> libc::c_int {
+  let mut value: libc::c_int = 0;
+  let mut size = mem::size_of::<libc::c_int>() as libc::socklen_t;
+  let result = unsafe { libc::getsockopt(stream.as_raw_fd(), level, name, (&mut value as *mut libc::c_int).cast(), &mut size) };
+  assert_eq!(result, 0);
+  value
+}
+
+#[test]
+fn dead_connections_are_noticed_through_keepalives() {
+  let mut mock = MockServo::bind().unwrap();
+  let link = connect(&mut mock);
+  let stream = link.stream().unwrap();
+
+  // a Servo that vanishes without closing the connection is noticed within
+  // seconds, rather than the kernel's default of over two hours
+  assert_eq!(option(stream, libc::SOL_SOCKET, libc::SO_KEEPALIVE), 1);
+  assert!(option(stream, libc::IPPROTO_TCP, libc::TCP_KEEPIDLE) <= 5);
+  assert!(option(stream, libc::IPPROTO_TCP, libc::TCP_KEEPINTVL) <= 5);
+  assert!(option(stream, libc::IPPROTO_TCP, libc::TCP_KEEPCNT) <= 5);
+}
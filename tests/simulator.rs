@@ -0,0 +1,121 @@
+use std::{net::{IpAddr, SocketAddr, UdpSocket}, os::unix::net::UnixDatagram, process, thread, time::{Duration, Instant}};
+use common::comm::{flight::SequenceDomainCommand, sam::ChannelType, NodeMapping, SensorType, ValveState};
+use flight_computer::{clock::ManualClock, computer::FlightComputer, config::Config, sequence::SequenceState, simulator::{BoardConfig, Faults, Sensor, SimulatedBoard}, status::StatusMessage};
+use mmap_sync::synchronizer::Synchronizer;
+use support::{mapping, mock_servo::MockServo, python_bytes, stub_python_library};
+
+mod support;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The loopback address that the simulated SAM binds, which no other test
+/// uses, so that its command port can be fixed.
+const BOARD_ADDRESS: [u8; 4] = [127, 0, 0, 42];
+const COMMAND_PORT: u16 = 18378;
+
+/// Steps the board and the flight computer, moving the clock forward a little
+/// each time, until the condition holds.
+fn run_until(
+  fc: &mut FlightComputer<ManualClock>,
+  board: &mut SimulatedBoard<ManualClock>,
+  what: &str,
+  condition: impl Fn(&FlightComputer<ManualClock>, &SimulatedBoard<ManualClock>) -> bool,
+) {
+  let deadline = Instant::now() + TIMEOUT;
+
+  while !condition(fc, board) {
+    assert!(Instant::now() < deadline, "Timed out waiting for {what}.");
+    board.step().unwrap();
+    fc.tick();
+    fc.clock().advance(Duration::from_millis(5));
+    thread::sleep(Duration::from_millis(1));
+  }
+}
+
+#[test]
+fn simulated_sam_telemetry_and_commands_are_understood_by_the_fc() {
+  stub_python_library();
+  let clock = ManualClock::new();
+  let mut mock = MockServo::bind().unwrap();
+  let directory = std::env::temp_dir().join(format!("fc-test-{}-simulator", process::id()));
+  let _ = std::fs::remove_dir_all(&directory);
+  std::fs::create_dir_all(&directory).unwrap();
+
+  let mut config = Config {
+    servo_addresses: vec![mock.address().to_string()],
+    servo_data_port: mock.data_port(),
+    servo_status_port: mock.status_port(),
+    device_command_port: COMMAND_PORT,
+    ..Config::default()
+  };
+  config.recorder.enabled = false;
+  config.store.enabled = false;
+
+  let socket = UdpSocket::bind(("127.0.0.1", 0)).unwrap();
+  socket.set_nonblocking(true).unwrap();
+  let command_path = directory.join("commands.sock");
+  let command_socket = UnixDatagram::bind(&command_path).unwrap();
+  command_socket.set_nonblocking(true).unwrap();
+  let synchronizer = Synchronizer::new(directory.join("vehicle-state").as_os_str());
+  let mut fc = FlightComputer::new(config, clock.clone(), socket, command_socket, synchronizer).unwrap();
+
+  let board = BoardConfig {
+    id: "sam-01".to_string(),
+    address: IpAddr::from(BOARD_ADDRESS),
+    rate: Duration::from_millis(10),
+    sensors: vec![Sensor { channel: 1, channel_type: ChannelType::CurrentLoop, value: 2.4, noise: 0.0 }],
+    valves: vec![2],
+    faults: Faults::default(),
+  };
+  let fc_address = SocketAddr::from(([127, 0, 0, 1], fc.socket().local_addr().unwrap().port()));
+  let mut board = SimulatedBoard::new(board, fc_address, COMMAND_PORT, clock.clone()).unwrap();
+
+  fc.tick();
+  mock.assert_accepts(TIMEOUT);
+  mock.send_mappings(vec![
+    mapping("PT1", SensorType::Pt, 1),
+    NodeMapping { powered_threshold: Some(0.25), ..mapping("IPV", SensorType::Valve, 2) },
+  ]).unwrap();
+
+  run_until(&mut fc, &mut board, "the board to connect", |fc, board| {
+    board.is_connected() && fc.devices().iter().any(|d| d.get_board_id() == "sam-01") && !fc.mappings().is_empty()
+  });
+
+  run_until(&mut fc, &mut board, "telemetry from the board", |fc, _| {
+    let valve = fc.state().valve_states.get("IPV").map(|v| v.actual);
+    fc.state().sensor_readings.contains_key("PT1") && valve == Some(ValveState::Closed)
+  });
+
+  // a sequence opens the valve, which the board follows
+  let command = SequenceDomainCommand::ActuateValve { valve: "IPV".to_string(), state: ValveState::Open };
+  let script = format!(
+    "import socket, time\n\
+     socket.socket(socket.AF_UNIX, socket.SOCK_DGRAM).sendto({}, {:?})\n\
+     time.sleep(10)\n",
+    python_bytes(&postcard::to_allocvec(&command).unwrap()),
+    command_path,
+  );
+  mock.send_sequence("open", &script).unwrap();
+
+  run_until(&mut fc, &mut board, "the valve to open", |fc, board| {
+    let valve = fc.state().valve_states.get("IPV");
+    board.is_powered(2) == Some(true) && valve.is_some_and(|v| v.commanded == ValveState::Open && v.actual == ValveState::Open)
+  });
+
+  mock.send_stop_sequence("open").unwrap();
+  let deadline = Instant::now() + TIMEOUT;
+
+  loop {
+    fc.tick();
+
+    let stopped = mock.wait_for_status(Duration::from_millis(1), |s| {
+      matches!(s, StatusMessage::Sequence(status) if status.name == "open" && matches!(status.state, SequenceState::Killed(_)))
+    });
+
+    if stopped.is_ok() {
+      break;
+    }
+
+    assert!(Instant::now() < deadline, "The sequence was never stopped.");
+  }
+}
@@ -0,0 +1,36 @@
+use std::{fs, process};
+use common::comm::Sequence;
+use flight_computer::{config::StoreConfig, store::{self, Store}};
+
+fn store(name: &str) -> Store {
+  let directory = std::env::temp_dir().join(format!("fc-store-{}-{name}", process::id()));
+  let _ = fs::remove_dir_all(&directory);
+  Store::new(&StoreConfig { enabled: true, directory })
+}
+
+#[test]
+fn abort_sequence_survives_a_restart() {
+  let store = store("round-trip");
+  let sequence = Sequence { name: "abort".to_string(), script: "print('abort')".to_string() };
+  assert!(store.load_abort_sequence().unwrap().is_none());
+
+  store.save_abort_sequence(&sequence).unwrap();
+  let restored = store.load_abort_sequence().unwrap().unwrap();
+  assert_eq!(restored.script, sequence.script);
+  assert_eq!(store::version(&restored), store::version(&sequence));
+}
+
+#[test]
+fn corrupted_copies_are_not_restored() {
+  let directory = std::env::temp_dir().join(format!("fc-store-{}-corrupted", process::id()));
+  let store = store("corrupted");
+  let sequence = Sequence { name: "abort".to_string(), script: "print('abort')".to_string() };
+  store.save_abort_sequence(&sequence).unwrap();
+
+  let path = directory.join("abort_sequence.bin");
+  let mut contents = fs::read(&path).unwrap();
+  *contents.last_mut().unwrap() ^= 0xff;
+  fs::write(&path, contents).unwrap();
+
+  assert!(store.load_abort_sequence().is_err());
+}
@@ -0,0 +1,288 @@
+//! A local stand-in for Servo, used to test the FC's control link without a
+//! ground station.
+//!
+//! The mock listens for the FC's TCP connection, checks the identity that
+//! `servo::establish` writes, sends length-prefixed FlightControlMessages the
+//! same way Servo does and receives the VehicleState datagrams that
+//! `servo::push` sends to the data port, along with the status reports that
+//! `servo::report` sends to the status port.
+
+use std::{fmt, io::{self, Read, Write}, net::{SocketAddr, TcpListener, TcpStream, UdpSocket}, thread, time::{Duration, Instant}};
+use common::comm::{Computer, FlightControlMessage, NodeMapping, Sequence, VehicleState};
+use postcard::experimental::max_size::MaxSize;
+use flight_computer::status::StatusMessage;
+
+/// How long the mock sleeps in between checks while waiting for something.
+const POLL_PERIOD: Duration = Duration::from_millis(1);
+
+pub struct MockServo {
+  listener: TcpListener,
+  data_socket: UdpSocket,
+  status_socket: UdpSocket,
+  stream: Option<TcpStream>,
+  latest_state: Option<VehicleState>,
+  states_received: usize,
+}
+
+impl MockServo {
+  /// Binds the control, data and status sockets to ephemeral ports on
+  /// localhost.
+  pub fn bind() -> Result<Self> {
+    let localhost = SocketAddr::from(([127, 0, 0, 1], 0));
+    Self::bind_to(localhost, localhost, localhost)
+  }
+
+  pub fn bind_to(control: SocketAddr, data: SocketAddr, status: SocketAddr) -> Result<Self> {
+    let listener = TcpListener::bind(control)?;
+    listener.set_nonblocking(true)?;
+    let data_socket = UdpSocket::bind(data)?;
+    data_socket.set_nonblocking(true)?;
+    let status_socket = UdpSocket::bind(status)?;
+    status_socket.set_nonblocking(true)?;
+
+    Ok(MockServo {
+      listener,
+      data_socket,
+      status_socket,
+      stream: None,
+      latest_state: None,
+      states_received: 0,
+    })
+  }
+
+  /// The address that the FC should be configured to connect to.
+  pub fn address(&self) -> SocketAddr {
+    self.listener.local_addr().expect("A bound listener always has an address.")
+  }
+
+  /// The port that the FC should be configured to push telemetry to.
+  pub fn data_port(&self) -> u16 {
+    self.data_socket.local_addr().expect("A bound socket always has an address.").port()
+  }
+
+  /// The port that the FC should be configured to send status reports to.
+  pub fn status_port(&self) -> u16 {
+    self.status_socket.local_addr().expect("A bound socket always has an address.").port()
+  }
+
+  pub fn is_connected(&self) -> bool {
+    self.stream.is_some()
+  }
+
+  /// Waits for the FC to connect and checks that it identified itself as the
+  /// flight computer.
+  pub fn accept(&mut self, timeout: Duration) -> Result<()> {
+    let deadline = Instant::now() + timeout;
+
+    let mut stream = loop {
+      match self.listener.accept() {
+        Ok((stream, _)) => break stream,
+        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {},
+        Err(e) => return Err(Error::Transport(e)),
+      };
+
+      if Instant::now() >= deadline {
+        return Err(Error::TimedOut("the FC to connect"));
+      }
+
+      thread::sleep(POLL_PERIOD);
+    };
+
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(deadline.saturating_duration_since(Instant::now()).max(POLL_PERIOD)))?;
+
+    let mut identity = [0; Computer::POSTCARD_MAX_SIZE];
+    stream.read_exact(&mut identity)?;
+
+    match postcard::from_bytes::<Computer>(&identity).map_err(Error::Deserialization)? {
+      Computer::Flight => {},
+      other => return Err(Error::WrongIdentity(other)),
+    };
+
+    stream.set_read_timeout(None)?;
+    stream.set_nodelay(true)?;
+    self.stream = Some(stream);
+    Ok(())
+  }
+
+  /// Closes the control connection, as if Servo had gone down.
+  pub fn disconnect(&mut self) {
+    self.stream = None;
+  }
+
+  /// Sends a message to the FC, prefixed with its length.
+  pub fn send(&mut self, message: &FlightControlMessage) -> Result<()> {
+    let Some(stream) = &mut self.stream else {
+      return Err(Error::NotConnected);
+    };
+
+    let payload = postcard::to_allocvec(message).map_err(Error::Serialization)?;
+    let size = u16::try_from(payload.len()).map_err(|_| Error::TooLarge(payload.len()))?;
+
+    let mut frame = Vec::with_capacity(payload.len() + 2);
+    frame.extend_from_slice(&size.to_be_bytes());
+    frame.extend_from_slice(&payload);
+    stream.write_all(&frame)?;
+    Ok(())
+  }
+
+  pub fn send_mappings(&mut self, mappings: Vec<NodeMapping>) -> Result<()> {
+    self.send(&FlightControlMessage::Mappings(mappings))
+  }
+
+  pub fn send_sequence(&mut self, name: &str, script: &str) -> Result<()> {
+    self.send(&FlightControlMessage::Sequence(Sequence { name: name.to_string(), script: script.to_string() }))
+  }
+
+  pub fn send_abort(&mut self) -> Result<()> {
+    self.send(&FlightControlMessage::Abort)
+  }
+
+  pub fn send_stop_sequence(&mut self, name: &str) -> Result<()> {
+    self.send(&FlightControlMessage::StopSequence(name.to_string()))
+  }
+
+  /// Receives every VehicleState datagram sent since the last call, keeping
+  /// the most recent one. Returns how many were received.
+  pub fn receive_states(&mut self) -> Result<usize> {
+    let mut buf = vec![0; u16::MAX as usize];
+    let mut received = 0;
+
+    loop {
+      let size = match self.data_socket.recv(&mut buf) {
+        Ok(size) => size,
+        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(received),
+        Err(e) => return Err(Error::Transport(e)),
+      };
+
+      self.latest_state = Some(postcard::from_bytes(&buf[..size]).map_err(Error::Deserialization)?);
+      self.states_received += 1;
+      received += 1;
+    }
+  }
+
+  /// Receives every status report sent since the last call.
+  pub fn receive_statuses(&mut self) -> Result<Vec<StatusMessage>> {
+    let mut buf = vec![0; u16::MAX as usize];
+    let mut statuses = Vec::new();
+
+    loop {
+      let size = match self.status_socket.recv(&mut buf) {
+        Ok(size) => size,
+        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(statuses),
+        Err(e) => return Err(Error::Transport(e)),
+      };
+
+      statuses.push(postcard::from_bytes(&buf[..size]).map_err(Error::Deserialization)?);
+    }
+  }
+
+  /// The most recent VehicleState received from the FC.
+  pub fn latest_state(&self) -> Option<&VehicleState> {
+    self.latest_state.as_ref()
+  }
+
+  /// How many VehicleStates have been received in total.
+  pub fn states_received(&self) -> usize {
+    self.states_received
+  }
+
+  /// Waits until a VehicleState satisfying the predicate is received.
+  pub fn wait_for_state(&mut self, timeout: Duration, predicate: impl Fn(&VehicleState) -> bool) -> Result<&VehicleState> {
+    let deadline = Instant::now() + timeout;
+
+    loop {
+      if self.receive_states()? > 0 && self.latest_state.as_ref().is_some_and(&predicate) {
+        break;
+      }
+
+      if Instant::now() >= deadline {
+        return Err(Error::TimedOut("a matching VehicleState"));
+      }
+
+      thread::sleep(POLL_PERIOD);
+    }
+
+    Ok(self.latest_state.as_ref().expect("A state was just received."))
+  }
+
+  /// Waits until a status report satisfying the predicate is received,
+  /// returning every report received in the meantime.
+  pub fn wait_for_status(&mut self, timeout: Duration, predicate: impl Fn(&StatusMessage) -> bool) -> Result<Vec<StatusMessage>> {
+    let deadline = Instant::now() + timeout;
+    let mut statuses = Vec::new();
+
+    loop {
+      let received = self.receive_statuses()?;
+      let found = received.iter().any(&predicate);
+      statuses.extend(received);
+
+      if found {
+        return Ok(statuses);
+      }
+
+      if Instant::now() >= deadline {
+        return Err(Error::TimedOut("a matching status report"));
+      }
+
+      thread::sleep(POLL_PERIOD);
+    }
+  }
+
+  /// Panics unless the FC connects and identifies itself within the timeout.
+  pub fn assert_accepts(&mut self, timeout: Duration) {
+    if let Err(e) = self.accept(timeout) {
+      panic!("The FC didn't connect to the mock Servo: {e}");
+    }
+  }
+
+  /// Panics unless the FC pushes a VehicleState satisfying the predicate
+  /// within the timeout.
+  pub fn assert_state(&mut self, timeout: Duration, description: &str, predicate: impl Fn(&VehicleState) -> bool) {
+    if let Err(e) = self.wait_for_state(timeout, predicate) {
+      panic!("Expected {description}, but {e} Latest state: {:#?}", self.latest_state);
+    }
+  }
+
+  /// Panics unless the FC sends a status report satisfying the predicate
+  /// within the timeout. Returns every report received in the meantime.
+  pub fn assert_status(&mut self, timeout: Duration, description: &str, predicate: impl Fn(&StatusMessage) -> bool) -> Vec<StatusMessage> {
+    match self.wait_for_status(timeout, predicate) {
+      Ok(statuses) => statuses,
+      Err(e) => panic!("Expected {description}, but {e}"),
+    }
+  }
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug)]
+pub enum Error {
+  NotConnected,
+  TimedOut(&'static str),
+  WrongIdentity(Computer),
+  TooLarge(usize),
+  Serialization(postcard::Error),
+  Deserialization(postcard::Error),
+  Transport(io::Error),
+}
+
+impl From<io::Error> for Error {
+  fn from(error: io::Error) -> Self {
+    Error::Transport(error)
+  }
+}
+
+impl fmt::Display for Error {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::NotConnected => write!(f, "the FC isn't connected to the mock Servo."),
+      Self::TimedOut(what) => write!(f, "timed out waiting for {what}."),
+      Self::WrongIdentity(computer) => write!(f, "the connecting computer identified itself as {computer:?}."),
+      Self::TooLarge(size) => write!(f, "a message of {size} bytes doesn't fit in a frame."),
+      Self::Serialization(e) => write!(f, "couldn't serialize a message: {e}"),
+      Self::Deserialization(e) => write!(f, "couldn't deserialize a message: {e}"),
+      Self::Transport(e) => write!(f, "the mock Servo's socket raised an error: {e}"),
+    }
+  }
+}
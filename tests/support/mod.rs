@@ -0,0 +1,58 @@
+//! Helpers shared by the integration tests.
+//!
+//! This lives under `support` rather than the usual `tests/common`, which
+//! would shadow the `common` crate. Each test binary only uses part of it.
+
+#![allow(dead_code)]
+
+use std::{env, fs, process, sync::Once};
+use common::comm::{Computer, NodeMapping, SensorType};
+
+pub mod mock_servo;
+
+/// Just enough of the `common` python package for the definitions that the FC
+/// prepends to every script.
+const PYTHON_LIBRARY: &str = "\
+class Sensor:
+    def __init__(self, name):
+        self.name = name
+
+class Valve(Sensor):
+    pass
+";
+
+/// Lets sequences started by the tests run without the `common` python
+/// package, which every script imports before anything else. Scripts talk to
+/// the FC through the standard library instead.
+pub fn stub_python_library() {
+  static STUB: Once = Once::new();
+
+  STUB.call_once(|| {
+    let directory = env::temp_dir().join(format!("fc-test-{}-python", process::id()));
+    fs::create_dir_all(&directory).unwrap();
+    fs::write(directory.join("common.py"), PYTHON_LIBRARY).unwrap();
+    env::set_var("PYTHONPATH", directory);
+  });
+}
+
+/// A python expression for the given bytes, such as a serialized command.
+pub fn python_bytes(bytes: &[u8]) -> String {
+  let values: Vec<String> = bytes.iter().map(u8::to_string).collect();
+  format!("bytes([{}])", values.join(", "))
+}
+
+/// A mapping of a channel on `sam-01` without limits or an offset.
+pub fn mapping(text_id: &str, sensor_type: SensorType, channel: u32) -> NodeMapping {
+  NodeMapping {
+    text_id: text_id.to_string(),
+    board_id: "sam-01".to_string(),
+    sensor_type,
+    channel,
+    computer: Computer::Flight,
+    max: None,
+    min: None,
+    calibrated_offset: 0.0,
+    powered_threshold: None,
+    normally_closed: None,
+  }
+}
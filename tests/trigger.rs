@@ -0,0 +1,179 @@
+use std::{thread, time::{Duration, Instant}};
+use common::comm::{Measurement, SensorType, Trigger, Unit, VehicleState};
+use flight_computer::{config::Config, mappings::Mappings, sequence::{SequenceState, Sequences}, status::StatusMessage, trigger::{self, Expression, Triggers}};
+use support::{mapping, stub_python_library};
+
+mod support;
+
+fn state(readings: &[(&str, f64)]) -> VehicleState {
+  let mut state = VehicleState::new();
+
+  for &(text_id, value) in readings {
+    state.sensor_readings.insert(text_id.to_string(), Measurement { value, unit: Unit::Psi });
+  }
+
+  state
+}
+
+fn evaluate(condition: &str, readings: &[(&str, f64)]) -> Result<f64, String> {
+  Expression::parse(condition).unwrap().evaluate(&state(readings))
+}
+
+fn mappings() -> Mappings {
+  Mappings::new(vec![
+    mapping("PT1", SensorType::Pt, 0),
+    mapping("PT2", SensorType::Pt, 1),
+    mapping("IPV", SensorType::Valve, 2),
+  ])
+}
+
+fn trigger(name: &str, condition: &str) -> Trigger {
+  Trigger { name: name.to_string(), condition: condition.to_string(), script: "pass".to_string(), active: true }
+}
+
+#[test]
+fn arithmetic_binds_tighter_than_comparisons_and_logic() {
+  assert_eq!(evaluate("1 + 2 * 3", &[]), Ok(7.0));
+  assert_eq!(evaluate("(1 + 2) * 3", &[]), Ok(9.0));
+  assert_eq!(evaluate("10 - 4 - 3", &[]), Ok(3.0));
+  assert_eq!(evaluate("-2 * -3", &[]), Ok(6.0));
+  assert_eq!(evaluate("1 + 2 * 3 == 7", &[]), Ok(1.0));
+  assert_eq!(evaluate("PT1 / 2 > 100", &[("PT1", 250.0)]), Ok(1.0));
+
+  // `and` binds tighter than `or`, and `not` tighter than both
+  assert_eq!(evaluate("True or True and False", &[]), Ok(1.0));
+  assert_eq!(evaluate("(True or True) and False", &[]), Ok(0.0));
+  assert_eq!(evaluate("not False and False", &[]), Ok(0.0));
+  assert_eq!(evaluate("not (False and False)", &[]), Ok(1.0));
+  assert_eq!(evaluate("not not True", &[]), Ok(1.0));
+}
+
+#[test]
+fn logic_is_evaluated_over_sensor_readings() {
+  let readings = [("PT1", 600.0), ("PT2", 50.0)];
+
+  assert_eq!(evaluate("PT1 > 500 and PT2 < 100", &readings), Ok(1.0));
+  assert_eq!(evaluate("PT1 > 500 and PT2 > 100", &readings), Ok(0.0));
+  assert_eq!(evaluate("PT1 < 500 or PT2 < 100", &readings), Ok(1.0));
+  assert_eq!(evaluate("PT1 < 500 or PT2 > 100", &readings), Ok(0.0));
+  assert_eq!(evaluate("not PT1 > 500", &readings), Ok(0.0));
+  assert_eq!(evaluate("PT1 >= 600 and PT2 <= 50 and PT1 != PT2", &readings), Ok(1.0));
+}
+
+#[test]
+fn comparisons_chain_like_python() {
+  assert_eq!(evaluate("0 < PT1 < 10", &[("PT1", 5.0)]), Ok(1.0));
+  assert_eq!(evaluate("0 < PT1 < 10", &[("PT1", 10.0)]), Ok(0.0));
+  assert_eq!(evaluate("0 < PT1 <= 10", &[("PT1", 10.0)]), Ok(1.0));
+
+  // each comparison is made against its neighbour, not the previous result,
+  // which would make `3 > 2 > 1` false in C
+  assert_eq!(evaluate("3 > 2 > 1", &[]), Ok(1.0));
+  assert_eq!(evaluate("1 < 3 > 2 == 2", &[]), Ok(1.0));
+  assert_eq!(evaluate("1 < 3 > 4", &[]), Ok(0.0));
+}
+
+#[test]
+fn malformed_conditions_are_not_parsed() {
+  for condition in ["", "PT1 >", "(PT1 > 1", "PT1 > 1)", "PT1 = 1", "PT1 > 1 2", "PT1 & PT2", "1.2.3 > 0", "and True"] {
+    assert!(Expression::parse(condition).is_err(), "'{condition}' was parsed.");
+  }
+}
+
+#[test]
+fn unknown_sensors_fail_evaluation() {
+  assert!(evaluate("PT3 > 1", &[("PT1", 2.0)]).is_err());
+
+  // like python, the right side is skipped once the result is known
+  assert_eq!(evaluate("True or PT3 > 1", &[]), Ok(1.0));
+  assert!(evaluate("False or PT3 > 1", &[]).is_err());
+}
+
+#[test]
+fn triggers_are_rejected_unless_every_sensor_is_mapped() {
+  let mut triggers = Triggers::new();
+  let mut reports = Vec::new();
+  let mappings = mappings();
+
+  trigger::register(&mut triggers, trigger("valid", "PT1 > 1 and PT2 < 2"), &mappings, &mut reports);
+  trigger::register(&mut triggers, trigger("malformed", "PT1 >"), &mappings, &mut reports);
+  trigger::register(&mut triggers, trigger("unknown", "PT1 > 1 or PT3 > 1"), &mappings, &mut reports);
+  trigger::register(&mut triggers, trigger("valve", "IPV > 0"), &mappings, &mut reports);
+  trigger::register(&mut triggers, trigger("valve reading", "IPV_V > 20 and IPV_I < 0.1"), &mappings, &mut reports);
+  trigger::register(&mut triggers, trigger("sensor reading", "PT1_V > 20"), &mappings, &mut reports);
+
+  let mut armed: Vec<_> = triggers.keys().collect();
+  armed.sort();
+  assert_eq!(armed, ["valid", "valve reading"]);
+
+  let rejected: Vec<&str> = reports
+    .iter()
+    .map(|report| match report {
+      StatusMessage::TriggerRejected { name, .. } => name.as_str(),
+      other => panic!("Unexpected report {other:?}"),
+    })
+    .collect();
+
+  assert_eq!(rejected, ["malformed", "unknown", "valve", "sensor reading"]);
+}
+
+#[test]
+fn rejected_triggers_leave_the_previous_definition_armed() {
+  let mut triggers = Triggers::new();
+  let mut reports = Vec::new();
+  let mappings = mappings();
+
+  trigger::register(&mut triggers, trigger("vent", "PT1 > 1"), &mappings, &mut reports);
+  trigger::register(&mut triggers, trigger("vent", "PT3 > 1"), &mappings, &mut reports);
+
+  assert!(triggers.contains_key("vent"));
+  assert_eq!(reports.len(), 1);
+}
+
+/// Reaps the sequences until the named one has exited.
+fn await_exit(sequences: &mut Sequences, name: &str) {
+  let deadline = Instant::now() + Duration::from_secs(5);
+
+  loop {
+    sequences.reap(Instant::now());
+
+    let exited = sequences.statuses().iter().any(|s| s.name == name && s.state != SequenceState::Running);
+    if exited {
+      return;
+    }
+
+    assert!(Instant::now() < deadline, "Sequence '{name}' never exited.");
+    thread::sleep(Duration::from_millis(1));
+  }
+}
+
+#[test]
+fn triggers_fire_once_each_time_their_condition_becomes_true() {
+  stub_python_library();
+  let mappings = mappings();
+  let mut sequences = Sequences::new(&Config::default());
+  let mut triggers = Triggers::new();
+  let mut reports = Vec::new();
+  trigger::register(&mut triggers, trigger("vent", "PT1 > 500"), &mappings, &mut reports);
+  trigger::register(&mut triggers, Trigger { active: false, ..trigger("inactive", "PT1 > 500") }, &mappings, &mut reports);
+
+  let low = state(&[("PT1", 100.0)]);
+  let high = state(&[("PT1", 600.0)]);
+  let started = |sequences: &mut Sequences| sequences.reap(Instant::now()).into_iter().filter(|s| s.state == SequenceState::Running).count();
+
+  trigger::check(&mut triggers, &low, &mappings, &mut sequences, Instant::now());
+  assert_eq!(started(&mut sequences), 0);
+
+  trigger::check(&mut triggers, &high, &mappings, &mut sequences, Instant::now());
+  assert_eq!(started(&mut sequences), 1);
+  await_exit(&mut sequences, "vent");
+
+  // staying true doesn't fire again, even though the sequence has exited
+  trigger::check(&mut triggers, &high, &mappings, &mut sequences, Instant::now());
+  assert_eq!(started(&mut sequences), 0);
+
+  trigger::check(&mut triggers, &low, &mappings, &mut sequences, Instant::now());
+  trigger::check(&mut triggers, &high, &mappings, &mut sequences, Instant::now());
+  assert_eq!(started(&mut sequences), 1);
+  assert!(sequences.statuses().iter().all(|s| s.name == "vent"));
+}
@@ -0,0 +1,193 @@
+use std::time::{Duration, Instant};
+use common::comm::{CompositeValveState, ValveState, VehicleState};
+use flight_computer::{config::{Config, ValveConfig}, status::StatusMessage, valve::{MismatchDetector, ValveEstimators}};
+
+fn set_valve(state: &mut VehicleState, commanded: ValveState, actual: ValveState) {
+  state.valve_states.insert("FU_VENT".to_string(), CompositeValveState { commanded, actual });
+}
+
+#[test]
+fn mismatch_is_flagged_after_settle_time_and_cleared_once_reached() {
+  let settle_time = ValveConfig::default().settle_time;
+  let mut detector = MismatchDetector::new(&Config::default());
+  let mut state = VehicleState::new();
+  let mut reports = Vec::new();
+  let start = Instant::now();
+
+  set_valve(&mut state, ValveState::Open, ValveState::Closed);
+  assert!(!detector.check(&state, start, &mut reports));
+  assert!(!detector.check(&state, start + settle_time - Duration::from_millis(1), &mut reports));
+  assert!(reports.is_empty());
+
+  assert!(!detector.check(&state, start + settle_time, &mut reports));
+  assert_eq!(reports, vec![StatusMessage::ValveMismatch {
+    valve: "FU_VENT".to_string(),
+    commanded: ValveState::Open,
+    actual: ValveState::Closed,
+  }]);
+
+  // only flagged once
+  reports.clear();
+  detector.check(&state, start + settle_time * 2, &mut reports);
+  assert!(reports.is_empty());
+
+  set_valve(&mut state, ValveState::Open, ValveState::Open);
+  detector.check(&state, start + settle_time * 3, &mut reports);
+  assert_eq!(reports, vec![StatusMessage::ValveMismatchCleared { valve: "FU_VENT".to_string() }]);
+}
+
+#[test]
+fn new_command_restarts_settle_time() {
+  let valve = ValveConfig { abort_on_mismatch: true, ..ValveConfig::default() };
+  let settle_time = valve.settle_time;
+  let mut config = Config::default();
+  config.valves.insert("FU_VENT".to_string(), valve);
+
+  let mut detector = MismatchDetector::new(&config);
+  let mut state = VehicleState::new();
+  let mut reports = Vec::new();
+  let start = Instant::now();
+
+  set_valve(&mut state, ValveState::Open, ValveState::Closed);
+  detector.check(&state, start, &mut reports);
+
+  set_valve(&mut state, ValveState::Closed, ValveState::Open);
+  assert!(!detector.check(&state, start + settle_time, &mut reports));
+  assert!(reports.is_empty());
+
+  assert!(detector.check(&state, start + settle_time * 2, &mut reports));
+  assert_eq!(reports.len(), 1);
+}
+
+#[test]
+fn repeating_a_command_restarts_settle_time() {
+  let settle_time = ValveConfig::default().settle_time;
+  let mut detector = MismatchDetector::new(&Config::default());
+  let mut state = VehicleState::new();
+  let mut reports = Vec::new();
+  let start = Instant::now();
+  let repeated = start + settle_time / 2;
+
+  set_valve(&mut state, ValveState::Open, ValveState::Closed);
+  detector.check(&state, start, &mut reports);
+
+  // commanded open again, which doesn't change the commanded state
+  detector.commanded("FU_VENT", repeated, &mut reports);
+  detector.check(&state, start + settle_time, &mut reports);
+  assert!(reports.is_empty());
+
+  detector.check(&state, repeated + settle_time, &mut reports);
+  assert_eq!(reports.len(), 1);
+
+  // and once flagged, commanding it again clears the mismatch
+  reports.clear();
+  detector.commanded("FU_VENT", repeated + settle_time * 2, &mut reports);
+  assert_eq!(reports, vec![StatusMessage::ValveMismatchCleared { valve: "FU_VENT".to_string() }]);
+
+  reports.clear();
+  detector.check(&state, repeated + settle_time * 3 - Duration::from_millis(1), &mut reports);
+  assert!(reports.is_empty());
+  detector.check(&state, repeated + settle_time * 3, &mut reports);
+  assert_eq!(reports.len(), 1);
+}
+
+/// Estimators where `IPV` has the given config.
+fn ipv_estimators(valve: ValveConfig) -> ValveEstimators {
+  let mut config = Config::default();
+  config.valves.insert("IPV".to_string(), valve);
+  ValveEstimators::new(&config)
+}
+
+/// A normally closed valve that's powered from 0.5 A.
+fn estimate(estimators: &mut ValveEstimators, voltage: f64, current: f64, now: Instant) -> ValveState {
+  estimators.estimate("IPV", voltage, current, Some(0.5), None, now)
+}
+
+#[test]
+fn estimates_switch_exactly_at_the_thresholds() {
+  let mut estimators = ipv_estimators(ValveConfig::default());
+  let now = Instant::now();
+
+  // unpowered below 0.5 A, closed below 4 V
+  assert_eq!(estimate(&mut estimators, 3.99, 0.49, now), ValveState::Closed);
+  assert_eq!(estimate(&mut estimators, 4.0, 0.49, now), ValveState::Disconnected);
+  assert_eq!(estimate(&mut estimators, 3.99, 0.49, now), ValveState::Closed);
+
+  // powered from 0.5 A, open from 20 V
+  assert_eq!(estimate(&mut estimators, 20.0, 0.5, now), ValveState::Open);
+  assert_eq!(estimate(&mut estimators, 19.99, 0.5, now), ValveState::Fault);
+  assert_eq!(estimate(&mut estimators, 20.0, 0.5, now), ValveState::Open);
+}
+
+#[test]
+fn hysteresis_favors_the_previous_estimate() {
+  let valve = ValveConfig { current_hysteresis: 0.1, voltage_hysteresis: 1.0, ..ValveConfig::default() };
+  let mut estimators = ipv_estimators(valve);
+  let now = Instant::now();
+
+  assert_eq!(estimate(&mut estimators, 24.0, 0.5, now), ValveState::Open);
+  assert_eq!(estimate(&mut estimators, 24.0, 0.41, now), ValveState::Open);
+  assert_eq!(estimate(&mut estimators, 19.1, 0.41, now), ValveState::Open);
+  assert_eq!(estimate(&mut estimators, 18.9, 0.41, now), ValveState::Fault);
+  assert_eq!(estimate(&mut estimators, 19.9, 0.41, now), ValveState::Fault);
+  assert_eq!(estimate(&mut estimators, 24.0, 0.41, now), ValveState::Open);
+
+  assert_eq!(estimate(&mut estimators, 0.0, 0.39, now), ValveState::Closed);
+  assert_eq!(estimate(&mut estimators, 0.0, 0.49, now), ValveState::Closed);
+  assert_eq!(estimate(&mut estimators, 4.5, 0.0, now), ValveState::Disconnected);
+  assert_eq!(estimate(&mut estimators, 3.1, 0.0, now), ValveState::Disconnected);
+  assert_eq!(estimate(&mut estimators, 2.9, 0.0, now), ValveState::Closed);
+}
+
+#[test]
+fn valves_start_undetermined_until_an_estimate_holds() {
+  let debounce = Duration::from_millis(100);
+  let mut estimators = ipv_estimators(ValveConfig { debounce, ..ValveConfig::default() });
+  let start = Instant::now();
+
+  assert_eq!(estimate(&mut estimators, 0.0, 0.0, start), ValveState::Undetermined);
+  assert_eq!(estimate(&mut estimators, 0.0, 0.0, start + debounce - Duration::from_millis(1)), ValveState::Undetermined);
+  assert_eq!(estimate(&mut estimators, 0.0, 0.0, start + debounce), ValveState::Closed);
+
+  // without a debounce, the first estimate is reported straight away
+  let mut estimators = ipv_estimators(ValveConfig::default());
+  assert_eq!(estimate(&mut estimators, 0.0, 0.0, start), ValveState::Closed);
+
+  // without any current threshold, the valve can't be estimated
+  assert_eq!(estimators.estimate("OTHER", 24.0, 1.0, None, None, start), ValveState::Fault);
+}
+
+#[test]
+fn debouncing_restarts_whenever_the_estimate_changes() {
+  let debounce = Duration::from_millis(100);
+  let mut estimators = ipv_estimators(ValveConfig { debounce, ..ValveConfig::default() });
+  let start = Instant::now();
+  let at = |ms| start + Duration::from_millis(ms);
+
+  estimate(&mut estimators, 0.0, 0.0, start);
+  assert_eq!(estimate(&mut estimators, 0.0, 0.0, at(100)), ValveState::Closed);
+
+  // open, then disconnected, then open again, each restarting the window
+  assert_eq!(estimate(&mut estimators, 24.0, 1.0, at(200)), ValveState::Closed);
+  assert_eq!(estimate(&mut estimators, 10.0, 0.0, at(250)), ValveState::Closed);
+  assert_eq!(estimate(&mut estimators, 24.0, 1.0, at(320)), ValveState::Closed);
+  assert_eq!(estimate(&mut estimators, 24.0, 1.0, at(419)), ValveState::Closed);
+  assert_eq!(estimate(&mut estimators, 24.0, 1.0, at(420)), ValveState::Open);
+
+  // briefly returning to the reported estimate also restarts it
+  assert_eq!(estimate(&mut estimators, 0.0, 0.0, at(500)), ValveState::Open);
+  assert_eq!(estimate(&mut estimators, 24.0, 1.0, at(550)), ValveState::Open);
+  assert_eq!(estimate(&mut estimators, 0.0, 0.0, at(560)), ValveState::Open);
+  assert_eq!(estimate(&mut estimators, 0.0, 0.0, at(600)), ValveState::Open);
+  assert_eq!(estimate(&mut estimators, 0.0, 0.0, at(660)), ValveState::Closed);
+}
+
+#[test]
+fn normally_open_valves_are_inverted() {
+  let mut estimators = ipv_estimators(ValveConfig::default());
+  let now = Instant::now();
+
+  assert_eq!(estimators.estimate("IPV", 0.0, 0.0, Some(0.5), Some(false), now), ValveState::Open);
+  assert_eq!(estimators.estimate("IPV", 24.0, 1.0, Some(0.5), Some(false), now), ValveState::Closed);
+  assert_eq!(estimators.estimate("IPV", 10.0, 1.0, Some(0.5), Some(false), now), ValveState::Fault);
+}
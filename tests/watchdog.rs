@@ -0,0 +1,36 @@
+use std::time::{Duration, Instant};
+use flight_computer::{config::{Config, SafingAction, ServoLossStage}, status::StatusMessage, watchdog::ServoWatchdog};
+
+#[test]
+fn stages_are_taken_once_in_order_until_servo_reconnects() {
+  let config = Config {
+    servo_to_fc_time_to_live: Duration::from_secs(10),
+    servo_loss: vec![
+      ServoLossStage { after: Duration::from_secs(5), action: SafingAction::Abort },
+      ServoLossStage { after: Duration::from_secs(1), action: SafingAction::Warn },
+    ],
+    ..Config::default()
+  };
+
+  let mut watchdog = ServoWatchdog::new(&config);
+  let mut reports = Vec::new();
+  let last_received = Instant::now();
+
+  assert!(watchdog.check(Duration::from_secs(1), &mut reports).is_empty());
+  assert_eq!(watchdog.deadline(last_received), Some(last_received + Duration::from_secs(1)));
+
+  assert_eq!(watchdog.check(Duration::from_secs(6), &mut reports), vec![SafingAction::Warn, SafingAction::Abort]);
+  assert!(watchdog.check(Duration::from_secs(7), &mut reports).is_empty());
+  assert!(!watchdog.is_lost());
+
+  assert_eq!(watchdog.check(Duration::from_secs(11), &mut reports), vec![SafingAction::SafeValves]);
+  assert!(watchdog.is_lost());
+  assert_eq!(watchdog.deadline(last_received), None);
+  assert_eq!(reports.len(), 3);
+  assert_eq!(reports[0], StatusMessage::ServoLost { silent_ms: 6000, action: SafingAction::Warn });
+
+  reports.clear();
+  watchdog.reset(&mut reports);
+  assert_eq!(reports, vec![StatusMessage::ServoRestored]);
+  assert!(!watchdog.is_lost());
+}